@@ -0,0 +1,137 @@
+use crate::filter::Filters;
+use crate::recording::RecordingTarget;
+use crate::screen::Message;
+use std::fmt;
+use std::fmt::{Debug, Formatter};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+
+/// Controls a running emulator, for example to take screenshots.
+///
+/// Get one from [`Settings::control`](crate::Settings::control) before starting the emulator. It can be
+/// cloned and sent to other threads, or kept in your cpu to use it from [`Cpu::tick`](crate::Cpu::tick).
+/// Commands are handled by the emulator in between cpu cycles.
+/// When the emulator isn't running (anymore), commands are ignored.
+#[derive(Clone, Debug)]
+pub struct Control {
+    tx: Sender<Message>,
+}
+
+impl Control {
+    /// Saves a screenshot of the last completed frame as a PNG, named after the current time,
+    /// in the directory set in [`ScreenshotSettings`](crate::ScreenshotSettings).
+    pub fn screenshot(&self) {
+        self.send(Message::Screenshot(None));
+    }
+
+    /// Saves a screenshot of the last completed frame as a PNG at `path`.
+    pub fn screenshot_to(&self, path: impl Into<PathBuf>) {
+        self.send(Message::Screenshot(Some(path.into())));
+    }
+
+    /// Starts recording every completed frame to a video file named after the current time,
+    /// in the directory and format set in [`RecordingSettings`](crate::RecordingSettings).
+    /// When a recording is already running, that one is stopped first.
+    pub fn start_recording(&self) {
+        self.send(Message::StartRecording(RecordingTarget::Timestamped));
+    }
+
+    /// Like [`Control::start_recording`], but records to the file at `path`.
+    pub fn start_recording_to(&self, path: impl Into<PathBuf>) {
+        self.send(Message::StartRecording(RecordingTarget::File(path.into())));
+    }
+
+    /// Like [`Control::start_recording`], but writes the video stream to `writer`.
+    /// This can be used to pipe the video straight into an encoder, like the stdin of a child process.
+    pub fn start_recording_to_writer(&self, writer: impl Write + Send + 'static) {
+        self.send(Message::StartRecording(RecordingTarget::Writer(Box::new(
+            writer,
+        ))));
+    }
+
+    /// Stops the recording that's running, if any.
+    pub fn stop_recording(&self) {
+        self.send(Message::StopRecording);
+    }
+
+    /// Starts capturing every completed frame into an animated GIF named after the current time,
+    /// in the directory set in [`GifSettings`](crate::GifSettings), until [`Control::stop_gif`] is called.
+    /// When a GIF is already being captured, that one is stopped first.
+    pub fn start_gif(&self) {
+        self.send(Message::StartGif(None, None));
+    }
+
+    /// Like [`Control::start_gif`], but saves the GIF at `path`.
+    pub fn start_gif_to(&self, path: impl Into<PathBuf>) {
+        self.send(Message::StartGif(Some(path.into()), None));
+    }
+
+    /// Captures the next `frames` frames into an animated GIF at `path`. Frames skipped
+    /// because of [`GifSettings::frame_skip`](crate::GifSettings::frame_skip) don't count.
+    pub fn capture_gif_to(&self, path: impl Into<PathBuf>, frames: u32) {
+        self.send(Message::StartGif(Some(path.into()), Some(frames)));
+    }
+
+    /// Stops capturing the GIF, if any, and saves it.
+    pub fn stop_gif(&self) {
+        self.send(Message::StopGif);
+    }
+
+    /// Changes the [`Filters`] applied to the picture while running. This changes what's shown
+    /// in the window and what's saved in screenshots. A video recording that's already running
+    /// keeps the filters it was started with, since its size can't change.
+    pub fn set_filters(&self, filters: Filters) {
+        self.send(Message::SetFilters(filters));
+    }
+
+    /// Shows `text` on the on-screen display for a while (see [`OsdSettings`](crate::OsdSettings)).
+    /// The built-in font has uppercase letters, digits and common punctuation.
+    pub fn show_message(&self, text: impl Into<String>) {
+        self.send(Message::ShowText(text.into()));
+    }
+
+    pub(crate) fn send(&self, message: Message) {
+        // when the emulator stopped there's nobody to tell, which is fine
+        let _ = self.tx.send(message);
+    }
+}
+
+/// The channel the emulator receives commands on. Every [`Control`] sends into this channel,
+/// the receiver is taken by the emulator when it starts running.
+#[derive(Clone)]
+pub(crate) struct ControlChannel {
+    tx: Sender<Message>,
+    rx: Arc<Mutex<Option<Receiver<Message>>>>,
+}
+
+impl Default for ControlChannel {
+    fn default() -> Self {
+        let (tx, rx) = channel();
+
+        Self {
+            tx,
+            rx: Arc::new(Mutex::new(Some(rx))),
+        }
+    }
+}
+
+impl Debug for ControlChannel {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ControlChannel").finish_non_exhaustive()
+    }
+}
+
+impl ControlChannel {
+    pub fn control(&self) -> Control {
+        Control {
+            tx: self.tx.clone(),
+        }
+    }
+
+    /// Only the first emulator that runs with these settings can be controlled
+    pub fn take_receiver(&self) -> Option<Receiver<Message>> {
+        self.rx.lock().expect("failed to lock").take()
+    }
+}
@@ -1,4 +1,4 @@
-use crate::Ppu;
+use crate::{Ppu, PpuFetch};
 use std::error::Error;
 
 /// To use your cpu with the provided PPU library, you need to implement this trait for
@@ -15,8 +15,49 @@ pub trait Cpu {
     /// This method is called when the PPU (implemented by us) wants to read a byte from memory.
     /// The byte that is actually read, may depend on the current mapper state. Since you implement
     /// the mapper, you should make sure the correct byte is returned here.
+    ///
+    /// This is used when the cpu reads character memory through the data register (0x2007).
+    /// It shouldn't have side effects, so it's also safe to use for debugging. While rendering,
+    /// the PPU uses [`Cpu::ppu_fetch_chr_rom`] instead.
     fn ppu_read_chr_rom(&self, offset: u16) -> u8;
 
+    /// Called when the PPU reads character memory while rendering. In contrast to
+    /// [`Cpu::ppu_read_chr_rom`], the mapper may change its state here. Some mappers
+    /// (like MMC2 and MMC4) switch banks when the PPU fetches specific tiles.
+    ///
+    /// The PPU fetches every tile once, in approximately the order of the real hardware: for each
+    /// line first the patterns of the 8 sprite slots (low byte, then high byte), then for every
+    /// background tile the nametable byte, the attribute byte and the low and high pattern byte.
+    /// Like on the real hardware, that's 34 tiles per line, including the one or two right of the
+    /// screen that are never drawn. The exact timing isn't modelled. The sprites are fetched at
+    /// the start of their line instead of at the end of the line before, the first two tiles of a
+    /// line aren't fetched early at the end of the line before, and the four fetches of a tile
+    /// happen at once instead of over 8 dots.
+    /// The garbage nametable fetches the hardware does between the sprite fetches and at the end
+    /// of every line are left out. While rendering is turned off in the mask register, nothing is
+    /// fetched through this method.
+    ///
+    /// By default, this just calls [`Cpu::ppu_read_chr_rom`].
+    fn ppu_fetch_chr_rom(&mut self, offset: u16) -> u8 {
+        self.ppu_read_chr_rom(offset)
+    }
+
+    /// Called for every fetch the PPU does while rendering, before the byte is read from memory
+    /// the usual way. The [`PpuFetch`] says what is being fetched (nametable, attribute, background
+    /// or sprite pattern), for which tile column and whether 8x16 sprites are active.
+    ///
+    /// Most mappers don't need this. Mappers like MMC5, which can substitute attributes and
+    /// change character banks per tile, can return `Some(byte)` to override the byte the PPU
+    /// gets. By default, this returns `None` and the PPU reads from memory as normal.
+    ///
+    /// Pattern fetches still go through [`Cpu::ppu_fetch_chr_rom`] when they're overridden, so
+    /// a mapper that switches banks on fetches sees every one of them. What that returns is
+    /// replaced by the byte returned here.
+    fn ppu_override_fetch(&mut self, fetch: PpuFetch) -> Option<u8> {
+        let _ = fetch;
+        None
+    }
+
     /// Only needed when the specific mapper you implement has character RAM, writable memory
     /// on the cartridge. Most games don't require this. If you just don't implement this
     /// method it will default to ignoring all writes (as if there was only character ROM, not RAM)
@@ -0,0 +1,127 @@
+use crate::image::Image;
+
+/// The shape the picture is shown in. The NES doesn't output square pixels,
+/// so without correction circles look squashed.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
+pub enum AspectRatio {
+    /// Every pixel is square. This is the default.
+    #[default]
+    Square,
+    /// Pixels are 8:7, like on an NTSC TV
+    Ntsc,
+    /// Pixels are about 1.39:1, like on a PAL TV
+    Pal,
+    /// The whole picture is stretched to 4:3, like a TV screen
+    Display4x3,
+}
+
+impl AspectRatio {
+    /// The width a picture of `width` by `height` pixels gets. The height stays the same.
+    pub fn corrected_width(&self, width: u32, height: u32) -> u32 {
+        let width = match self {
+            AspectRatio::Square => return width,
+            AspectRatio::Ntsc => width as f64 * 8.0 / 7.0,
+            AspectRatio::Pal => width as f64 * 2_950_000.0 / 2_128_137.0,
+            AspectRatio::Display4x3 => height as f64 * 4.0 / 3.0,
+        };
+        width.round() as u32
+    }
+
+    /// Stretches `input` horizontally into `output`. Every output pixel is the average of
+    /// the part of the input it covers, so the picture stays sharp without uneven pixels.
+    pub(crate) fn apply_into(&self, input: &Image, output: &mut Image) {
+        let (width, height) = (input.width(), input.height());
+        let new_width = self.corrected_width(width, height);
+        output.resize(new_width, height);
+
+        // how many input pixels every output pixel covers
+        let step = width as f64 / new_width as f64;
+        // for every output pixel, the input pixels it covers and how much of them it covers
+        let columns = (0..new_width)
+            .map(|x| {
+                let start = x as f64 * step;
+                let end = start + step;
+                (start.floor() as usize..(end.ceil() as usize).min(width as usize))
+                    .map(|source| {
+                        let from = start.max(source as f64);
+                        let to = end.min(source as f64 + 1.0);
+                        (source, (to - from) / step)
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        let rows = input.rgba().chunks_exact(width as usize * 4);
+        let out_rows = output.rgba_mut().chunks_exact_mut(new_width as usize * 4);
+        for (row, out_row) in rows.zip(out_rows) {
+            for (covered, out) in columns.iter().zip(out_row.chunks_exact_mut(4)) {
+                let mut color = [0.0; 4];
+                for &(source, weight) in covered {
+                    for (channel, value) in color.iter_mut().zip(&row[source * 4..source * 4 + 4]) {
+                        *channel += *value as f64 * weight;
+                    }
+                }
+
+                for (out, value) in out.iter_mut().zip(color) {
+                    *out = value.round().clamp(0.0, 255.0) as u8;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(aspect_ratio: AspectRatio, input: &Image) -> Image {
+        let mut output = Image::default();
+        aspect_ratio.apply_into(input, &mut output);
+        output
+    }
+
+    #[test]
+    fn widths_are_corrected() {
+        assert_eq!(AspectRatio::Square.corrected_width(256, 240), 256);
+        assert_eq!(AspectRatio::Ntsc.corrected_width(256, 240), 293);
+        assert_eq!(AspectRatio::Pal.corrected_width(256, 240), 355);
+        assert_eq!(AspectRatio::Display4x3.corrected_width(256, 240), 320);
+    }
+
+    #[test]
+    fn solid_colors_stay_the_same() {
+        let rgba = [10, 20, 30, 0xff].repeat(256 * 4);
+        let input = Image::from_rgba(256, 4, rgba);
+
+        for aspect_ratio in [
+            AspectRatio::Square,
+            AspectRatio::Ntsc,
+            AspectRatio::Pal,
+            AspectRatio::Display4x3,
+        ] {
+            let output = apply(aspect_ratio, &input);
+            assert_eq!(output.width(), aspect_ratio.corrected_width(256, 4));
+            assert_eq!(output.height(), 4);
+            assert!(output
+                .rgba()
+                .chunks_exact(4)
+                .all(|pixel| pixel == [10, 20, 30, 0xff]));
+        }
+    }
+
+    #[test]
+    fn stretched_pixels_are_blended_where_they_meet() {
+        // 7 pixels become 8, so the black one spills a little into the second
+        let mut rgba = [0, 0, 0, 0xff].to_vec();
+        rgba.extend([0xff; 4].repeat(6));
+        let input = Image::from_rgba(7, 1, rgba);
+
+        assert_eq!(apply(AspectRatio::Square, &input), input);
+
+        let output = apply(AspectRatio::Ntsc, &input);
+        assert_eq!(output.width(), 8);
+        assert_eq!(output.get(0, 0), [0, 0, 0, 0xff]);
+        assert_eq!(output.get(1, 0), [219, 219, 219, 0xff]);
+        assert_eq!(output.get(2, 0), [0xff; 4]);
+    }
+}
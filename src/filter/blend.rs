@@ -0,0 +1,136 @@
+use crate::image::Image;
+
+/// Blends frames with the frames before them. Games that show sprites every other frame
+/// to get around the sprite limit flicker a lot on modern displays, which this smooths out.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub enum Blending {
+    /// Every frame is shown as it is. This is the default.
+    #[default]
+    None,
+    /// Shows the average of the last two frames
+    Average,
+    /// Like the phosphors of a CRT, bright pixels fade out slowly instead of disappearing at once.
+    /// Every frame, what was shown before is darkened by multiplying it with `persistence`
+    /// (from 0.0 to 1.0), and wherever the new frame is brighter that is shown instead.
+    Phosphor { persistence: f32 },
+}
+
+/// Blends frames with [`Blending`], keeping what it needs of the frames before
+pub(crate) struct Blender {
+    blending: Blending,
+    /// For [`Blending::Average`] the previous frame, for [`Blending::Phosphor`] what was shown last
+    previous: Option<Image>,
+}
+
+impl Blender {
+    pub(crate) fn new(blending: Blending) -> Self {
+        Self {
+            blending,
+            previous: None,
+        }
+    }
+
+    /// Blends `image` with the images before it, in place
+    pub(crate) fn apply(&mut self, image: &mut Image) {
+        let previous = match &mut self.previous {
+            Some(previous)
+                if previous.width() == image.width() && previous.height() == image.height() =>
+            {
+                previous
+            }
+            // the first frame has nothing to blend with
+            _ => {
+                self.previous = Some(image.clone());
+                return;
+            }
+        };
+
+        let pixels = image.rgba_mut().chunks_exact_mut(4);
+        let previous_pixels = previous.rgba_mut().chunks_exact_mut(4);
+        for (pixel, previous) in pixels.zip(previous_pixels) {
+            for channel in 0..3 {
+                let (current, before) = (pixel[channel], previous[channel]);
+                match self.blending {
+                    Blending::None => {}
+                    Blending::Average => {
+                        pixel[channel] = (current as u16 + before as u16).div_ceil(2) as u8;
+                        previous[channel] = current;
+                    }
+                    Blending::Phosphor { persistence } => {
+                        let faded = (before as f32 * persistence.clamp(0.0, 1.0)) as u8;
+                        pixel[channel] = current.max(faded);
+                        previous[channel] = pixel[channel];
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn solid(width: u32, height: u32, level: u8) -> Image {
+        let rgba = (0..width * height)
+            .flat_map(|_| [level, level, level, 0xff])
+            .collect();
+        Image::from_rgba(width, height, rgba)
+    }
+
+    /// Blends the images one after the other, returning what's shown for each
+    fn blend(blending: Blending, images: &[Image]) -> Vec<Image> {
+        let mut blender = Blender::new(blending);
+        images
+            .iter()
+            .map(|image| {
+                let mut image = image.clone();
+                blender.apply(&mut image);
+                image
+            })
+            .collect()
+    }
+
+    #[test]
+    fn no_blending_keeps_the_frames() {
+        let frames = [solid(4, 2, 0), solid(4, 2, 0xff), solid(4, 2, 100)];
+        assert_eq!(blend(Blending::None, &frames), frames);
+    }
+
+    #[test]
+    fn average_mixes_the_last_two_frames() {
+        let frames = [solid(4, 2, 0), solid(4, 2, 0xff), solid(4, 2, 100)];
+        assert_eq!(
+            blend(Blending::Average, &frames),
+            [solid(4, 2, 0), solid(4, 2, 128), solid(4, 2, 178)]
+        );
+    }
+
+    #[test]
+    fn phosphor_fades_out_bright_pixels() {
+        let frames = [
+            solid(4, 2, 0xff),
+            solid(4, 2, 0),
+            solid(4, 2, 0),
+            solid(4, 2, 100),
+        ];
+        assert_eq!(
+            blend(Blending::Phosphor { persistence: 0.5 }, &frames),
+            [
+                solid(4, 2, 0xff),
+                solid(4, 2, 127),
+                solid(4, 2, 63),
+                solid(4, 2, 100),
+            ]
+        );
+    }
+
+    #[test]
+    fn frames_of_another_size_start_over() {
+        let frames = [solid(4, 2, 0xff), solid(2, 2, 0), solid(2, 2, 0)];
+        assert_eq!(
+            blend(Blending::Average, &frames),
+            [solid(4, 2, 0xff), solid(2, 2, 0), solid(2, 2, 0)]
+        );
+    }
+}
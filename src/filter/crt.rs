@@ -0,0 +1,336 @@
+use crate::image::Image;
+
+/// The pattern of the phosphors on the screen of a CRT
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
+pub enum CrtMask {
+    None,
+    /// Vertical red, green and blue stripes, like a Trinitron. This is the default.
+    #[default]
+    ApertureGrille,
+    /// Stripes broken up into slots, where every other column of slots is shifted down by half a slot
+    SlotMask,
+    /// Dots of red, green and blue in triangles, where every other line is shifted by half a dot
+    ShadowMask,
+}
+
+impl CrtMask {
+    /// Whether the mask covers up `channel` (0 is red, 1 green, 2 blue) at `x`, `y`
+    fn covers(&self, x: u32, y: u32, channel: usize) -> bool {
+        match self {
+            CrtMask::None => false,
+            CrtMask::ApertureGrille => x as usize % 3 != channel,
+            CrtMask::SlotMask => {
+                let offset = if (x / 3) % 2 == 0 { 0 } else { 2 };
+                (y + offset) % 4 == 3 || x as usize % 3 != channel
+            }
+            CrtMask::ShadowMask => {
+                let offset = if (y / 2) % 2 == 0 { 0 } else { 2 };
+                (x + offset) as usize % 3 != channel
+            }
+        }
+    }
+}
+
+/// Makes the picture look like it's shown on a CRT. Works best when the picture
+/// is scaled up first (see [`Filters::upscaler`](crate::Filters::upscaler)),
+/// since the effects need a few pixels for every pixel of the frame.
+///
+/// Every effect can be turned off by setting it to 0.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct CrtFilter {
+    /// How much darker the space between lines is. From 0.0 to 1.0, 0.5 by default.
+    /// Only visible when every line of the frame is at least 2 pixels high.
+    pub scanlines: f32,
+    pub mask: CrtMask,
+    /// How much the dark parts of the mask darken the picture. From 0.0 to 1.0, 0.3 by default.
+    pub mask_strength: f32,
+    /// How much bright parts glow into their surroundings. Only the part of a color above
+    /// 60% brightness glows, so darker parts don't wash out the picture. 0.2 by default.
+    pub bloom: f32,
+    /// How much the screen bulges outwards. 0.05 by default.
+    pub curvature: f32,
+}
+
+impl Default for CrtFilter {
+    fn default() -> Self {
+        Self {
+            scanlines: 0.5,
+            mask: CrtMask::default(),
+            mask_strength: 0.3,
+            bloom: 0.2,
+            curvature: 0.05,
+        }
+    }
+}
+
+impl CrtFilter {
+    /// Applies the effects to `input` into `output`, which gets the same size.
+    /// `line_height` is how many rows of the input every line of the frame became.
+    pub(crate) fn apply_into(&self, input: &Image, line_height: u32, output: &mut Image) {
+        let (width, height) = (input.width(), input.height());
+        let line_height = line_height.max(1);
+        let glow = (self.bloom > 0.0).then(|| Glow::new(input, line_height));
+
+        // brightest in the middle of the line, darkest at the edges
+        let scanline_gain = (0..line_height)
+            .map(|row| {
+                if line_height == 1 {
+                    return 1.0;
+                }
+                let position = row as f32 + 0.5;
+                let distance = (position / line_height as f32 - 0.5).abs() * 2.0;
+                1.0 - self.scanlines * distance * distance
+            })
+            .collect::<Vec<_>>();
+
+        // the masks repeat every 6 pixels horizontally and 4 vertically
+        let mask: [[[f32; 3]; 6]; 4] = std::array::from_fn(|y| {
+            std::array::from_fn(|x| {
+                std::array::from_fn(|channel| {
+                    if self.mask.covers(x as u32, y as u32, channel) {
+                        1.0 - self.mask_strength
+                    } else {
+                        1.0
+                    }
+                })
+            })
+        });
+
+        // from -1.0 to 1.0, with 0.0 in the middle of the screen
+        let screen_x = (0..width)
+            .map(|x| (x as f32 + 0.5) / width as f32 * 2.0 - 1.0)
+            .collect::<Vec<_>>();
+
+        output.resize(width, height);
+        let pixels = input.rgba();
+        let rgba = output.rgba_mut();
+
+        for y in 0..height {
+            let screen_y = (y as f32 + 0.5) / height as f32 * 2.0 - 1.0;
+
+            for x in 0..width {
+                let out = &mut rgba[((y * width + x) * 4) as usize..][..4];
+                let Some((source_x, source_y)) = self.warp(screen_x[x as usize], screen_y) else {
+                    out.copy_from_slice(&[0, 0, 0, 0xff]);
+                    continue;
+                };
+                let source_x = ((source_x + 1.0) / 2.0 * width as f32) as u32;
+                let source_y = ((source_y + 1.0) / 2.0 * height as f32) as u32;
+
+                let index = ((source_y * width + source_x) * 4) as usize;
+                let gain = scanline_gain[(source_y % line_height) as usize];
+                // the mask is part of the glass, so it doesn't bend with the picture
+                let mask = mask[y as usize % 4][x as usize % 6];
+                let glow = glow.as_ref().map(|glow| glow.get(source_x, source_y));
+
+                for channel in 0..3 {
+                    let mut value = pixels[index + channel] as f32 / 255.0 * gain * mask[channel];
+                    if let Some(glow) = glow {
+                        value += glow[channel] * self.bloom;
+                    }
+                    out[channel] = (value.clamp(0.0, 1.0) * 255.0 + 0.5) as u8;
+                }
+                out[3] = 0xff;
+            }
+        }
+    }
+
+    /// Where on the flat picture the point `x`, `y` of the curved screen is, if anywhere.
+    /// Both go from -1.0 to 1.0.
+    fn warp(&self, x: f32, y: f32) -> Option<(f32, f32)> {
+        if self.curvature <= 0.0 {
+            return Some((x, y));
+        }
+
+        let bulge = 1.0 + self.curvature * (x * x + y * y);
+        let (x, y) = (x * bulge, y * bulge);
+
+        (x.abs() < 1.0 && y.abs() < 1.0).then_some((x, y))
+    }
+}
+
+/// How bright (from 0.0 to 1.0) a color has to be before it starts to glow
+const BLOOM_THRESHOLD: f32 = 0.6;
+
+/// A blurred copy of the bright parts of the picture, at the resolution of the frame,
+/// which is added to make them glow
+struct Glow {
+    width: u32,
+    height: u32,
+    /// How many pixels of the picture each pixel of the glow covers in both directions
+    scale: u32,
+    pixels: Vec<[f32; 3]>,
+}
+
+impl Glow {
+    fn new(image: &Image, scale: u32) -> Self {
+        let width = (image.width() / scale).max(1);
+        let height = (image.height() / scale).max(1);
+        let pixels = (0..width * height)
+            .map(|index| {
+                // the top left pixel of the block is as good as any, since the picture was scaled up
+                let (x, y) = (index % width * scale, index / width * scale);
+                let [r, g, b, _] = image.get(x, y);
+                let color = [r, g, b].map(|value| value as f32 / 255.0);
+
+                // only what's above the threshold glows, as much as it's above it
+                let luma = 0.299 * color[0] + 0.587 * color[1] + 0.114 * color[2];
+                let glow = ((luma - BLOOM_THRESHOLD) / (1.0 - BLOOM_THRESHOLD)).max(0.0);
+                color.map(|value| value * glow)
+            })
+            .collect::<Vec<_>>();
+
+        Self {
+            width,
+            height,
+            scale,
+            pixels: blur(&pixels, width, height, 2),
+        }
+    }
+
+    fn get(&self, x: u32, y: u32) -> [f32; 3] {
+        let x = (x / self.scale).min(self.width - 1);
+        let y = (y / self.scale).min(self.height - 1);
+        self.pixels[(y * self.width + x) as usize]
+    }
+}
+
+/// Blurs the picture with a box of `radius` pixels in every direction
+fn blur(pixels: &[[f32; 3]], width: u32, height: u32, radius: u32) -> Vec<[f32; 3]> {
+    let horizontal = blur_lines(pixels, width as usize, height as usize, 1, radius as usize);
+    blur_lines(
+        &horizontal,
+        height as usize,
+        width as usize,
+        width as usize,
+        radius as usize,
+    )
+}
+
+/// Blurs `count` lines of `length` pixels, where the pixels of a line are `step` apart
+fn blur_lines(
+    pixels: &[[f32; 3]],
+    length: usize,
+    count: usize,
+    step: usize,
+    radius: usize,
+) -> Vec<[f32; 3]> {
+    let mut blurred = vec![[0.0; 3]; pixels.len()];
+    // lines are next to each other when the pixels in them aren't
+    let line_step = if step == 1 { length } else { 1 };
+    let mut sums = vec![[0.0f32; 3]; length + 1];
+
+    for line in 0..count {
+        let start = line * line_step;
+        for i in 0..length {
+            let pixel = pixels[start + i * step];
+            sums[i + 1] = std::array::from_fn(|channel| sums[i][channel] + pixel[channel]);
+        }
+
+        for i in 0..length {
+            let from = i.saturating_sub(radius);
+            let to = (i + radius + 1).min(length);
+            blurred[start + i * step] = std::array::from_fn(|channel| {
+                (sums[to][channel] - sums[from][channel]) / (to - from) as f32
+            });
+        }
+    }
+
+    blurred
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// All effects turned off
+    const NONE: CrtFilter = CrtFilter {
+        scanlines: 0.0,
+        mask: CrtMask::None,
+        mask_strength: 0.0,
+        bloom: 0.0,
+        curvature: 0.0,
+    };
+
+    /// An image where the pixels left of `split` have the color `left`, and the others `right`
+    fn split(width: u32, height: u32, split: u32, left: [u8; 4], right: [u8; 4]) -> Image {
+        let rgba = (0..width * height)
+            .flat_map(|i| if i % width < split { left } else { right })
+            .collect();
+        Image::from_rgba(width, height, rgba)
+    }
+
+    fn solid(color: [u8; 4]) -> Image {
+        split(12, 8, 0, color, color)
+    }
+
+    fn apply(filter: CrtFilter, input: &Image, line_height: u32) -> Image {
+        let mut output = Image::default();
+        filter.apply_into(input, line_height, &mut output);
+        output
+    }
+
+    #[test]
+    fn keeps_the_size() {
+        let output = apply(CrtFilter::default(), &solid([100, 150, 200, 0xff]), 2);
+        assert_eq!((output.width(), output.height()), (12, 8));
+    }
+
+    #[test]
+    fn solid_colors_stay_the_same_without_effects() {
+        let input = solid([100, 150, 200, 0xff]);
+        assert_eq!(apply(NONE, &input, 2), input);
+
+        // mid grey is too dark to glow
+        let grey = solid([128, 128, 128, 0xff]);
+        let bloom = CrtFilter { bloom: 1.0, ..NONE };
+        assert_eq!(apply(bloom, &grey, 1), grey);
+    }
+
+    #[test]
+    fn every_effect_changes_the_picture() {
+        let white = solid([0xff; 4]);
+        let dark = solid([100, 100, 100, 0xff]);
+
+        let scanlines = CrtFilter {
+            scanlines: 0.5,
+            ..NONE
+        };
+        let output = apply(scanlines, &white, 2);
+        assert_eq!(output.get(0, 0), [223, 223, 223, 0xff]);
+        assert_eq!(apply(scanlines, &white, 1), white);
+
+        let mask = CrtFilter {
+            mask: CrtMask::ApertureGrille,
+            mask_strength: 0.5,
+            ..NONE
+        };
+        let output = apply(mask, &white, 1);
+        assert_eq!(output.get(0, 0), [0xff, 128, 128, 0xff]);
+        assert_eq!(output.get(1, 0), [128, 0xff, 128, 0xff]);
+
+        let curvature = CrtFilter {
+            curvature: 0.5,
+            ..NONE
+        };
+        let output = apply(curvature, &dark, 1);
+        assert_eq!(output.get(0, 0), [0, 0, 0, 0xff]);
+        assert_eq!(output.get(6, 4), [100, 100, 100, 0xff]);
+
+        let bloom = CrtFilter { bloom: 0.5, ..NONE };
+        let edge = split(12, 8, 6, [0xff; 4], [0, 0, 0, 0xff]);
+        assert!(apply(bloom, &edge, 1).get(6, 4)[0] > 0);
+    }
+
+    #[test]
+    fn only_bright_parts_glow() {
+        let bloom = CrtFilter { bloom: 1.0, ..NONE };
+
+        let grey_next_to_black = split(12, 8, 6, [128, 128, 128, 0xff], [0, 0, 0, 0xff]);
+        assert_eq!(apply(bloom, &grey_next_to_black, 1), grey_next_to_black);
+
+        let white_next_to_black = split(12, 8, 6, [0xff; 4], [0, 0, 0, 0xff]);
+        let output = apply(bloom, &white_next_to_black, 1);
+        assert!(output.get(6, 4)[0] > output.get(11, 4)[0]);
+    }
+}
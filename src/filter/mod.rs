@@ -0,0 +1,170 @@
+use crate::image::Image;
+use crate::ppu::colors::Palette;
+use crate::ppu::frame::Frame;
+use crate::settings::{Overscan, Settings};
+
+mod aspect;
+mod blend;
+mod crt;
+mod ntsc;
+mod scale;
+
+pub use aspect::AspectRatio;
+use blend::Blender;
+pub use blend::Blending;
+pub use crt::{CrtFilter, CrtMask};
+use ntsc::NtscDecoder;
+pub use ntsc::NtscFilter;
+pub use scale::Upscaler;
+
+/// Filters that change how the picture looks, applied to every frame before
+/// it's shown in the window, saved as a screenshot or recorded.
+///
+/// They're done in software, so the output doesn't depend on the GPU, and works without a window too.
+/// The [`Default`] doesn't change the picture.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Filters {
+    /// Decodes the frame like a TV decodes the composite video signal, instead of using the palette.
+    /// Applied before the `upscaler`.
+    pub ntsc: Option<NtscFilter>,
+    /// Blends every frame with the ones before it. Applied before the `upscaler`.
+    pub blending: Blending,
+    pub upscaler: Upscaler,
+    /// Makes the picture look like it's shown on a CRT. Applied after the `upscaler`.
+    pub crt: Option<CrtFilter>,
+    /// Stretches the picture to the shape it had on a TV. Applied last,
+    /// and also used to size the window.
+    pub aspect_ratio: AspectRatio,
+}
+
+impl Filters {
+    /// The size of the picture after the filters are applied to a frame cropped by `overscan`
+    pub fn output_size(&self, overscan: Overscan) -> (u32, u32) {
+        let mut factor = self.upscaler.factor();
+        if self.ntsc.is_some() {
+            factor *= ntsc::SCALE;
+        }
+        let (width, height) = (overscan.width() * factor, overscan.height() * factor);
+        (self.aspect_ratio.corrected_width(width, height), height)
+    }
+
+    /// Applies the filters to a single frame, with the colors from `palette`
+    /// and the edges given by `overscan` cut off. Filters that change between frames,
+    /// like the phase of the [`NtscFilter`], act as if this is the first frame.
+    pub fn apply(&self, frame: &Frame, palette: &Palette, overscan: Overscan) -> Image {
+        Pipeline::new(self.clone(), overscan, None)
+            .render(frame, palette)
+            .clone()
+    }
+}
+
+/// Turns frames into images, by applying the [`Filters`]. Keeps the buffers between frames.
+pub(crate) struct Pipeline {
+    filters: Filters,
+    overscan: Overscan,
+    /// The palette to use instead of the one the frames are drawn with
+    palette: Option<Palette>,
+    ntsc: Option<NtscDecoder>,
+    blender: Blender,
+    /// How many frames were rendered
+    frames: u64,
+    colored: Image,
+    scaled: Image,
+    crt: Image,
+    output: Image,
+}
+
+impl Pipeline {
+    pub(crate) fn new(filters: Filters, overscan: Overscan, palette: Option<Palette>) -> Self {
+        Self {
+            ntsc: filters.ntsc.as_ref().map(NtscDecoder::new),
+            blender: Blender::new(filters.blending),
+            filters,
+            overscan,
+            palette,
+            frames: 0,
+            colored: Image::new(overscan.width(), overscan.height()),
+            scaled: Image::default(),
+            crt: Image::default(),
+            output: Image::default(),
+        }
+    }
+
+    /// A pipeline for screenshots and recordings, which can leave out the filters and cropping
+    /// of the `settings`, and use their own palette.
+    pub(crate) fn for_export(
+        settings: &Settings,
+        filtered: bool,
+        crop_overscan: bool,
+        palette: Option<Palette>,
+    ) -> Self {
+        let filters = if filtered {
+            settings.filters.clone()
+        } else {
+            Filters::default()
+        };
+        let overscan = if crop_overscan {
+            settings.overscan
+        } else {
+            Overscan::NONE
+        };
+
+        Self::new(filters, overscan, palette)
+    }
+
+    /// The size of the images this pipeline renders
+    pub(crate) fn output_size(&self) -> (u32, u32) {
+        self.filters.output_size(self.overscan)
+    }
+
+    pub(crate) fn render(&mut self, frame: &Frame, palette: &Palette) -> &Image {
+        match &mut self.ntsc {
+            Some(ntsc) => ntsc.decode(frame, self.frames, self.overscan, &mut self.colored),
+            None => {
+                let palette = self.palette.as_ref().unwrap_or(palette);
+                frame.write_rgba(palette, self.overscan, self.colored.rgba_mut());
+            }
+        }
+        self.frames += 1;
+
+        if self.filters.blending != Blending::None {
+            self.blender.apply(&mut self.colored);
+        }
+
+        let mut image = &self.colored;
+
+        if self.filters.upscaler != Upscaler::None {
+            self.filters.upscaler.apply_into(image, &mut self.scaled);
+            image = &self.scaled;
+        }
+
+        if let Some(crt) = &self.filters.crt {
+            let line_height = image.height() / self.overscan.height();
+            crt.apply_into(image, line_height, &mut self.crt);
+            image = &self.crt;
+        }
+
+        if self.filters.aspect_ratio != AspectRatio::Square {
+            self.filters
+                .aspect_ratio
+                .apply_into(image, &mut self.output);
+        }
+
+        self.last_image().expect("a frame was just rendered")
+    }
+
+    /// The last image that was rendered, if any
+    pub(crate) fn last_image(&self) -> Option<&Image> {
+        if self.frames == 0 {
+            None
+        } else if self.filters.aspect_ratio != AspectRatio::Square {
+            Some(&self.output)
+        } else if self.filters.crt.is_some() {
+            Some(&self.crt)
+        } else if self.filters.upscaler != Upscaler::None {
+            Some(&self.scaled)
+        } else {
+            Some(&self.colored)
+        }
+    }
+}
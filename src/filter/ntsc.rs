@@ -0,0 +1,212 @@
+use crate::image::Image;
+use crate::ppu::frame::Frame;
+use crate::ppu::ntsc::{signal, Decoder};
+use crate::settings::Overscan;
+use crate::{PaletteGenerator, WIDTH};
+
+/// How many samples of the signal the PPU outputs for every pixel. The color subcarrier
+/// has a period of 12 samples, so each pixel is two thirds of a cycle.
+const SAMPLES_PER_PIXEL: usize = 8;
+/// How many output pixels every pixel of the frame becomes, in both directions
+pub(crate) const SCALE: u32 = 2;
+/// How many samples are averaged to get the luma (one cycle of the subcarrier)
+/// and the chroma (two cycles, since TVs have less bandwidth for color).
+const LUMA_WINDOW: usize = 12;
+const CHROMA_WINDOW: usize = 24;
+
+/// Simulates the composite video signal of the NES, and how a TV decodes it.
+///
+/// Instead of looking up the color of every pixel in a palette, the signal of the whole line is
+/// decoded at once. That gives the artifacts games were made for: dithering blends into
+/// transparency and colors, and edges get colored fringes. The phase of the color subcarrier
+/// changes every line and cycles through three phases between frames, just like it does on the PPU.
+///
+/// Because the signal is decoded directly, the palette isn't used. The output is twice as
+/// wide and high as the frame.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct NtscFilter {
+    /// The knobs of the simulated TV
+    pub tv: PaletteGenerator,
+}
+
+/// Decodes frames with an [`NtscFilter`]
+pub(crate) struct NtscDecoder {
+    decoder: Decoder,
+    /// The signal of every pixel value at each of the 12 phases of the subcarrier
+    signals: Box<[[f32; 12]]>,
+    /// The subcarrier at each of the 12 phases, to decode the color with
+    carrier: [(f32, f32); 12],
+    /// Running sums of the luma and chroma over the line, so any window can be summed at once
+    sums: Vec<(f32, f32, f32)>,
+}
+
+impl NtscDecoder {
+    pub(crate) fn new(filter: &NtscFilter) -> Self {
+        let decoder = filter.tv.decoder();
+
+        Self {
+            decoder,
+            signals: (0..512)
+                .map(|pixel| std::array::from_fn(|phase| signal(pixel, phase) as f32))
+                .collect(),
+            carrier: std::array::from_fn(|phase| {
+                let (cos, sin) = decoder.carrier(phase as f64);
+                (cos as f32, sin as f32)
+            }),
+            sums: Vec::with_capacity(WIDTH as usize * SAMPLES_PER_PIXEL + 1),
+        }
+    }
+
+    /// Decodes `frame` into `output`, which gets [`SCALE`] times the size of the cropped frame.
+    /// `frame_number` sets the phase of the subcarrier.
+    pub(crate) fn decode(
+        &mut self,
+        frame: &Frame,
+        frame_number: u64,
+        overscan: Overscan,
+        output: &mut Image,
+    ) {
+        let overscan = overscan.clamped();
+        let width = overscan.width() * SCALE;
+        output.resize(width, overscan.height() * SCALE);
+        let rows = frame
+            .pixels()
+            .chunks_exact(WIDTH as usize)
+            .enumerate()
+            .skip(overscan.top as usize)
+            .take(overscan.height() as usize);
+        let out_rows = output.rgba_mut().chunks_exact_mut(width as usize * 4 * 2);
+
+        for ((y, row), out) in rows.zip(out_rows) {
+            // every line starts 4 samples later in the cycle, every frame too
+            let start_phase = (frame_number % 3) as usize * 4 + y * 4;
+
+            self.sums.clear();
+            self.sums.push((0.0, 0.0, 0.0));
+            let mut total = (0.0, 0.0, 0.0);
+            for (sample, &pixel) in row
+                .iter()
+                .flat_map(|pixel| [pixel; SAMPLES_PER_PIXEL])
+                .enumerate()
+            {
+                let phase = (start_phase + sample) % 12;
+                let signal = self.signals[pixel as usize & 0x1ff][phase];
+                let (cos, sin) = self.carrier[phase];
+                total = (
+                    total.0 + signal,
+                    total.1 + signal * cos,
+                    total.2 + signal * sin,
+                );
+                self.sums.push(total);
+            }
+
+            let (line, doubled) = out.split_at_mut(width as usize * 4);
+            let first = overscan.left as usize * SCALE as usize;
+            for (column, rgba) in (first..).zip(line.chunks_exact_mut(4)) {
+                // decode over whole cycles of the subcarrier, centered on the output pixel
+                let center = column * SAMPLES_PER_PIXEL / SCALE as usize + 2;
+                let window = |size: usize| {
+                    // at the edges of the line, the window is moved to stay inside it
+                    let start = center
+                        .saturating_sub(size / 2)
+                        .min(self.sums.len() - 1 - size);
+                    (self.sums[start], self.sums[start + size])
+                };
+
+                let ((y1, _, _), (y2, _, _)) = window(LUMA_WINDOW);
+                let ((_, i1, q1), (_, i2, q2)) = window(CHROMA_WINDOW);
+                let (r, g, b) = self.decoder.to_rgb(
+                    ((y2 - y1) / LUMA_WINDOW as f32) as f64,
+                    ((i2 - i1) / CHROMA_WINDOW as f32) as f64,
+                    ((q2 - q1) / CHROMA_WINDOW as f32) as f64,
+                );
+
+                rgba.copy_from_slice(&[r, g, b, 0xff]);
+            }
+
+            doubled.copy_from_slice(line);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::HEIGHT;
+
+    /// A frame where every pixel is `pixel(x)`
+    fn frame(pixel: impl Fn(usize) -> u16) -> Frame {
+        let mut frame = Frame::default();
+        for y in 0..HEIGHT as usize {
+            for x in 0..WIDTH as usize {
+                frame.set(x, y, pixel(x));
+            }
+        }
+        frame
+    }
+
+    fn decode(filter: NtscFilter, frame: &Frame, number: u64, overscan: Overscan) -> Image {
+        let mut output = Image::default();
+        NtscDecoder::new(&filter).decode(frame, number, overscan, &mut output);
+        output
+    }
+
+    #[test]
+    fn output_is_twice_the_cropped_size() {
+        let frame = Frame::default();
+        let output = decode(NtscFilter::default(), &frame, 0, Overscan::NONE);
+        assert_eq!((output.width(), output.height()), (512, 480));
+        let output = decode(NtscFilter::default(), &frame, 0, Overscan::NTSC);
+        assert_eq!((output.width(), output.height()), (512, 448));
+    }
+
+    #[test]
+    fn solid_colors_match_the_generated_palette() {
+        let palette = PaletteGenerator::default().generate();
+
+        // black, red, white, green and emphasized red
+        for pixel in [0x0f, 0x16, 0x20, 0x2a, 0x16 | 0b001 << 6] {
+            let output = decode(NtscFilter::default(), &frame(|_| pixel), 0, Overscan::NONE);
+            let (r, g, b) = palette.color(pixel);
+
+            for (x, y) in [(0, 0), (255, 100), (511, 479)] {
+                let decoded = output.get(x, y);
+                for (decoded, expected) in decoded.into_iter().zip([r, g, b, 0xff]) {
+                    assert!(
+                        decoded.abs_diff(expected) <= 1,
+                        "{pixel:#x} at {x}, {y} is {decoded:?}, should be {expected:?}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn settings_change_the_picture() {
+        let red = frame(|_| 0x16);
+        let default = decode(NtscFilter::default(), &red, 0, Overscan::NONE);
+
+        let grey = NtscFilter {
+            tv: PaletteGenerator {
+                saturation: 0.0,
+                ..Default::default()
+            },
+        };
+        let [r, g, b, _] = decode(grey, &red, 0, Overscan::NONE).get(100, 100);
+        assert!(r == g && g == b);
+
+        let rotated = NtscFilter {
+            tv: PaletteGenerator {
+                hue: 30.0,
+                ..Default::default()
+            },
+        };
+        assert_ne!(decode(rotated, &red, 0, Overscan::NONE), default);
+
+        // the colored fringes of thin lines move between frames
+        let stripes = frame(|x| if x % 2 == 0 { 0x20 } else { 0x0f });
+        let first = decode(NtscFilter::default(), &stripes, 0, Overscan::NONE);
+        let second = decode(NtscFilter::default(), &stripes, 1, Overscan::NONE);
+        assert_ne!(first, second);
+    }
+}
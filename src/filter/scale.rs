@@ -0,0 +1,731 @@
+use crate::image::Image;
+
+/// Scales up the picture in software, so it looks the same on every GPU.
+///
+/// Apart from [`Upscaler::Nearest`], these are made for pixel art: they smooth
+/// diagonal edges while keeping the picture sharp.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
+pub enum Upscaler {
+    /// Doesn't scale the picture. This is the default.
+    #[default]
+    None,
+    /// Repeats every pixel the given number of times in both directions
+    Nearest(u32),
+    /// The Scale2x (also known as AdvMAME2x) algorithm
+    Scale2x,
+    /// The Scale3x (also known as AdvMAME3x) algorithm
+    Scale3x,
+    /// The hq2x algorithm, which blends pixels along edges between colors that look different
+    Hq2x,
+    /// The hq3x algorithm, which blends pixels along edges between colors that look different
+    Hq3x,
+    /// The 2xBR algorithm (xBR level 1 at 2x), which smooths edges of all angles
+    Xbr2x,
+}
+
+impl Upscaler {
+    /// How many times bigger the picture gets in both directions
+    pub fn factor(&self) -> u32 {
+        match self {
+            Upscaler::None => 1,
+            Upscaler::Nearest(factor) => (*factor).max(1),
+            Upscaler::Scale2x | Upscaler::Hq2x | Upscaler::Xbr2x => 2,
+            Upscaler::Scale3x | Upscaler::Hq3x => 3,
+        }
+    }
+
+    /// Scales up `image`
+    pub fn apply(&self, image: &Image) -> Image {
+        let mut output = Image::default();
+        self.apply_into(image, &mut output);
+        output
+    }
+
+    /// Scales up `image` into `output`, reusing its buffer
+    pub(crate) fn apply_into(&self, image: &Image, output: &mut Image) {
+        let factor = self.factor();
+        output.resize(image.width() * factor, image.height() * factor);
+
+        let input = Pixels::new(image);
+        let output_width = output.width() as usize;
+        let rgba = output.rgba_mut();
+
+        // every input pixel becomes a block of `factor` by `factor` output pixels, row by row
+        let mut block = [0; 9];
+        for y in 0..image.height() as i32 {
+            for x in 0..image.width() as i32 {
+                let block: &[u32] = match self {
+                    Upscaler::None | Upscaler::Nearest(_) => &[input.get(x, y).color],
+                    Upscaler::Scale2x => {
+                        block[..4].copy_from_slice(&scale2x(&input, x, y));
+                        &block[..4]
+                    }
+                    Upscaler::Scale3x => {
+                        block = scale3x(&input, x, y);
+                        &block
+                    }
+                    Upscaler::Hq2x => {
+                        block[..4].copy_from_slice(&hq2x(&input, x, y));
+                        &block[..4]
+                    }
+                    Upscaler::Hq3x => {
+                        block = hq3x(&input, x, y);
+                        &block
+                    }
+                    Upscaler::Xbr2x => {
+                        block[..4].copy_from_slice(&xbr2x(&input, x, y));
+                        &block[..4]
+                    }
+                };
+
+                for i in 0..(factor * factor) as usize {
+                    // nearest neighbour has a single color for the whole block
+                    let color = block[i % block.len()];
+                    let out_x = x as usize * factor as usize + i % factor as usize;
+                    let out_y = y as usize * factor as usize + i / factor as usize;
+                    let index = (out_y * output_width + out_x) * 4;
+                    rgba[index..index + 4].copy_from_slice(&color.to_be_bytes());
+                }
+            }
+        }
+    }
+}
+
+/// A pixel as `0xRRGGBBAA`, with its YUV value, which is what hqx and xBR compare colors in
+#[derive(Copy, Clone, PartialEq, Eq)]
+struct Pixel {
+    color: u32,
+    yuv: (i32, i32, i32),
+}
+
+impl Pixel {
+    fn new(color: u32) -> Self {
+        let [r, g, b, _] = color.to_be_bytes().map(i32::from);
+        Self {
+            color,
+            yuv: (
+                (299 * r + 587 * g + 114 * b) / 1000,
+                (-169 * r - 331 * g + 500 * b) / 1000 + 128,
+                (500 * r - 419 * g - 81 * b) / 1000 + 128,
+            ),
+        }
+    }
+}
+
+/// The pixels of an image, where reads outside the image get the nearest edge pixel
+struct Pixels {
+    width: i32,
+    height: i32,
+    pixels: Vec<Pixel>,
+}
+
+impl Pixels {
+    fn new(image: &Image) -> Self {
+        Self {
+            width: image.width() as i32,
+            height: image.height() as i32,
+            pixels: image
+                .rgba()
+                .chunks_exact(4)
+                .map(|rgba| Pixel::new(u32::from_be_bytes([rgba[0], rgba[1], rgba[2], rgba[3]])))
+                .collect(),
+        }
+    }
+
+    fn get(&self, x: i32, y: i32) -> Pixel {
+        let x = x.clamp(0, self.width - 1);
+        let y = y.clamp(0, self.height - 1);
+        self.pixels[(y * self.width + x) as usize]
+    }
+}
+
+/// The 3x3 pixels around `x`, `y`, row by row
+fn neighbours(input: &Pixels, x: i32, y: i32) -> [Pixel; 9] {
+    let mut neighbours = [input.get(x, y); 9];
+    for (i, neighbour) in neighbours.iter_mut().enumerate() {
+        *neighbour = input.get(x + i as i32 % 3 - 1, y + i as i32 / 3 - 1);
+    }
+    neighbours
+}
+
+fn scale2x(input: &Pixels, x: i32, y: i32) -> [u32; 4] {
+    let [_, b, _, d, e, f, _, h, _] = neighbours(input, x, y).map(|pixel| pixel.color);
+
+    if b != h && d != f {
+        [
+            if d == b { d } else { e },
+            if b == f { f } else { e },
+            if d == h { d } else { e },
+            if h == f { f } else { e },
+        ]
+    } else {
+        [e; 4]
+    }
+}
+
+fn scale3x(input: &Pixels, x: i32, y: i32) -> [u32; 9] {
+    let [a, b, c, d, e, f, g, h, i] = neighbours(input, x, y).map(|pixel| pixel.color);
+
+    if b != h && d != f {
+        [
+            if d == b { d } else { e },
+            if (d == b && e != c) || (b == f && e != a) {
+                b
+            } else {
+                e
+            },
+            if b == f { f } else { e },
+            if (d == b && e != g) || (d == h && e != a) {
+                d
+            } else {
+                e
+            },
+            e,
+            if (b == f && e != i) || (h == f && e != c) {
+                f
+            } else {
+                e
+            },
+            if d == h { d } else { e },
+            if (d == h && e != i) || (h == f && e != g) {
+                h
+            } else {
+                e
+            },
+            if h == f { f } else { e },
+        ]
+    } else {
+        [e; 9]
+    }
+}
+
+/// Whether two colors look different, with the thresholds used by hqx
+fn different(a: Pixel, b: Pixel) -> bool {
+    let ((y1, u1, v1), (y2, u2, v2)) = (a.yuv, b.yuv);
+    (y1 - y2).abs() > 48 || (u1 - u2).abs() > 7 || (v1 - v2).abs() > 6
+}
+
+/// How different two colors look, as used by xBR
+fn distance(a: Pixel, b: Pixel) -> i32 {
+    let ((y1, u1, v1), (y2, u2, v2)) = (a.yuv, b.yuv);
+    (y1 - y2).abs() * 48 + (u1 - u2).abs() * 7 + (v1 - v2).abs() * 6
+}
+
+/// Mixes colors, each with its own weight
+fn blend(colors: &[(Pixel, u32)]) -> u32 {
+    let total: u32 = colors.iter().map(|(_, weight)| weight).sum();
+    let mut channels = [0u32; 4];
+
+    for (pixel, weight) in colors {
+        for (channel, value) in channels.iter_mut().zip(pixel.color.to_be_bytes()) {
+            *channel += value as u32 * weight;
+        }
+    }
+
+    u32::from_be_bytes(channels.map(|channel| ((channel + total / 2) / total) as u8))
+}
+
+/// How hqx colors the top left corner of an output pixel, named after the hq2x weights.
+/// hq3x uses the same choice with its own weights, see [`hq3x_corner`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum HqCorner {
+    /// Just the center pixel
+    Center,
+    /// 3:1 with the top left neighbour
+    Diagonal,
+    /// 3:1 with the left neighbour
+    Left,
+    /// 3:1 with the top neighbour
+    Top,
+    /// 2:1:1 with the left and top neighbours
+    Sides,
+    /// 2:1:1 with the top left and top neighbours
+    DiagonalTop,
+    /// 2:1:1 with the top left and left neighbours
+    DiagonalLeft,
+    /// 5:2:1 with the top and left neighbours
+    MostlyTop,
+    /// 5:2:1 with the left and top neighbours
+    MostlyLeft,
+    /// 6:1:1 with the left and top neighbours
+    Soft,
+    /// 2:3:3 with the left and top neighbours
+    Edge,
+    /// 14:1:1 with the left and top neighbours
+    Faint,
+}
+
+/// Which of the 8 neighbours in `w` (the 3x3 pixels row by row) look different from the
+/// center, one bit per neighbour, starting with the top left one in the lowest bit
+fn pattern(w: &[Pixel; 9]) -> u8 {
+    [0, 1, 2, 3, 5, 6, 7, 8]
+        .into_iter()
+        .enumerate()
+        .fold(0, |pattern, (bit, i)| {
+            pattern | (different(w[4], w[i]) as u8) << bit
+        })
+}
+
+/// Whether `pattern` matches any of the `(mask, bits)` pairs in `patterns`
+fn matches(pattern: u8, patterns: &[(u8, u8)]) -> bool {
+    patterns.iter().any(|&(mask, bits)| pattern & mask == bits)
+}
+
+/// Picks how to color the top left corner of the output pixel for the 3x3 pixels `w`.
+///
+/// These are the 256 cases of the hq2x tables, written as the masks of the neighbours that
+/// matter, like FFmpeg's hqx filter does, so the other corners can use them mirrored.
+fn hq_corner(w: &[Pixel; 9]) -> HqCorner {
+    let pattern = pattern(w);
+    let is = |patterns: &[(u8, u8)]| matches(pattern, patterns);
+    let (top, left, right, bottom) = (w[1], w[3], w[5], w[7]);
+
+    if is(&[(0xbf, 0x37), (0xdb, 0x13)]) && different(top, right) {
+        HqCorner::Left
+    } else if is(&[(0xdb, 0x49), (0xef, 0x6d)]) && different(bottom, left) {
+        HqCorner::Top
+    } else if is(&[(0x0b, 0x0b), (0xfe, 0x4a), (0xfe, 0x1a)]) && different(left, top) {
+        HqCorner::Center
+    } else if is(&[
+        (0x6f, 0x2a),
+        (0x5b, 0x0a),
+        (0xbf, 0x3a),
+        (0xdf, 0x5a),
+        (0x9f, 0x8a),
+        (0xcf, 0x8a),
+        (0xef, 0x4e),
+        (0x3f, 0x0e),
+        (0xfb, 0x5a),
+        (0xbb, 0x8a),
+        (0x7f, 0x5a),
+        (0xaf, 0x8a),
+        (0xeb, 0x8a),
+    ]) && different(left, top)
+    {
+        HqCorner::Diagonal
+    } else if is(&[(0x0b, 0x08)]) {
+        HqCorner::DiagonalTop
+    } else if is(&[(0x0b, 0x02)]) {
+        HqCorner::DiagonalLeft
+    } else if is(&[(0x2f, 0x2f)]) {
+        HqCorner::Faint
+    } else if is(&[(0xbf, 0x37), (0xdb, 0x13)]) {
+        HqCorner::MostlyTop
+    } else if is(&[(0xdb, 0x49), (0xef, 0x6d)]) {
+        HqCorner::MostlyLeft
+    } else if is(&[(0x1b, 0x03), (0x4f, 0x43), (0x8b, 0x83), (0x6b, 0x43)]) {
+        HqCorner::Left
+    } else if is(&[(0x4b, 0x09), (0x8b, 0x89), (0x1f, 0x19), (0x3b, 0x19)]) {
+        HqCorner::Top
+    } else if is(&[(0x7e, 0x2a), (0xef, 0xab), (0xbf, 0x8f), (0x7e, 0x0e)]) {
+        HqCorner::Edge
+    } else if is(&[
+        (0xfb, 0x6a),
+        (0x6f, 0x6e),
+        (0x3f, 0x3e),
+        (0xfb, 0xfa),
+        (0xdf, 0xde),
+        (0xdf, 0x1e),
+    ]) {
+        HqCorner::Diagonal
+    } else if is(&[
+        (0x0a, 0x00),
+        (0x4f, 0x4b),
+        (0x9f, 0x1b),
+        (0x2f, 0x0b),
+        (0xbe, 0x0a),
+        (0xee, 0x0a),
+        (0x7e, 0x0a),
+        (0xeb, 0x4b),
+        (0x3b, 0x1b),
+    ]) {
+        HqCorner::Sides
+    } else {
+        HqCorner::Soft
+    }
+}
+
+/// The top left corner of the output pixel with hq2x, for the 3x3 pixels `w`
+fn hq2x_corner(w: &[Pixel; 9]) -> u32 {
+    let [diagonal, top, _, left, e, ..] = *w;
+
+    match hq_corner(w) {
+        HqCorner::Center => e.color,
+        HqCorner::Diagonal => blend(&[(e, 3), (diagonal, 1)]),
+        HqCorner::Left => blend(&[(e, 3), (left, 1)]),
+        HqCorner::Top => blend(&[(e, 3), (top, 1)]),
+        HqCorner::Sides => blend(&[(e, 2), (left, 1), (top, 1)]),
+        HqCorner::DiagonalTop => blend(&[(e, 2), (diagonal, 1), (top, 1)]),
+        HqCorner::DiagonalLeft => blend(&[(e, 2), (diagonal, 1), (left, 1)]),
+        HqCorner::MostlyTop => blend(&[(e, 5), (top, 2), (left, 1)]),
+        HqCorner::MostlyLeft => blend(&[(e, 5), (left, 2), (top, 1)]),
+        HqCorner::Soft => blend(&[(e, 6), (left, 1), (top, 1)]),
+        HqCorner::Edge => blend(&[(e, 2), (left, 3), (top, 3)]),
+        HqCorner::Faint => blend(&[(e, 14), (left, 1), (top, 1)]),
+    }
+}
+
+/// The top left corner of the output pixel with hq3x, for the 3x3 pixels `w`.
+/// The corners of hq3x have fewer weights than hq2x, since they are further from the center.
+fn hq3x_corner(w: &[Pixel; 9]) -> u32 {
+    let [diagonal, top, _, left, e, ..] = *w;
+
+    match hq_corner(w) {
+        HqCorner::Center => e.color,
+        HqCorner::Diagonal | HqCorner::DiagonalTop | HqCorner::DiagonalLeft => {
+            blend(&[(e, 3), (diagonal, 1)])
+        }
+        HqCorner::Left => blend(&[(e, 3), (left, 1)]),
+        HqCorner::Top => blend(&[(e, 3), (top, 1)]),
+        HqCorner::Edge => blend(&[(e, 2), (left, 7), (top, 7)]),
+        HqCorner::Sides
+        | HqCorner::MostlyTop
+        | HqCorner::MostlyLeft
+        | HqCorner::Soft
+        | HqCorner::Faint => blend(&[(e, 2), (left, 1), (top, 1)]),
+    }
+}
+
+/// The output pixel right of the top left corner with hq3x, for the 3x3 pixels `w`
+fn hq3x_edge(w: &[Pixel; 9]) -> u32 {
+    let pattern = pattern(w);
+    let is = |patterns: &[(u8, u8)]| matches(pattern, patterns);
+    let [_, top, _, left, e, right, ..] = *w;
+
+    // the edge of a corner at either end of this side
+    let right_corner = is(&[
+        (0xfe, 0xde),
+        (0x9e, 0x16),
+        (0xda, 0x12),
+        (0x17, 0x16),
+        (0x5b, 0x12),
+        (0xbb, 0x12),
+    ]) && different(top, right);
+    let left_corner = is(&[
+        (0x0f, 0x0b),
+        (0x5e, 0x0a),
+        (0xfb, 0x7b),
+        (0x3b, 0x0b),
+        (0xbe, 0x0a),
+        (0x7a, 0x0a),
+    ]) && different(left, top);
+
+    if right_corner || left_corner {
+        e.color
+    } else if is(&[(0xbf, 0x8f), (0x7e, 0x0e), (0xbf, 0x37), (0xdb, 0x13)]) {
+        blend(&[(top, 3), (e, 1)])
+    } else if is(&[
+        (0x02, 0x00),
+        (0x7c, 0x28),
+        (0xed, 0xa9),
+        (0xf5, 0xb4),
+        (0xd9, 0x90),
+    ]) {
+        blend(&[(e, 3), (top, 1)])
+    } else if is(&[
+        (0x4f, 0x4b),
+        (0xfb, 0x7b),
+        (0xfe, 0x7e),
+        (0x9f, 0x1b),
+        (0x2f, 0x0b),
+        (0xbe, 0x0a),
+        (0x7e, 0x0a),
+        (0xfb, 0x4b),
+        (0xfb, 0xdb),
+        (0xfe, 0xde),
+        (0xfe, 0x56),
+        (0x57, 0x56),
+        (0x97, 0x16),
+        (0x3f, 0x1e),
+        (0xdb, 0x12),
+        (0xbb, 0x12),
+    ]) {
+        blend(&[(e, 7), (top, 1)])
+    } else {
+        e.color
+    }
+}
+
+/// Orders of the 3x3 pixels that mirror each corner of the pixel to the top left, so
+/// `order[i]` is the pixel that ends up at `i`
+const MIRRORS: [[usize; 9]; 4] = [
+    [0, 1, 2, 3, 4, 5, 6, 7, 8],
+    [2, 1, 0, 5, 4, 3, 8, 7, 6],
+    [6, 7, 8, 3, 4, 5, 0, 1, 2],
+    [8, 7, 6, 5, 4, 3, 2, 1, 0],
+];
+
+/// Orders of the 3x3 pixels that turn each corner of the pixel to the top left, going
+/// clockwise, with where that corner and the edge after it end up in a 3x3 block
+const ROTATIONS: [([usize; 9], usize, usize); 4] = [
+    ([0, 1, 2, 3, 4, 5, 6, 7, 8], 0, 1),
+    ([2, 5, 8, 1, 4, 7, 0, 3, 6], 2, 5),
+    ([8, 7, 6, 5, 4, 3, 2, 1, 0], 8, 7),
+    ([6, 3, 0, 7, 4, 1, 8, 5, 2], 6, 3),
+];
+
+fn hq2x(input: &Pixels, x: i32, y: i32) -> [u32; 4] {
+    let w = neighbours(input, x, y);
+    MIRRORS.map(|order| hq2x_corner(&order.map(|i| w[i])))
+}
+
+fn hq3x(input: &Pixels, x: i32, y: i32) -> [u32; 9] {
+    let w = neighbours(input, x, y);
+    let mut block = [w[4].color; 9];
+
+    for (order, corner, edge) in ROTATIONS {
+        let w = order.map(|i| w[i]);
+        block[corner] = hq3x_corner(&w);
+        block[edge] = hq3x_edge(&w);
+    }
+
+    block
+}
+
+fn xbr2x(input: &Pixels, x: i32, y: i32) -> [u32; 4] {
+    type Rotation = fn(i32, i32) -> (i32, i32);
+
+    // the rotations that turn the bottom right corner into each of the output pixels
+    const ROTATIONS: [Rotation; 4] = [
+        |x, y| (-x, -y),
+        |x, y| (y, -x),
+        |x, y| (-y, x),
+        |x, y| (x, y),
+    ];
+
+    ROTATIONS.map(|rotate| {
+        let get = |dx: i32, dy: i32| {
+            let (dx, dy) = rotate(dx, dy);
+            input.get(x + dx, y + dy)
+        };
+
+        let e = get(0, 0);
+        let (b, c, d, f) = (get(0, -1), get(1, -1), get(-1, 0), get(1, 0));
+        let (g, h, i) = (get(-1, 1), get(0, 1), get(1, 1));
+        let (f4, h5, i4, i5) = (get(2, 0), get(0, 2), get(2, 1), get(1, 2));
+
+        // the weight of an edge along h-f, against one along e-i
+        let along = distance(e, c)
+            + distance(e, g)
+            + distance(i, f4)
+            + distance(i, h5)
+            + 4 * distance(h, f);
+        let across = distance(h, d)
+            + distance(h, i5)
+            + distance(f, i4)
+            + distance(f, b)
+            + 4 * distance(e, i);
+
+        if along < across && e != f && e != h {
+            let closest = if distance(e, f) <= distance(e, h) {
+                f
+            } else {
+                h
+            };
+            blend(&[(e, 1), (closest, 1)])
+        } else {
+            e.color
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLACK: [u8; 4] = [0, 0, 0, 0xff];
+    const WHITE: [u8; 4] = [0xff; 4];
+
+    /// An image from rows of `#` (black) and `.` (white), separated by whitespace
+    fn image(rows: &str) -> Image {
+        let rows = rows.split_whitespace().collect::<Vec<_>>();
+        let rgba = rows
+            .iter()
+            .flat_map(|row| row.chars())
+            .flat_map(|c| if c == '#' { BLACK } else { WHITE })
+            .collect();
+        Image::from_rgba(rows[0].len() as u32, rows.len() as u32, rgba)
+    }
+
+    /// An image with shades of gray, from black (0) to white (255)
+    fn gray(rows: &[&[u8]]) -> Image {
+        let rgba = rows
+            .iter()
+            .flat_map(|row| row.iter())
+            .flat_map(|&level| [level, level, level, 0xff])
+            .collect();
+        Image::from_rgba(rows[0].len() as u32, rows.len() as u32, rgba)
+    }
+
+    fn staircase() -> Image {
+        image(
+            "
+            #..
+            ##.
+            ###
+            ",
+        )
+    }
+
+    #[test]
+    fn scale2x_rounds_corners() {
+        let input = image(
+            "
+            ##.
+            #..
+            ...
+            ",
+        );
+
+        assert_eq!(
+            Upscaler::Scale2x.apply(&input),
+            image(
+                "
+                ####..
+                ###...
+                ###...
+                #.....
+                ......
+                ......
+                ",
+            )
+        );
+    }
+
+    #[test]
+    fn scale3x_rounds_corners() {
+        let input = image(
+            "
+            ###
+            #..
+            ...
+            ",
+        );
+
+        assert_eq!(
+            Upscaler::Scale3x.apply(&input),
+            image(
+                "
+                #########
+                #########
+                #########
+                #####....
+                ###......
+                #........
+                .........
+                .........
+                .........
+                ",
+            )
+        );
+    }
+
+    #[test]
+    fn hq2x_smooths_diagonal_edges() {
+        assert_eq!(
+            Upscaler::Hq2x.apply(&staircase()),
+            gray(&[
+                &[0, 0, 191, 255, 255, 255],
+                &[0, 0, 64, 255, 255, 255],
+                &[0, 0, 0, 128, 255, 255],
+                &[0, 0, 0, 0, 64, 191],
+                &[0, 0, 0, 0, 0, 0],
+                &[0, 0, 0, 0, 0, 0],
+            ])
+        );
+    }
+
+    #[test]
+    fn hq3x_smooths_diagonal_edges() {
+        assert_eq!(
+            Upscaler::Hq3x.apply(&staircase()),
+            gray(&[
+                &[0, 0, 0, 191, 255, 255, 255, 255, 255],
+                &[0, 0, 0, 64, 255, 255, 255, 255, 255],
+                &[0, 0, 0, 32, 191, 255, 255, 255, 255],
+                &[0, 0, 0, 0, 32, 128, 255, 255, 255],
+                &[0, 0, 0, 0, 0, 32, 191, 255, 255],
+                &[0, 0, 0, 0, 0, 0, 32, 64, 191],
+                &[0, 0, 0, 0, 0, 0, 0, 0, 0],
+                &[0, 0, 0, 0, 0, 0, 0, 0, 0],
+                &[0, 0, 0, 0, 0, 0, 0, 0, 0],
+            ])
+        );
+    }
+
+    #[test]
+    fn hqx_blends_a_lone_pixel() {
+        let input = image(
+            "
+            ...
+            .#.
+            ...
+            ",
+        );
+
+        // a pixel surrounded by a different color only gets a faint edge in hq2x
+        let output = Upscaler::Hq2x.apply(&input);
+        for (x, y) in [(2, 2), (3, 2), (2, 3), (3, 3)] {
+            assert_eq!(output.get(x, y), [32, 32, 32, 0xff]);
+        }
+
+        // while hq3x keeps its middle, and rounds off the corners
+        let output = Upscaler::Hq3x.apply(&input);
+        for (i, level) in [128, 0, 128, 0, 0, 0, 128, 0, 128].into_iter().enumerate() {
+            let (x, y) = (3 + i as u32 % 3, 3 + i as u32 / 3);
+            assert_eq!(output.get(x, y), [level, level, level, 0xff]);
+        }
+    }
+
+    #[test]
+    fn xbr2x_smooths_diagonal_edges() {
+        assert_eq!(
+            Upscaler::Xbr2x.apply(&staircase()),
+            gray(&[
+                &[0, 0, 255, 255, 255, 255],
+                &[0, 0, 128, 255, 255, 255],
+                &[0, 0, 0, 128, 255, 255],
+                &[0, 0, 0, 0, 128, 255],
+                &[0, 0, 0, 0, 0, 0],
+                &[0, 0, 0, 0, 0, 0],
+            ])
+        );
+    }
+
+    #[test]
+    fn nearest_repeats_pixels() {
+        assert_eq!(
+            Upscaler::Nearest(3).apply(&image("#. .#")),
+            image(
+                "
+                ###...
+                ###...
+                ###...
+                ...###
+                ...###
+                ...###
+                ",
+            )
+        );
+    }
+
+    #[test]
+    fn scaling_keeps_flat_areas() {
+        for upscaler in [
+            Upscaler::None,
+            Upscaler::Nearest(2),
+            Upscaler::Scale2x,
+            Upscaler::Scale3x,
+            Upscaler::Hq2x,
+            Upscaler::Hq3x,
+            Upscaler::Xbr2x,
+        ] {
+            let size = 2 * upscaler.factor() as usize;
+            let row = "#".repeat(size);
+            assert_eq!(
+                upscaler.apply(&image("## ##")),
+                image(&vec![row.as_str(); size].join(" "))
+            );
+        }
+    }
+}
@@ -0,0 +1,178 @@
+use crate::sink::FrameSink;
+use crate::{Frame, Palette};
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+/// The hashes (see [`Frame::hash`]) of every frame the PPU completed, in order.
+///
+/// This is a [`FrameSink`], so it can collect the hashes while running the emulator with
+/// [`run_cpu_with_sink`](crate::run_cpu_with_sink), or use
+/// [`run_cpu_headless_hashes`](crate::run_cpu_headless_hashes). Logs can be saved and loaded, and
+/// compared with [`FrameHashLog::first_difference`] to find the first frame that's drawn differently
+/// in another run, without storing any pictures.
+///
+/// Saved logs are text, with one hash per line as 16 hexadecimal digits. Empty lines and lines
+/// starting with `#` are skipped when loading.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FrameHashLog {
+    hashes: Vec<u64>,
+}
+
+/// Where two [`FrameHashLog`]s differ, see [`FrameHashLog::first_difference`]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct FrameHashDifference {
+    /// The number of the first frame that's different, counting from 0
+    pub frame: usize,
+    /// The hash of the frame in the log that was compared, or `None` when that log ended before it
+    pub hash: Option<u64>,
+    /// The hash of the frame in the other log, or `None` when that log ended before it
+    pub other_hash: Option<u64>,
+}
+
+impl FrameHashLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The hashes of all frames, in the order they were completed
+    pub fn hashes(&self) -> &[u64] {
+        &self.hashes
+    }
+
+    /// How many frames are in the log
+    pub fn len(&self) -> usize {
+        self.hashes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hashes.is_empty()
+    }
+
+    /// Finds the first frame where this log and `other` differ, or where one of them ends
+    /// before the other does. Returns `None` when they're the same.
+    pub fn first_difference(&self, other: &FrameHashLog) -> Option<FrameHashDifference> {
+        let frames = self.hashes.len().max(other.hashes.len());
+        (0..frames)
+            .map(|frame| FrameHashDifference {
+                frame,
+                hash: self.hashes.get(frame).copied(),
+                other_hash: other.hashes.get(frame).copied(),
+            })
+            .find(|difference| difference.hash != difference.other_hash)
+    }
+
+    /// Writes the log to `writer`
+    pub fn write_to(&self, writer: impl Write) -> io::Result<()> {
+        let mut writer = BufWriter::new(writer);
+        for hash in &self.hashes {
+            writeln!(writer, "{hash:016x}")?;
+        }
+        writer.flush()
+    }
+
+    /// Reads a log that was written with [`FrameHashLog::write_to`]
+    pub fn read_from(reader: impl Read) -> io::Result<Self> {
+        let mut hashes = Vec::new();
+        for (index, line) in BufReader::new(reader).lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let hash = u64::from_str_radix(line, 16).map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("invalid frame hash on line {}: {e}", index + 1),
+                )
+            })?;
+            hashes.push(hash);
+        }
+
+        Ok(Self { hashes })
+    }
+
+    /// Saves the log to a file at `path`
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        self.write_to(File::create(path)?)
+    }
+
+    /// Loads a log from a file at `path`, that was saved with [`FrameHashLog::save`]
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::read_from(File::open(path)?)
+    }
+}
+
+impl FrameSink for FrameHashLog {
+    fn frame_completed(&mut self, frame: &Frame, _palette: &Palette) {
+        self.hashes.push(frame.hash());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn log(hashes: &[u64]) -> FrameHashLog {
+        FrameHashLog {
+            hashes: hashes.to_vec(),
+        }
+    }
+
+    #[test]
+    fn save_and_load() {
+        let hashes = log(&[0, 1, 0x0123_4567_89ab_cdef, u64::MAX]);
+        let path = std::env::temp_dir().join(format!("frame-hashes-{}.txt", std::process::id()));
+
+        hashes.save(&path).unwrap();
+        let loaded = FrameHashLog::load(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.unwrap(), hashes);
+    }
+
+    #[test]
+    fn read_skips_comments_and_rejects_garbage() {
+        let text = "# run 1\n\n00000000000000ff\n  0000000000000001  \n";
+        assert_eq!(
+            FrameHashLog::read_from(text.as_bytes()).unwrap(),
+            log(&[0xff, 1])
+        );
+
+        let error = FrameHashLog::read_from("00ff\nnot a hash\n".as_bytes()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn first_difference() {
+        let hashes = log(&[1, 2, 3]);
+
+        assert_eq!(hashes.first_difference(&log(&[1, 2, 3])), None);
+        assert_eq!(
+            hashes.first_difference(&log(&[1, 5, 3])),
+            Some(FrameHashDifference {
+                frame: 1,
+                hash: Some(2),
+                other_hash: Some(5),
+            })
+        );
+        assert_eq!(
+            hashes.first_difference(&log(&[1, 2])),
+            Some(FrameHashDifference {
+                frame: 2,
+                hash: Some(3),
+                other_hash: None,
+            })
+        );
+        assert_eq!(
+            log(&[]).first_difference(&hashes),
+            Some(FrameHashDifference {
+                frame: 0,
+                hash: None,
+                other_hash: Some(1),
+            })
+        );
+    }
+}
@@ -0,0 +1,383 @@
+use crate::ppu::colors::Palette;
+use crate::ppu::frame::Frame;
+#[cfg(feature = "gif")]
+use crate::recording::FRAME_RATE;
+use crate::settings::Overscan;
+#[cfg(feature = "gif")]
+use crate::WIDTH;
+#[cfg(feature = "gif")]
+use gif::{Encoder, Repeat};
+#[cfg(feature = "gif")]
+use std::borrow::Cow;
+#[cfg(feature = "gif")]
+use std::fs;
+#[cfg(feature = "gif")]
+use std::fs::File;
+use std::io;
+#[cfg(feature = "gif")]
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+/// Settings for animated GIFs captured with [`Control::start_gif`](crate::Control::start_gif)
+/// or by pressing F10 in the window.
+///
+/// GIFs store the colors of the NES palette directly, so the [`Settings::filters`](crate::Settings::filters)
+/// aren't applied to them.
+#[derive(Debug, Clone)]
+pub struct GifSettings {
+    /// Where GIFs are saved. The current directory by default.
+    pub directory: PathBuf,
+    /// How many frames are skipped after every frame that's put in the GIF, to make it smaller.
+    ///
+    /// 1 by default, which gives about 30 frames per second. Most browsers can't show GIFs
+    /// faster than 50 frames per second, so without skipping frames the GIF plays too slow.
+    pub frame_skip: u32,
+    /// Whether the edges cut off by [`Settings::overscan`](crate::Settings::overscan) are left out
+    /// of GIFs too. `true` by default.
+    pub crop_overscan: bool,
+    /// The palette used for GIFs. When `None` (the default), the palette
+    /// that's currently selected on the PPU is used.
+    pub palette: Option<Palette>,
+}
+
+impl Default for GifSettings {
+    fn default() -> Self {
+        Self {
+            directory: PathBuf::from("."),
+            frame_skip: 1,
+            crop_overscan: true,
+            palette: None,
+        }
+    }
+}
+
+/// Encodes frames into an animated GIF.
+///
+/// GIFs can have at most 256 colors. The 64 colors of the NES palette are used as the global
+/// palette, so most frames are stored without any color conversion. Frames that use color
+/// emphasis, or that are drawn with another palette than the first frame, get their own palette.
+#[cfg(feature = "gif")]
+pub(crate) struct GifRecorder {
+    path: PathBuf,
+    /// The file is only turned into an encoder at the first frame, when the global palette is known
+    file: Option<BufWriter<File>>,
+    encoder: Option<(Encoder<BufWriter<File>>, Palette)>,
+    overscan: Overscan,
+    palette: Option<Palette>,
+    frame_skip: u32,
+    /// How many more frames are put in the GIF, if the length is limited
+    frames_left: Option<u32>,
+    /// How many frames were completed since the recording started
+    frames: u64,
+    indices: Vec<u8>,
+    /// Which index in the palette of the current frame each pixel value got
+    lookup: Box<[u8; 512]>,
+}
+
+#[cfg(feature = "gif")]
+impl GifRecorder {
+    pub(crate) fn new(
+        path: PathBuf,
+        settings: &GifSettings,
+        overscan: Overscan,
+        frames: Option<u32>,
+    ) -> io::Result<Self> {
+        let overscan = if settings.crop_overscan {
+            overscan.clamped()
+        } else {
+            Overscan::NONE
+        };
+
+        Ok(Self {
+            file: Some(BufWriter::new(File::create(&path)?)),
+            path,
+            encoder: None,
+            overscan,
+            palette: settings.palette.clone(),
+            frame_skip: settings.frame_skip,
+            frames_left: frames,
+            frames: 0,
+            indices: vec![0; (overscan.width() * overscan.height()) as usize],
+            lookup: Box::new([0; 512]),
+        })
+    }
+
+    /// Whether all frames that were asked for are in the GIF
+    pub(crate) fn is_done(&self) -> bool {
+        self.frames_left == Some(0)
+    }
+
+    pub(crate) fn write_frame(&mut self, frame: &Frame, palette: &Palette) -> io::Result<()> {
+        let number = self.frames;
+        let frames_shown = self.frame_skip as u64 + 1;
+        self.frames += 1;
+        if number % frames_shown != 0 || self.is_done() {
+            return Ok(());
+        }
+
+        let palette = self.palette.as_ref().unwrap_or(palette);
+        let (encoder, global_palette) = match (&mut self.encoder, self.file.take()) {
+            (Some((encoder, global_palette)), _) => (encoder, &*global_palette),
+            (None, Some(file)) => {
+                let mut encoder = Encoder::new(
+                    file,
+                    self.overscan.width() as u16,
+                    self.overscan.height() as u16,
+                    &colors(palette, 0..64),
+                )
+                .map_err(io::Error::other)?;
+                encoder
+                    .set_repeat(Repeat::Infinite)
+                    .map_err(io::Error::other)?;
+
+                let (encoder, global_palette) = self.encoder.insert((encoder, palette.clone()));
+                (encoder, &*global_palette)
+            }
+            (None, None) => unreachable!("the gif has either a file or an encoder"),
+        };
+
+        // the 64 colors without emphasis always get the same index, other pixel values
+        // get the next free index the first time they're used in this frame
+        let mut local_colors = (0..64).collect::<Vec<u16>>();
+        for (pixel, index) in self.lookup.iter_mut().enumerate() {
+            *index = if pixel < 64 { pixel as u8 } else { u8::MAX };
+        }
+
+        let width = self.overscan.width() as usize;
+        let rows = frame
+            .pixels()
+            .chunks_exact(WIDTH as usize)
+            .skip(self.overscan.top as usize)
+            .zip(self.indices.chunks_exact_mut(width));
+
+        for (row, out_row) in rows {
+            let row = &row[self.overscan.left as usize..self.overscan.left as usize + width];
+            for (&pixel, out) in row.iter().zip(out_row) {
+                let pixel = pixel as usize & 0x1ff;
+                if self.lookup[pixel] == u8::MAX && pixel >= 64 {
+                    self.lookup[pixel] = if local_colors.len() < 255 {
+                        local_colors.push(pixel as u16);
+                        (local_colors.len() - 1) as u8
+                    } else {
+                        // out of colors: leave out the emphasis
+                        (pixel & 0x3f) as u8
+                    };
+                }
+                *out = self.lookup[pixel];
+            }
+        }
+
+        let local_palette = if local_colors.len() > 64 || global_palette != palette {
+            Some(colors(palette, local_colors.into_iter()))
+        } else {
+            None
+        };
+
+        encoder
+            .write_frame(&gif::Frame {
+                width: self.overscan.width() as u16,
+                height: self.overscan.height() as u16,
+                delay: (centiseconds(number + frames_shown) - centiseconds(number)) as u16,
+                palette: local_palette,
+                buffer: Cow::Borrowed(&self.indices),
+                ..gif::Frame::default()
+            })
+            .map_err(io::Error::other)?;
+
+        if let Some(frames_left) = &mut self.frames_left {
+            *frames_left -= 1;
+        }
+
+        Ok(())
+    }
+
+    /// Writes the end of the GIF, and returns where it was saved.
+    /// When no frames were captured there's no GIF, so the file is removed and `None` is returned.
+    pub(crate) fn finish(self) -> io::Result<Option<PathBuf>> {
+        match self.encoder {
+            Some((encoder, _)) => {
+                encoder.into_inner()?.flush()?;
+                Ok(Some(self.path))
+            }
+            None => {
+                drop(self.file);
+                fs::remove_file(self.path)?;
+                Ok(None)
+            }
+        }
+    }
+}
+
+/// The time (in 1/100th of a second, the unit GIFs use) at which a frame starts
+#[cfg(feature = "gif")]
+fn centiseconds(frame: u64) -> u64 {
+    let (numerator, denominator) = (FRAME_RATE.0 as u64, FRAME_RATE.1 as u64);
+    (frame * denominator * 100 + numerator / 2) / numerator
+}
+
+/// The colors of the `pixels` in `palette`, as RGB
+#[cfg(feature = "gif")]
+fn colors(palette: &Palette, pixels: impl Iterator<Item = u16>) -> Vec<u8> {
+    pixels
+        .flat_map(|pixel| {
+            let (r, g, b) = palette.color(pixel);
+            [r, g, b]
+        })
+        .collect()
+}
+
+/// Without the `gif` feature there's no encoder, so capturing a GIF fails right away
+#[cfg(not(feature = "gif"))]
+pub(crate) struct GifRecorder(std::convert::Infallible);
+
+#[cfg(not(feature = "gif"))]
+impl GifRecorder {
+    pub(crate) fn new(
+        _path: PathBuf,
+        _settings: &GifSettings,
+        _overscan: Overscan,
+        _frames: Option<u32>,
+    ) -> io::Result<Self> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "capturing GIFs needs the `gif` feature",
+        ))
+    }
+
+    pub(crate) fn is_done(&self) -> bool {
+        match self.0 {}
+    }
+
+    pub(crate) fn write_frame(&mut self, _frame: &Frame, _palette: &Palette) -> io::Result<()> {
+        match self.0 {}
+    }
+
+    pub(crate) fn finish(self) -> io::Result<Option<PathBuf>> {
+        match self.0 {}
+    }
+}
+
+#[cfg(all(test, feature = "gif"))]
+mod tests {
+    use super::*;
+    use std::env;
+
+    /// A frame filled with one pixel value
+    fn solid(pixel: u16) -> Frame {
+        let mut frame = Frame::default();
+        for index in 0..256 * 240 {
+            frame.set(index % 256, index / 256, pixel);
+        }
+        frame
+    }
+
+    /// Captures `frames` into a GIF with the given frame skip and limit, and decodes it again
+    fn capture(
+        name: &str,
+        frame_skip: u32,
+        limit: Option<u32>,
+        frames: &[Frame],
+    ) -> Vec<gif::Frame<'static>> {
+        let path = env::temp_dir().join(format!("capture-{name}-{}.gif", std::process::id()));
+        let settings = GifSettings {
+            frame_skip,
+            ..GifSettings::default()
+        };
+        let palette = Palette::default();
+
+        let mut recorder =
+            GifRecorder::new(path.clone(), &settings, Overscan::NTSC, limit).unwrap();
+        for frame in frames {
+            recorder.write_frame(frame, &palette).unwrap();
+        }
+        assert_eq!(recorder.finish().unwrap(), Some(path.clone()));
+
+        let mut decoder = gif::Decoder::new(File::open(&path).unwrap()).unwrap();
+        assert_eq!((decoder.width(), decoder.height()), (256, 224));
+        assert_eq!(decoder.global_palette(), Some(&*colors(&palette, 0..64)));
+        let mut decoded = Vec::new();
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            decoded.push(frame.clone());
+        }
+        fs::remove_file(&path).unwrap();
+
+        decoded
+    }
+
+    #[test]
+    fn skipped_frames_are_left_out() {
+        let frames = (0..7).map(solid).collect::<Vec<_>>();
+
+        let decoded = capture("skip", 2, None, &frames);
+        let first_pixels = decoded
+            .iter()
+            .map(|frame| frame.buffer[0])
+            .collect::<Vec<_>>();
+        assert_eq!(first_pixels, [0, 3, 6]);
+    }
+
+    #[test]
+    fn delays_add_up_to_the_frame_rate() {
+        let frames = vec![solid(0); 8];
+
+        let decoded = capture("delays", 1, None, &frames);
+        let delays = decoded.iter().map(|frame| frame.delay).collect::<Vec<_>>();
+        // 2 frames of the NES take 3.33 centiseconds
+        assert_eq!(delays, [3, 4, 3, 3]);
+    }
+
+    #[test]
+    fn emphasis_gets_its_own_palette() {
+        let mut emphasized = solid(0x16);
+        emphasized.set(0, 8, 0x16 | 0b001 << 6);
+
+        let decoded = capture("emphasis", 0, None, &[solid(0x16), emphasized]);
+        assert_eq!(decoded[0].palette, None);
+        assert!(decoded[0].buffer.iter().all(|&index| index == 0x16));
+
+        let palette = Palette::default();
+        let mut expected = colors(&palette, 0..64);
+        expected.extend(colors(&palette, [0x56].into_iter()));
+        // GIF palettes have a power of 2 colors, so the decoded one is padded
+        let local_palette = decoded[1].palette.as_ref().expect("a local palette");
+        assert_eq!(local_palette[..expected.len()], expected);
+        assert_eq!(decoded[1].buffer[0], 64);
+        assert!(decoded[1].buffer[1..].iter().all(|&index| index == 0x16));
+    }
+
+    #[test]
+    fn capturing_stops_at_the_limit() {
+        let path = env::temp_dir().join(format!("capture-limit-{}.gif", std::process::id()));
+        let settings = GifSettings {
+            frame_skip: 0,
+            ..GifSettings::default()
+        };
+        let mut recorder =
+            GifRecorder::new(path.clone(), &settings, Overscan::NONE, Some(2)).unwrap();
+
+        recorder
+            .write_frame(&solid(0), &Palette::default())
+            .unwrap();
+        assert!(!recorder.is_done());
+        recorder
+            .write_frame(&solid(1), &Palette::default())
+            .unwrap();
+        assert!(recorder.is_done());
+        recorder.finish().unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let frames = (0..5).map(solid).collect::<Vec<_>>();
+        assert_eq!(capture("limit", 0, Some(2), &frames).len(), 2);
+    }
+
+    #[test]
+    fn nothing_is_saved_without_frames() {
+        let path = env::temp_dir().join(format!("capture-empty-{}.gif", std::process::id()));
+        let recorder =
+            GifRecorder::new(path.clone(), &GifSettings::default(), Overscan::NONE, None).unwrap();
+        assert!(path.exists());
+
+        assert_eq!(recorder.finish().unwrap(), None);
+        assert!(!path.exists());
+    }
+}
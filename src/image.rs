@@ -0,0 +1,86 @@
+use crate::screenshot::save_png;
+use std::io;
+use std::path::Path;
+
+/// An RGBA picture, like a [`Frame`](crate::Frame) after its colors are looked up
+/// and the [`Filters`](crate::Filters) are applied.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Image {
+    width: u32,
+    height: u32,
+    rgba: Vec<u8>,
+}
+
+impl Image {
+    /// Creates a black image
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            rgba: vec![0; (width * height * 4) as usize],
+        }
+    }
+
+    /// Creates an image from RGBA pixels, 4 bytes per pixel, row by row.
+    ///
+    /// # Panics
+    /// When `rgba` doesn't have the right length for the size
+    pub fn from_rgba(width: u32, height: u32, rgba: Vec<u8>) -> Self {
+        assert_eq!(
+            rgba.len(),
+            (width * height * 4) as usize,
+            "wrong image size"
+        );
+        Self {
+            width,
+            height,
+            rgba,
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// All pixels, 4 bytes per pixel, row by row
+    pub fn rgba(&self) -> &[u8] {
+        &self.rgba
+    }
+
+    pub fn rgba_mut(&mut self) -> &mut [u8] {
+        &mut self.rgba
+    }
+
+    /// Gets the RGBA color of the pixel at `x`, `y`.
+    ///
+    /// # Panics
+    /// When the coordinates are outside the image
+    pub fn get(&self, x: u32, y: u32) -> [u8; 4] {
+        assert!(x < self.width && y < self.height);
+        let index = ((y * self.width + x) * 4) as usize;
+        [
+            self.rgba[index],
+            self.rgba[index + 1],
+            self.rgba[index + 2],
+            self.rgba[index + 3],
+        ]
+    }
+
+    /// Changes the size of the image. The pixels are left in an unspecified state.
+    pub(crate) fn resize(&mut self, width: u32, height: u32) {
+        self.width = width;
+        self.height = height;
+        self.rgba.resize((width * height * 4) as usize, 0);
+    }
+
+    /// Saves the image as a PNG file
+    ///
+    /// Without the `png` feature, this fails with [`io::ErrorKind::Unsupported`].
+    pub fn save_png(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        save_png(path.as_ref(), &self.rgba, self.width, self.height)
+    }
+}
@@ -0,0 +1,223 @@
+use crate::image::Image;
+use crate::screen::{ButtonName, Buttons};
+
+/// A corner of the picture
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
+pub enum Corner {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    /// This is the default.
+    #[default]
+    BottomRight,
+}
+
+/// Shows which buttons are pressed on the controller, as a small drawing of a controller
+/// over the picture. Useful for tutorials, or to review inputs frame by frame in recordings.
+/// Enable it with [`OsdSettings::input_display`](crate::OsdSettings::input_display).
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct InputDisplay {
+    /// Where the controller is drawn. The bottom right corner by default.
+    pub corner: Corner,
+    /// How much the controller covers the picture, from 0.0 (invisible) to 1.0. 0.8 by default.
+    pub opacity: f32,
+}
+
+impl Default for InputDisplay {
+    fn default() -> Self {
+        Self {
+            corner: Corner::default(),
+            opacity: 0.8,
+        }
+    }
+}
+
+/// How big the controller is, in pixels of a frame
+const WIDTH: u32 = 40;
+const HEIGHT: u32 = 16;
+
+/// What the controller is made of
+enum Part {
+    Body,
+    /// The middle of the D-pad, which can't be pressed
+    Center,
+    Button(ButtonName),
+}
+
+/// The parts of the controller as `(x, y, width, height, part)`, drawn in this order
+const PARTS: [(u32, u32, u32, u32, Part); 10] = [
+    (0, 0, WIDTH, HEIGHT, Part::Body),
+    (6, 6, 4, 4, Part::Center),
+    (6, 2, 4, 4, Part::Button(ButtonName::Up)),
+    (6, 10, 4, 4, Part::Button(ButtonName::Down)),
+    (2, 6, 4, 4, Part::Button(ButtonName::Left)),
+    (10, 6, 4, 4, Part::Button(ButtonName::Right)),
+    (15, 9, 4, 2, Part::Button(ButtonName::Select)),
+    (21, 9, 4, 2, Part::Button(ButtonName::Start)),
+    (27, 6, 4, 4, Part::Button(ButtonName::B)),
+    (33, 6, 4, 4, Part::Button(ButtonName::A)),
+];
+
+impl InputDisplay {
+    /// Draws the controller with `buttons` pressed over `image`, scaled to the size of the picture
+    pub(crate) fn draw(&self, image: &mut Image, buttons: Buttons) {
+        let scale = ((image.height() + 120) / 240).max(1);
+        let margin = 4 * scale;
+        let (width, height) = (WIDTH * scale, HEIGHT * scale);
+        if image.width() < width + margin * 2 || image.height() < height + margin * 2 {
+            return;
+        }
+
+        let left = match self.corner {
+            Corner::TopLeft | Corner::BottomLeft => margin,
+            Corner::TopRight | Corner::BottomRight => image.width() - width - margin,
+        };
+        let top = match self.corner {
+            Corner::TopLeft | Corner::TopRight => margin,
+            Corner::BottomLeft | Corner::BottomRight => image.height() - height - margin,
+        };
+        let opacity = self.opacity.clamp(0.0, 1.0);
+        let image_width = image.width();
+
+        for (x, y, part_width, part_height, part) in &PARTS {
+            let color = match part {
+                Part::Body => [0x20, 0x20, 0x20],
+                Part::Center => [0x60, 0x60, 0x60],
+                Part::Button(button @ (ButtonName::A | ButtonName::B)) => {
+                    if is_pressed(buttons, *button) {
+                        [0xff, 0x40, 0x40]
+                    } else {
+                        [0x80, 0x20, 0x20]
+                    }
+                }
+                Part::Button(button) => {
+                    if is_pressed(buttons, *button) {
+                        [0xf0, 0xf0, 0xf0]
+                    } else {
+                        [0x60, 0x60, 0x60]
+                    }
+                }
+            };
+            // A and B are round, so their corners are left out
+            let round = matches!(part, Part::Button(ButtonName::A | ButtonName::B));
+
+            for dy in 0..part_height * scale {
+                for dx in 0..part_width * scale {
+                    let edge_x = dx < scale || dx >= (part_width - 1) * scale;
+                    let edge_y = dy < scale || dy >= (part_height - 1) * scale;
+                    if round && edge_x && edge_y {
+                        continue;
+                    }
+
+                    let px = left + x * scale + dx;
+                    let py = top + y * scale + dy;
+                    let index = ((py * image_width + px) * 4) as usize;
+                    let rgb = &mut image.rgba_mut()[index..index + 3];
+                    for (channel, value) in rgb.iter_mut().zip(color) {
+                        *channel = (*channel as f32 * (1.0 - opacity) + value as f32 * opacity)
+                            .round() as u8;
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn is_pressed(buttons: Buttons, button: ButtonName) -> bool {
+    match button {
+        ButtonName::A => buttons.a,
+        ButtonName::B => buttons.b,
+        ButtonName::Up => buttons.up,
+        ButtonName::Down => buttons.down,
+        ButtonName::Left => buttons.left,
+        ButtonName::Right => buttons.right,
+        ButtonName::Start => buttons.start,
+        ButtonName::Select => buttons.select,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A grey picture the size of a frame
+    fn grey() -> Image {
+        Image::from_rgba(256, 240, vec![0x80; 256 * 240 * 4])
+    }
+
+    /// The pixels that differ between `a` and `b`
+    fn changed(a: &Image, b: &Image) -> Vec<(u32, u32)> {
+        (0..a.height())
+            .flat_map(|y| (0..a.width()).map(move |x| (x, y)))
+            .filter(|&(x, y)| a.get(x, y) != b.get(x, y))
+            .collect()
+    }
+
+    fn pressing_a() -> Buttons {
+        Buttons {
+            a: true,
+            ..Buttons::default()
+        }
+    }
+
+    #[test]
+    fn draws_in_the_chosen_corner() {
+        let corners = [
+            (Corner::TopLeft, (4, 4)),
+            (Corner::TopRight, (256 - 4 - WIDTH, 4)),
+            (Corner::BottomLeft, (4, 240 - 4 - HEIGHT)),
+            (Corner::BottomRight, (256 - 4 - WIDTH, 240 - 4 - HEIGHT)),
+        ];
+        for (corner, (left, top)) in corners {
+            let display = InputDisplay {
+                corner,
+                ..InputDisplay::default()
+            };
+            let mut image = grey();
+            display.draw(&mut image, pressing_a());
+
+            let changed = changed(&image, &grey());
+            assert_eq!(changed.len(), (WIDTH * HEIGHT) as usize, "{corner:?}");
+            assert!(
+                changed
+                    .iter()
+                    .all(|&(x, y)| (left..left + WIDTH).contains(&x)
+                        && (top..top + HEIGHT).contains(&y)),
+                "{corner:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn pressed_buttons_are_drawn_differently() {
+        let display = InputDisplay::default();
+        let mut released = grey();
+        display.draw(&mut released, Buttons::default());
+        let mut pressed = grey();
+        display.draw(&mut pressed, pressing_a());
+
+        // only A changes, which is round
+        let (left, top) = (256 - 4 - WIDTH + 33, 240 - 4 - HEIGHT + 6);
+        let changed = changed(&released, &pressed);
+        assert_eq!(changed.len(), 4 * 4 - 4);
+        assert!(changed
+            .iter()
+            .all(|&(x, y)| (left..left + 4).contains(&x) && (top..top + 4).contains(&y)));
+    }
+
+    #[test]
+    fn invisible_when_transparent_or_too_small() {
+        let display = InputDisplay {
+            opacity: 0.0,
+            ..InputDisplay::default()
+        };
+        let mut image = grey();
+        display.draw(&mut image, pressing_a());
+        assert!(changed(&image, &grey()).is_empty());
+
+        let small = Image::from_rgba(32, 16, vec![0x80; 32 * 16 * 4]);
+        let mut image = small.clone();
+        InputDisplay::default().draw(&mut image, pressing_a());
+        assert!(changed(&image, &small).is_empty());
+    }
+}
@@ -7,13 +7,59 @@ pub const HEIGHT: u32 = 240;
 /// That's also what's emulated in the rest of the ppu.
 pub const CPU_FREQ: f64 = 1.789_773 * 1_000_000.0; //hz
 
+mod control;
 mod cpu;
+mod filter;
+mod frame_hash;
+mod gif_capture;
+mod image;
+mod input_display;
+mod osd;
 mod ppu;
+mod recording;
 mod run;
 mod screen;
+mod screenshot;
+mod settings;
+mod sink;
+#[cfg(feature = "software")]
+mod software;
+#[cfg(feature = "stream")]
+mod stream;
+#[cfg(feature = "terminal")]
+mod terminal;
+#[cfg(test)]
+mod testing;
+mod triple_buffer;
+#[cfg(feature = "vnc")]
+mod vnc;
 
+pub use control::Control;
 pub use cpu::Cpu;
+pub use filter::{AspectRatio, Blending, CrtFilter, CrtMask, Filters, NtscFilter, Upscaler};
+pub use frame_hash::{FrameHashDifference, FrameHashLog};
+pub use gif_capture::GifSettings;
+pub use image::Image;
+pub use input_display::{Corner, InputDisplay};
+pub use osd::OsdSettings;
+pub use ppu::colors::{BuiltinPalette, Color, Palette, PaletteError};
+pub use ppu::fetch::{FetchKind, PpuFetch};
+pub use ppu::frame::Frame;
 pub use ppu::mirroring::Mirroring;
+pub use ppu::ntsc::PaletteGenerator;
 pub use ppu::{registers::PpuRegister, Ppu};
-pub use run::{run_cpu, run_cpu_headless, run_cpu_headless_for};
-pub use screen::Buttons;
+pub use recording::{RecordingSettings, VideoFormat, FRAME_RATE};
+pub use run::{
+    run_cpu, run_cpu_headless, run_cpu_headless_capture, run_cpu_headless_for,
+    run_cpu_headless_hashes, run_cpu_with_settings, run_cpu_with_sink,
+};
+pub use screen::{Buttons, WindowBackend};
+pub use screenshot::ScreenshotSettings;
+pub use settings::{Overscan, Settings};
+pub use sink::{CapturedFrame, DummySink, FrameCapture, FrameSink};
+#[cfg(feature = "stream")]
+pub use stream::{FrameStream, StreamProtocol};
+#[cfg(feature = "terminal")]
+pub use terminal::{run_cpu_in_terminal, run_cpu_in_terminal_with_settings, TerminalSettings};
+#[cfg(feature = "vnc")]
+pub use vnc::{run_cpu_vnc, run_cpu_vnc_with_settings};
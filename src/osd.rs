@@ -0,0 +1,448 @@
+use crate::image::Image;
+use crate::input_display::InputDisplay;
+use crate::recording::FRAME_RATE;
+use crate::screen::Buttons;
+use std::time::{Duration, Instant};
+
+/// How many pixels wide and high every character is, including the space after it
+const CHAR_WIDTH: u32 = 6;
+const CHAR_HEIGHT: u32 = 8;
+/// How many messages are shown at once. Older messages make way for newer ones.
+const MAX_MESSAGES: usize = 4;
+
+/// Settings for the on-screen display: text drawn over the picture, like messages and
+/// a frame rate counter. It's shown in the window, and in everything else that gets the
+/// pictures through [`FrameSink::image_completed`](crate::FrameSink::image_completed).
+/// Screenshots and recordings leave it out, unless they're set to include it.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct OsdSettings {
+    /// Whether the on-screen display is shown at all. `true` by default.
+    pub enabled: bool,
+    /// Shows how many frames are drawn per second, and how fast that is compared to
+    /// a real NES, in the top right corner. `false` by default.
+    pub show_fps: bool,
+    /// How long messages are shown. 3 seconds by default.
+    pub message_duration: Duration,
+    /// Shows which buttons are pressed on the controller. `None` (not shown) by default.
+    pub input_display: Option<InputDisplay>,
+}
+
+impl Default for OsdSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            show_fps: false,
+            message_duration: Duration::from_secs(3),
+            input_display: None,
+        }
+    }
+}
+
+/// What the on-screen display shows, and draws it over pictures
+pub(crate) struct Osd {
+    settings: OsdSettings,
+    /// The messages, oldest first, with when they disappear
+    messages: Vec<(String, Instant)>,
+    /// The frame rate counter, if it's shown
+    fps: Option<FpsCounter>,
+    pub(crate) recording: bool,
+    pub(crate) capturing_gif: bool,
+    /// The buttons pressed when the last frame was completed
+    pub(crate) buttons: Buttons,
+}
+
+struct FpsCounter {
+    /// When the current second started, and how many frames were completed since
+    since: Instant,
+    frames: u32,
+    text: String,
+}
+
+impl Osd {
+    pub(crate) fn new(settings: OsdSettings) -> Self {
+        Self {
+            settings,
+            messages: Vec::new(),
+            fps: settings.show_fps.then(|| FpsCounter {
+                since: Instant::now(),
+                frames: 0,
+                text: String::new(),
+            }),
+            recording: false,
+            capturing_gif: false,
+            buttons: Buttons::default(),
+        }
+    }
+
+    /// Shows `text` for a while. When the newest message is the same, it's shown for longer instead.
+    pub(crate) fn show_message(&mut self, text: impl Into<String>) {
+        let text = text.into();
+        let until = Instant::now() + self.settings.message_duration;
+
+        match self.messages.last_mut() {
+            Some((last, last_until)) if *last == text => *last_until = until,
+            _ => {
+                self.messages.push((text, until));
+                if self.messages.len() > MAX_MESSAGES {
+                    self.messages.remove(0);
+                }
+            }
+        }
+    }
+
+    /// Counts a frame for the frame rate counter, and removes messages that are done
+    pub(crate) fn frame_completed(&mut self) {
+        let now = Instant::now();
+        self.messages.retain(|(_, until)| *until > now);
+
+        if let Some(fps) = &mut self.fps {
+            fps.frames += 1;
+            let elapsed = now.duration_since(fps.since);
+            if elapsed >= Duration::from_secs(1) {
+                let rate = fps.frames as f64 / elapsed.as_secs_f64();
+                let speed = rate / (FRAME_RATE.0 as f64 / FRAME_RATE.1 as f64) * 100.0;
+                fps.text = format!("{rate:.0} FPS {speed:.0}%");
+                fps.since = now;
+                fps.frames = 0;
+            }
+        }
+    }
+
+    /// Whether there's any text to draw
+    pub(crate) fn is_visible(&self) -> bool {
+        self.settings.enabled
+            && (!self.messages.is_empty()
+                || self.fps.as_ref().is_some_and(|fps| !fps.text.is_empty())
+                || self.recording
+                || self.capturing_gif)
+    }
+
+    /// Whether the controller is drawn
+    pub(crate) fn shows_inputs(&self) -> bool {
+        self.settings.enabled && self.settings.input_display.is_some()
+    }
+
+    /// Draws the controller over `image`, if it's shown
+    pub(crate) fn draw_inputs(&self, image: &mut Image) {
+        if let Some(input_display) = &self.settings.input_display {
+            if self.settings.enabled {
+                input_display.draw(image, self.buttons);
+            }
+        }
+    }
+
+    /// Draws all the text over `image`, scaled to the size of the picture
+    pub(crate) fn draw(&self, image: &mut Image) {
+        if !self.is_visible() {
+            return;
+        }
+
+        // the same size relative to the picture, whatever filters made it bigger
+        let scale = ((image.height() + 120) / 240).max(1);
+        let margin = 4 * scale;
+
+        let status = [
+            self.recording.then_some("REC"),
+            self.capturing_gif.then_some("GIF"),
+        ];
+        let status = status.into_iter().flatten().collect::<Vec<_>>().join(" ");
+        if !status.is_empty() {
+            draw_text(image, &status, margin, margin, scale, [0xff, 0x40, 0x40]);
+        }
+
+        if let Some(fps) = &self.fps {
+            let width = text_width(&fps.text, scale);
+            let x = image.width().saturating_sub(width + margin);
+            draw_text(image, &fps.text, x, margin, scale, [0xff; 3]);
+        }
+
+        // the newest message at the bottom
+        let line_height = (CHAR_HEIGHT + 2) * scale;
+        let mut y = image.height().saturating_sub(margin + CHAR_HEIGHT * scale);
+        for (text, _) in self.messages.iter().rev() {
+            draw_text(image, text, margin, y, scale, [0xff; 3]);
+            y = y.saturating_sub(line_height);
+        }
+    }
+}
+
+fn text_width(text: &str, scale: u32) -> u32 {
+    text.chars().count() as u32 * CHAR_WIDTH * scale
+}
+
+/// Draws `text` with its top left corner at `x`, `y`, on a dark background so it can be read on any picture.
+/// Whatever doesn't fit in the image is cut off.
+fn draw_text(image: &mut Image, text: &str, x: u32, y: u32, scale: u32, color: [u8; 3]) {
+    let (width, height) = (image.width(), image.height());
+    let mut set = |px: u32, py: u32, pixel: &dyn Fn([u8; 4]) -> [u8; 4]| {
+        if px < width && py < height {
+            let index = ((py * width + px) * 4) as usize;
+            let rgba = &mut image.rgba_mut()[index..index + 4];
+            rgba.copy_from_slice(&pixel([rgba[0], rgba[1], rgba[2], rgba[3]]));
+        }
+    };
+
+    // the background reaches one pixel (at the scale of the text) around the text
+    let left = x.saturating_sub(scale);
+    let right = x + text_width(text, scale);
+    let top = y.saturating_sub(scale);
+    let bottom = y + CHAR_HEIGHT * scale;
+    for py in top..bottom {
+        for px in left..right {
+            set(px, py, &|[r, g, b, a]| [r / 3, g / 3, b / 3, a]);
+        }
+    }
+
+    let [r, g, b] = color;
+    for (index, c) in text.chars().enumerate() {
+        let glyph = glyph(c);
+        let glyph_x = x + index as u32 * CHAR_WIDTH * scale;
+
+        for (row, bits) in glyph.iter().enumerate() {
+            for column in 0..5 {
+                if bits & (0x10 >> column) == 0 {
+                    continue;
+                }
+                for dy in 0..scale {
+                    for dx in 0..scale {
+                        let px = glyph_x + column * scale + dx;
+                        let py = y + row as u32 * scale + dy;
+                        set(px, py, &|_| [r, g, b, 0xff]);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The built-in font: 5 by 7 pixels, one byte per row, where bit 4 is the leftmost pixel.
+/// Lowercase letters are shown as uppercase, and characters that aren't in the font as `?`.
+fn glyph(c: char) -> [u8; 7] {
+    match c.to_ascii_uppercase() {
+        ' ' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+        '0' => [0x0e, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0e],
+        '1' => [0x04, 0x0c, 0x04, 0x04, 0x04, 0x04, 0x0e],
+        '2' => [0x0e, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1f],
+        '3' => [0x1f, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0e],
+        '4' => [0x02, 0x06, 0x0a, 0x12, 0x1f, 0x02, 0x02],
+        '5' => [0x1f, 0x10, 0x1e, 0x01, 0x01, 0x11, 0x0e],
+        '6' => [0x06, 0x08, 0x10, 0x1e, 0x11, 0x11, 0x0e],
+        '7' => [0x1f, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08],
+        '8' => [0x0e, 0x11, 0x11, 0x0e, 0x11, 0x11, 0x0e],
+        '9' => [0x0e, 0x11, 0x11, 0x0f, 0x01, 0x02, 0x0c],
+        'A' => [0x0e, 0x11, 0x11, 0x11, 0x1f, 0x11, 0x11],
+        'B' => [0x1e, 0x11, 0x11, 0x1e, 0x11, 0x11, 0x1e],
+        'C' => [0x0e, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0e],
+        'D' => [0x1c, 0x12, 0x11, 0x11, 0x11, 0x12, 0x1c],
+        'E' => [0x1f, 0x10, 0x10, 0x1e, 0x10, 0x10, 0x1f],
+        'F' => [0x1f, 0x10, 0x10, 0x1e, 0x10, 0x10, 0x10],
+        'G' => [0x0e, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0f],
+        'H' => [0x11, 0x11, 0x11, 0x1f, 0x11, 0x11, 0x11],
+        'I' => [0x0e, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0e],
+        'J' => [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0c],
+        'K' => [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11],
+        'L' => [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1f],
+        'M' => [0x11, 0x1b, 0x15, 0x15, 0x11, 0x11, 0x11],
+        'N' => [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11],
+        'O' => [0x0e, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0e],
+        'P' => [0x1e, 0x11, 0x11, 0x1e, 0x10, 0x10, 0x10],
+        'Q' => [0x0e, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0d],
+        'R' => [0x1e, 0x11, 0x11, 0x1e, 0x14, 0x12, 0x11],
+        'S' => [0x0f, 0x10, 0x10, 0x0e, 0x01, 0x01, 0x1e],
+        'T' => [0x1f, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04],
+        'U' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0e],
+        'V' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x0a, 0x04],
+        'W' => [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0a],
+        'X' => [0x11, 0x11, 0x0a, 0x04, 0x0a, 0x11, 0x11],
+        'Y' => [0x11, 0x11, 0x11, 0x0a, 0x04, 0x04, 0x04],
+        'Z' => [0x1f, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1f],
+        '.' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x0c, 0x0c],
+        ',' => [0x00, 0x00, 0x00, 0x00, 0x0c, 0x04, 0x08],
+        ':' => [0x00, 0x0c, 0x0c, 0x00, 0x0c, 0x0c, 0x00],
+        ';' => [0x00, 0x0c, 0x0c, 0x00, 0x0c, 0x04, 0x08],
+        '!' => [0x04, 0x04, 0x04, 0x04, 0x04, 0x00, 0x04],
+        '-' => [0x00, 0x00, 0x00, 0x1f, 0x00, 0x00, 0x00],
+        '+' => [0x00, 0x04, 0x04, 0x1f, 0x04, 0x04, 0x00],
+        '=' => [0x00, 0x00, 0x1f, 0x00, 0x1f, 0x00, 0x00],
+        '_' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x1f],
+        '/' => [0x00, 0x01, 0x02, 0x04, 0x08, 0x10, 0x00],
+        '%' => [0x18, 0x19, 0x02, 0x04, 0x08, 0x13, 0x03],
+        '#' => [0x0a, 0x0a, 0x1f, 0x0a, 0x1f, 0x0a, 0x0a],
+        '*' => [0x00, 0x04, 0x15, 0x0e, 0x15, 0x04, 0x00],
+        '(' => [0x02, 0x04, 0x08, 0x08, 0x08, 0x04, 0x02],
+        ')' => [0x08, 0x04, 0x02, 0x02, 0x02, 0x04, 0x08],
+        '[' => [0x0e, 0x08, 0x08, 0x08, 0x08, 0x08, 0x0e],
+        ']' => [0x0e, 0x02, 0x02, 0x02, 0x02, 0x02, 0x0e],
+        '<' => [0x02, 0x04, 0x08, 0x10, 0x08, 0x04, 0x02],
+        '>' => [0x08, 0x04, 0x02, 0x01, 0x02, 0x04, 0x08],
+        '\'' => [0x0c, 0x04, 0x08, 0x00, 0x00, 0x00, 0x00],
+        '"' => [0x0a, 0x0a, 0x0a, 0x00, 0x00, 0x00, 0x00],
+        _ => [0x0e, 0x11, 0x01, 0x02, 0x04, 0x00, 0x04],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ppu::colors::Palette;
+    use crate::ppu::frame::Frame;
+    use crate::recording::{Recorder, VideoFormat};
+    use crate::screenshot::ScreenshotSettings;
+    use crate::settings::Settings;
+    use std::io;
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+
+    /// A grey picture, so both the text and its dark background show up
+    fn grey() -> Image {
+        Image::from_rgba(256, 240, vec![0x80; 256 * 240 * 4])
+    }
+
+    /// Draws `osd` over a grey picture, and returns the left, top, right and bottom
+    /// of the pixels that changed
+    fn drawn_area(osd: &Osd) -> Option<(u32, u32, u32, u32)> {
+        let mut image = grey();
+        osd.draw(&mut image);
+        let before = grey();
+
+        let mut area: Option<(u32, u32, u32, u32)> = None;
+        for y in 0..image.height() {
+            for x in 0..image.width() {
+                if image.get(x, y) != before.get(x, y) {
+                    let (left, top, right, bottom) = area.unwrap_or((x, y, x, y));
+                    area = Some((left.min(x), top.min(y), right.max(x), bottom.max(y)));
+                }
+            }
+        }
+        area
+    }
+
+    #[test]
+    fn nothing_is_drawn_without_text() {
+        let osd = Osd::new(OsdSettings::default());
+        assert!(!osd.is_visible());
+        assert_eq!(drawn_area(&osd), None);
+    }
+
+    #[test]
+    fn messages_are_drawn_in_the_bottom_left() {
+        let mut osd = Osd::new(OsdSettings::default());
+        osd.show_message("Hello");
+        osd.frame_completed();
+
+        let (left, top, right, bottom) = drawn_area(&osd).expect("the message is drawn");
+        assert!(left < 8 && right < 128, "{left} to {right}");
+        assert!(top > 200 && bottom < 240, "{top} to {bottom}");
+    }
+
+    #[test]
+    fn messages_are_not_drawn_when_disabled() {
+        let mut osd = Osd::new(OsdSettings {
+            enabled: false,
+            ..OsdSettings::default()
+        });
+        osd.show_message("Hello");
+        assert_eq!(drawn_area(&osd), None);
+    }
+
+    #[test]
+    fn only_the_newest_messages_are_kept() {
+        let mut osd = Osd::new(OsdSettings::default());
+        for index in 0..MAX_MESSAGES + 1 {
+            osd.show_message(format!("message {index}"));
+        }
+        // the same message again only keeps it around for longer
+        osd.show_message(format!("message {MAX_MESSAGES}"));
+
+        let texts = osd.messages.iter().map(|(text, _)| text.as_str());
+        assert_eq!(
+            texts.collect::<Vec<_>>(),
+            ["message 1", "message 2", "message 3", "message 4"]
+        );
+    }
+
+    #[test]
+    fn status_is_drawn_in_the_top_left() {
+        let mut osd = Osd::new(OsdSettings::default());
+        osd.recording = true;
+        let (left, top, right, bottom) = drawn_area(&osd).expect("REC is drawn");
+        assert!(
+            left < 8 && top < 8 && bottom < 16,
+            "{left}, {top} to {bottom}"
+        );
+
+        // REC GIF is wider than REC alone
+        osd.capturing_gif = true;
+        let (_, _, both_right, _) = drawn_area(&osd).expect("REC GIF is drawn");
+        assert!(both_right > right, "{both_right} after {right}");
+
+        osd.recording = false;
+        let (_, _, gif_right, _) = drawn_area(&osd).expect("GIF is drawn");
+        assert!(gif_right < both_right, "{gif_right} before {both_right}");
+    }
+
+    #[test]
+    fn frame_rate_is_drawn_in_the_top_right() {
+        let mut osd = Osd::new(OsdSettings {
+            show_fps: true,
+            ..OsdSettings::default()
+        });
+        // nothing to show before a second passed
+        osd.frame_completed();
+        assert_eq!(drawn_area(&osd), None);
+
+        let fps = osd.fps.as_mut().expect("the counter is shown");
+        fps.since = Instant::now() - Duration::from_secs(1);
+        osd.frame_completed();
+        let text = &osd.fps.as_ref().expect("the counter is shown").text;
+        assert!(text.contains(" FPS ") && text.ends_with('%'), "{text}");
+
+        let (left, top, right, bottom) = drawn_area(&osd).expect("the counter is drawn");
+        assert!(left > 128 && right > 240, "{left} to {right}");
+        assert!(top < 8 && bottom < 16, "{top} to {bottom}");
+    }
+
+    /// A writer that can still be read after it's given to a recorder
+    #[derive(Clone, Default)]
+    struct SharedWriter(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Records one frame with `osd` showing, and returns the raw pixels
+    fn record(settings: &Settings, osd: &Osd) -> Vec<u8> {
+        let writer = SharedWriter::default();
+        let mut recorder = Recorder::new(Box::new(writer.clone()), settings).unwrap();
+        recorder
+            .write_frame(&Frame::default(), &Palette::default(), osd)
+            .unwrap();
+        recorder.finish().unwrap();
+        let bytes = writer.0.lock().unwrap().clone();
+        bytes
+    }
+
+    #[test]
+    fn exports_leave_it_out_by_default() {
+        assert!(!ScreenshotSettings::default().include_osd);
+
+        let mut settings = Settings::default();
+        settings.recordings.format = VideoFormat::RawRgb;
+        let mut osd = Osd::new(settings.osd);
+        let without = record(&settings, &osd);
+
+        osd.recording = true;
+        osd.show_message("Recording started");
+        assert_eq!(record(&settings, &osd), without);
+
+        settings.recordings.include_osd = true;
+        assert_ne!(record(&settings, &osd), without);
+    }
+}
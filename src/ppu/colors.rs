@@ -1,5 +1,187 @@
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+use std::{fmt, fs, io};
+
 pub type Color = (u8, u8, u8);
 
+/// How much the color emphasis bits darken the channels that aren't emphasized,
+/// for palettes that don't specify colors for every emphasis combination.
+const EMPHASIS_ATTENUATION: f64 = 0.746;
+
+/// The palettes that come with this library. See [`Palette::builtin`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
+pub enum BuiltinPalette {
+    /// The palette this library has always used. Like before palettes could be chosen,
+    /// the color emphasis bits turn the emphasized channels fully on.
+    #[default]
+    Default,
+    /// The 2C02 palette from the nesdev wiki (<https://www.nesdev.org/wiki/PPU_palettes>)
+    Ppu2C02,
+    /// FirebrandX' "Smooth" palette (<https://www.firebrandx.com/nespalette.html>)
+    FirebrandXSmooth,
+}
+
+impl BuiltinPalette {
+    /// All builtin palettes, for example to cycle through them.
+    pub const ALL: [BuiltinPalette; 3] = [
+        BuiltinPalette::Default,
+        BuiltinPalette::Ppu2C02,
+        BuiltinPalette::FirebrandXSmooth,
+    ];
+}
+
+/// Something went wrong while loading a palette
+#[derive(Debug)]
+pub enum PaletteError {
+    /// The palette file could not be read
+    Io(io::Error),
+    /// A `.pal` file has to be either 192 bytes (64 colors) or 1536 bytes (64 colors for each of the
+    /// 8 combinations of emphasis bits)
+    InvalidSize(usize),
+}
+
+impl Display for PaletteError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            PaletteError::Io(e) => write!(f, "failed to read palette: {e}"),
+            PaletteError::InvalidSize(size) => write!(
+                f,
+                "invalid palette size of {size} bytes (expected 192 or 1536 bytes)"
+            ),
+        }
+    }
+}
+
+impl Error for PaletteError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            PaletteError::Io(e) => Some(e),
+            PaletteError::InvalidSize(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for PaletteError {
+    fn from(value: io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+/// Maps the colors the PPU outputs to RGB.
+///
+/// A palette has 64 colors for each of the 8 combinations of color emphasis bits,
+/// so it can be indexed directly with the pixels of a [`Frame`](crate::Frame).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Palette {
+    colors: Box<[Color]>,
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self::builtin(BuiltinPalette::Default)
+    }
+}
+
+impl Palette {
+    /// One of the palettes that come with this library
+    pub fn builtin(palette: BuiltinPalette) -> Self {
+        match palette {
+            BuiltinPalette::Default => {
+                let colors = (0..8u8)
+                    .flat_map(|emphasis| {
+                        NES_COLOR_PALLETE
+                            .iter()
+                            .map(move |&color| saturate(color, emphasis))
+                    })
+                    .collect();
+                Self { colors }
+            }
+            BuiltinPalette::Ppu2C02 => Self::from_colors(&PPU_2C02),
+            BuiltinPalette::FirebrandXSmooth => Self::from_colors(&FIREBRANDX_SMOOTH),
+        }
+    }
+
+    /// Creates a palette from 64 colors. The colors for when emphasis bits are set
+    /// are derived by darkening the channels that aren't emphasized.
+    pub fn from_colors(colors: &[Color; 64]) -> Self {
+        let colors = (0..8u8)
+            .flat_map(|emphasis| colors.iter().map(move |&color| emphasize(color, emphasis)))
+            .collect();
+
+        Self { colors }
+    }
+
+    /// Creates a palette from all 512 colors, ordered like the pixels in a [`Frame`](crate::Frame)
+    pub(crate) fn from_all_colors(colors: Vec<Color>) -> Self {
+        assert_eq!(colors.len(), 512);
+        Self {
+            colors: colors.into_boxed_slice(),
+        }
+    }
+
+    /// Creates a palette from the contents of a `.pal` file. These contain RGB triplets, either for
+    /// just the 64 colors (192 bytes) or for the 64 colors with each of the 8 combinations of emphasis
+    /// bits (1536 bytes).
+    pub fn from_pal(bytes: &[u8]) -> Result<Self, PaletteError> {
+        let colors = bytes
+            .chunks_exact(3)
+            .map(|rgb| (rgb[0], rgb[1], rgb[2]))
+            .collect::<Vec<_>>();
+
+        match bytes.len() {
+            192 => Ok(Self::from_colors(
+                colors.as_slice().try_into().expect("64 colors"),
+            )),
+            1536 => Ok(Self::from_all_colors(colors)),
+            size => Err(PaletteError::InvalidSize(size)),
+        }
+    }
+
+    /// Loads a `.pal` file, see [`Palette::from_pal`].
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, PaletteError> {
+        Self::from_pal(&fs::read(path)?)
+    }
+
+    /// The RGB color of a pixel as stored in a [`Frame`](crate::Frame)
+    pub fn color(&self, pixel: u16) -> Color {
+        self.colors[pixel as usize & 0x1ff]
+    }
+}
+
+/// Applies emphasis bits (red, green, blue from least to most significant) to a color
+fn emphasize(color: Color, emphasis: u8) -> Color {
+    if emphasis == 0 {
+        return color;
+    }
+
+    let attenuate = |channel: u8, bit: u8| {
+        if emphasis & bit == 0 || emphasis == 0b111 {
+            (f64::from(channel) * EMPHASIS_ATTENUATION).round() as u8
+        } else {
+            channel
+        }
+    };
+
+    (
+        attenuate(color.0, 0b001),
+        attenuate(color.1, 0b010),
+        attenuate(color.2, 0b100),
+    )
+}
+
+/// Applies emphasis bits like this library always did for the default palette,
+/// by turning the emphasized channels fully on
+fn saturate(color: Color, emphasis: u8) -> Color {
+    let saturate = |channel: u8, bit: u8| if emphasis & bit != 0 { 0xff } else { channel };
+
+    (
+        saturate(color.0, 0b001),
+        saturate(color.1, 0b010),
+        saturate(color.2, 0b100),
+    )
+}
+
 #[allow(clippy::zero_prefixed_literal)]
 pub static NES_COLOR_PALLETE: [Color; 64] = [
     (128, 128, 128),
@@ -67,3 +249,188 @@ pub static NES_COLOR_PALLETE: [Color; 64] = [
     (017, 017, 017),
     (017, 017, 017),
 ];
+
+#[allow(clippy::zero_prefixed_literal)]
+static PPU_2C02: [Color; 64] = [
+    (102, 102, 102),
+    (000, 042, 136),
+    (020, 018, 167),
+    (059, 000, 164),
+    (092, 000, 126),
+    (110, 000, 064),
+    (108, 006, 000),
+    (086, 029, 000),
+    (051, 053, 000),
+    (011, 072, 000),
+    (000, 082, 000),
+    (000, 079, 008),
+    (000, 064, 077),
+    (000, 000, 000),
+    (000, 000, 000),
+    (000, 000, 000),
+    (173, 173, 173),
+    (021, 095, 217),
+    (066, 064, 255),
+    (117, 039, 254),
+    (160, 026, 204),
+    (183, 030, 123),
+    (181, 049, 032),
+    (153, 078, 000),
+    (107, 109, 000),
+    (056, 135, 000),
+    (012, 147, 000),
+    (000, 143, 050),
+    (000, 124, 141),
+    (000, 000, 000),
+    (000, 000, 000),
+    (000, 000, 000),
+    (255, 254, 255),
+    (100, 176, 255),
+    (146, 144, 255),
+    (198, 118, 255),
+    (243, 106, 255),
+    (254, 110, 204),
+    (254, 129, 112),
+    (234, 158, 034),
+    (188, 190, 000),
+    (136, 216, 000),
+    (092, 228, 048),
+    (069, 224, 130),
+    (072, 205, 222),
+    (079, 079, 079),
+    (000, 000, 000),
+    (000, 000, 000),
+    (255, 254, 255),
+    (192, 223, 255),
+    (211, 210, 255),
+    (232, 200, 255),
+    (251, 194, 255),
+    (254, 196, 234),
+    (254, 204, 197),
+    (247, 216, 165),
+    (228, 229, 148),
+    (207, 239, 150),
+    (189, 244, 171),
+    (179, 243, 204),
+    (181, 235, 242),
+    (184, 184, 184),
+    (000, 000, 000),
+    (000, 000, 000),
+];
+
+#[allow(clippy::zero_prefixed_literal)]
+static FIREBRANDX_SMOOTH: [Color; 64] = [
+    (106, 109, 106),
+    (000, 019, 128),
+    (030, 000, 138),
+    (057, 000, 122),
+    (085, 000, 086),
+    (090, 000, 024),
+    (079, 016, 000),
+    (061, 028, 000),
+    (037, 050, 000),
+    (000, 061, 000),
+    (000, 064, 000),
+    (000, 057, 036),
+    (000, 046, 085),
+    (000, 000, 000),
+    (000, 000, 000),
+    (000, 000, 000),
+    (185, 188, 185),
+    (024, 080, 199),
+    (075, 048, 227),
+    (115, 034, 214),
+    (149, 031, 169),
+    (157, 040, 092),
+    (152, 055, 000),
+    (127, 076, 000),
+    (094, 100, 000),
+    (034, 119, 000),
+    (002, 126, 002),
+    (000, 118, 069),
+    (000, 110, 138),
+    (000, 000, 000),
+    (000, 000, 000),
+    (000, 000, 000),
+    (255, 255, 255),
+    (104, 166, 255),
+    (140, 156, 255),
+    (181, 134, 255),
+    (217, 117, 253),
+    (227, 119, 185),
+    (229, 141, 104),
+    (212, 157, 041),
+    (179, 175, 012),
+    (123, 194, 017),
+    (085, 202, 071),
+    (070, 203, 129),
+    (071, 193, 197),
+    (074, 077, 074),
+    (000, 000, 000),
+    (000, 000, 000),
+    (255, 255, 255),
+    (204, 234, 255),
+    (221, 222, 255),
+    (236, 218, 255),
+    (248, 215, 254),
+    (252, 214, 245),
+    (253, 219, 207),
+    (249, 231, 181),
+    (241, 240, 170),
+    (218, 250, 169),
+    (201, 255, 188),
+    (195, 251, 215),
+    (196, 246, 246),
+    (190, 193, 190),
+    (000, 000, 000),
+    (000, 000, 000),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_palette_saturates_emphasized_channels() {
+        let palette = Palette::default();
+        let (r, g, b) = NES_COLOR_PALLETE[0x01];
+
+        assert_eq!(palette.color(0x01), (r, g, b));
+        // red emphasis
+        assert_eq!(palette.color(0x41), (0xff, g, b));
+        // green and blue emphasis
+        assert_eq!(palette.color(0x181), (r, 0xff, 0xff));
+    }
+
+    #[test]
+    fn pal_files_with_64_colors() {
+        let bytes = (0..192).map(|byte| byte as u8).collect::<Vec<_>>();
+        let palette = Palette::from_pal(&bytes).unwrap();
+
+        assert_eq!(palette.color(0x00), (0, 1, 2));
+        assert_eq!(palette.color(0x3f), (189, 190, 191));
+        // emphasis is derived
+        assert_eq!(palette.color(0x7f), emphasize((189, 190, 191), 0b001));
+    }
+
+    #[test]
+    fn pal_files_with_512_colors() {
+        let bytes = (0..1536).map(|byte| (byte / 3) as u8).collect::<Vec<_>>();
+        let palette = Palette::from_pal(&bytes).unwrap();
+
+        assert_eq!(palette.color(0x00), (0, 0, 0));
+        assert_eq!(palette.color(0x3f), (0x3f, 0x3f, 0x3f));
+        // emphasis comes from the file
+        assert_eq!(palette.color(0x41), (0x41, 0x41, 0x41));
+    }
+
+    #[test]
+    fn pal_files_of_other_sizes_are_rejected() {
+        for size in [0, 3, 191, 193, 1535, 1537] {
+            assert!(matches!(
+                Palette::from_pal(&vec![0; size]),
+                Err(PaletteError::InvalidSize(s)) if s == size
+            ));
+        }
+    }
+}
@@ -0,0 +1,37 @@
+/// What the PPU is fetching from memory while it renders.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum FetchKind {
+    /// A tile number from a nametable (0x2000-0x2fff)
+    Nametable,
+    /// A byte from an attribute table (the last 64 bytes of a nametable)
+    Attribute,
+    /// A byte of a background tile's pattern (0x0000-0x1fff)
+    BackgroundPattern,
+    /// A byte of a sprite's pattern (0x0000-0x1fff)
+    SpritePattern,
+}
+
+/// Describes a single memory fetch done by the PPU while rendering.
+///
+/// Some mappers (most notably MMC5) need to know what the PPU is fetching, and not
+/// just from where, to decide what byte should be returned. This is passed to
+/// [`Cpu::ppu_override_fetch`](crate::Cpu::ppu_override_fetch) for every fetch.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PpuFetch {
+    pub kind: FetchKind,
+    /// The address on the PPU bus the fetch is done from
+    pub address: u16,
+    /// For background fetches, the column of the tile (0-33) counted from the left edge of the
+    /// screen. Note that when scrolling, 33 tiles may be (partly) visible on one line. Like on the
+    /// real hardware, the tiles right of those are fetched too, up to 34 per line.
+    ///
+    /// For sprite fetches, the slot (0-7) of the sprite in the sprites found for this line.
+    /// With [`Ppu::set_unlimited_sprites`](crate::Ppu::set_unlimited_sprites), the sprites over
+    /// the limit of 8 get slots 8-63. The real hardware never fetches those, so mappers shouldn't
+    /// change their state for them.
+    pub column: u8,
+    /// The scanline (0-239) that is being rendered
+    pub scanline: u8,
+    /// Whether 8x16 sprites are enabled in the controller register
+    pub large_sprites: bool,
+}
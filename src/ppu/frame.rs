@@ -0,0 +1,176 @@
+use crate::ppu::colors::Palette;
+use crate::screenshot::save_png;
+use crate::settings::Overscan;
+use crate::{HEIGHT, WIDTH};
+use std::io;
+use std::path::Path;
+
+/// One picture drawn by the PPU, as the colors it actually outputs rather than RGB.
+///
+/// Every pixel is a 9-bit value. The lower 6 bits are the index of the color in the NES palette
+/// (0x00-0x3f), the upper 3 bits are the color emphasis bits of the mask register
+/// at the time the pixel was drawn (bit 6: red, bit 7: green, bit 8: blue).
+///
+/// Pixels are stored row by row, [`WIDTH`] pixels per row and [`HEIGHT`] rows.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Frame {
+    pixels: Box<[u16]>,
+}
+
+impl Default for Frame {
+    fn default() -> Self {
+        Self {
+            pixels: vec![0; WIDTH as usize * HEIGHT as usize].into_boxed_slice(),
+        }
+    }
+}
+
+impl Frame {
+    /// All pixels of the frame, row by row.
+    pub fn pixels(&self) -> &[u16] {
+        &self.pixels
+    }
+
+    /// Gets the pixel at `x`, `y`.
+    ///
+    /// # Panics
+    /// When the coordinates are outside the frame
+    pub fn get(&self, x: usize, y: usize) -> u16 {
+        assert!(x < WIDTH as usize && y < HEIGHT as usize);
+        self.pixels[y * WIDTH as usize + x]
+    }
+
+    /// A fast 64-bit hash of all pixels (FNV-1a), to check whether two frames are the same
+    /// without keeping them around. Since it's calculated from the pixels rather than RGB, it
+    /// doesn't depend on the palette. The hash of a frame is the same on every platform and
+    /// every version of this crate, so hashes can be stored and compared later, see
+    /// [`FrameHashLog`](crate::FrameHashLog).
+    pub fn hash(&self) -> u64 {
+        const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+        const PRIME: u64 = 0x0000_0100_0000_01b3;
+
+        self.pixels
+            .iter()
+            .flat_map(|pixel| pixel.to_le_bytes())
+            .fold(OFFSET_BASIS, |hash, byte| {
+                (hash ^ byte as u64).wrapping_mul(PRIME)
+            })
+    }
+
+    pub(crate) fn set(&mut self, x: usize, y: usize, pixel: u16) {
+        self.pixels[y * WIDTH as usize + x] = pixel;
+    }
+
+    /// Converts the frame to RGBA using `palette`, 4 bytes per pixel, row by row.
+    pub fn to_rgba(&self, palette: &Palette) -> Vec<u8> {
+        self.to_rgba_cropped(palette, Overscan::NONE)
+    }
+
+    /// Like [`Frame::to_rgba`], but cuts off the edges given by `overscan`.
+    /// The result is [`Overscan::width`] pixels wide and [`Overscan::height`] pixels high.
+    pub fn to_rgba_cropped(&self, palette: &Palette, overscan: Overscan) -> Vec<u8> {
+        let mut rgba = vec![0; (overscan.width() * overscan.height() * 4) as usize];
+        self.write_rgba(palette, overscan, &mut rgba);
+        rgba
+    }
+
+    /// Saves the frame as a PNG file, with the colors of `palette` and the edges
+    /// given by `overscan` cut off.
+    ///
+    /// Without the `png` feature, this fails with [`io::ErrorKind::Unsupported`].
+    pub fn save_png(
+        &self,
+        path: impl AsRef<Path>,
+        palette: &Palette,
+        overscan: Overscan,
+    ) -> io::Result<()> {
+        save_png(
+            path.as_ref(),
+            &self.to_rgba_cropped(palette, overscan),
+            overscan.width(),
+            overscan.height(),
+        )
+    }
+
+    pub(crate) fn write_rgba(&self, palette: &Palette, overscan: Overscan, rgba: &mut [u8]) {
+        let overscan = overscan.clamped();
+        let width = overscan.width() as usize;
+        let rows = self
+            .pixels
+            .chunks_exact(WIDTH as usize)
+            .skip(overscan.top as usize)
+            .take(overscan.height() as usize);
+
+        for (row, out_row) in rows.zip(rgba.chunks_exact_mut(width * 4)) {
+            let row = &row[overscan.left as usize..overscan.left as usize + width];
+
+            for (pixel, out) in row.iter().zip(out_row.chunks_exact_mut(4)) {
+                let color = palette.color(*pixel);
+                out.copy_from_slice(&[color.0, color.1, color.2, 0xff]);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hash_stays_the_same() {
+        let mut frame = Frame::default();
+        assert_eq!(frame.hash(), 0x7114_b985_2317_a325);
+
+        frame.set(0, 0, 0x21);
+        frame.set(WIDTH as usize - 1, HEIGHT as usize - 1, 0x1ff);
+        assert_eq!(frame.hash(), 0xa79a_07fd_5ade_ef20);
+    }
+
+    /// The RGBA of the pixel at `x`, `y` in an image that's `width` wide
+    fn rgba_at(rgba: &[u8], width: u32, x: u32, y: u32) -> &[u8] {
+        let start = ((y * width + x) * 4) as usize;
+        &rgba[start..start + 4]
+    }
+
+    #[test]
+    fn cropping_cuts_off_the_edges() {
+        let palette = Palette::default();
+        let color = |pixel: u16| {
+            let (r, g, b) = palette.color(pixel);
+            [r, g, b, 0xff]
+        };
+        let mut frame = Frame::default();
+        frame.set(0, 7, 0x30);
+        frame.set(0, 8, 0x21);
+        frame.set(255, 231, 0x16);
+        frame.set(255, 232, 0x30);
+
+        let rgba = frame.to_rgba_cropped(&palette, Overscan::NTSC);
+        assert_eq!(rgba.len(), 256 * 224 * 4);
+        assert_eq!(rgba_at(&rgba, 256, 0, 0), color(0x21));
+        assert_eq!(rgba_at(&rgba, 256, 255, 223), color(0x16));
+
+        let sides = Overscan {
+            left: 8,
+            right: 16,
+            ..Overscan::NONE
+        };
+        let rgba = frame.to_rgba_cropped(&palette, sides);
+        assert_eq!(rgba.len(), 232 * 240 * 4);
+        assert_eq!(rgba_at(&rgba, 232, 0, 0), color(0));
+        assert_eq!(rgba_at(&rgba, 232, 0, 8), color(0));
+        frame.set(8, 8, 0x21);
+        let rgba = frame.to_rgba_cropped(&palette, sides);
+        assert_eq!(rgba_at(&rgba, 232, 0, 8), color(0x21));
+
+        // cropping everything leaves the bottom right pixel
+        let everything = Overscan {
+            top: 300,
+            bottom: 300,
+            left: 300,
+            right: 300,
+        };
+        frame.set(255, 239, 0x16);
+        assert_eq!(frame.to_rgba_cropped(&palette, everything), color(0x16));
+    }
+}
@@ -1,9 +1,11 @@
 use crate::cpu::Cpu;
-use crate::ppu::colors::{Color, NES_COLOR_PALLETE};
+use crate::ppu::colors::Palette;
+use crate::ppu::fetch::{FetchKind, PpuFetch};
+use crate::ppu::frame::Frame;
 use crate::ppu::registers::{
     AddrRegister, ControllerRegister, MaskRegister, OamAddrRegister, ScrollRegister, StatusRegister,
 };
-use crate::screen::ScreenWriter;
+use crate::sink::FrameSink;
 use crate::Buttons;
 use crate::{Mirroring, HEIGHT, WIDTH};
 use itertools::Itertools;
@@ -11,9 +13,21 @@ use registers::PpuRegister;
 use std::default::Default;
 
 pub mod colors;
+pub mod fetch;
+pub mod frame;
 pub mod mirroring;
+pub mod ntsc;
 pub mod registers;
 
+/// A background tile as fetched by the PPU for the line it's drawing
+#[derive(Copy, Clone)]
+struct BackgroundTile {
+    column: usize,
+    palette: [u8; 4],
+    byte_upper: u8,
+    byte_lower: u8,
+}
+
 /// Emulating an NTSC PPU chip
 pub struct Ppu {
     /// how many lines we've drawn. After 240, an NMI is given to the cpu
@@ -45,6 +59,25 @@ pub struct Ppu {
 
     oam: [u8; 256],
     secondary_oam: [u8; 32],
+    /// The sprites on the current line over the limit of 8 that fit in secondary oam.
+    /// Only used when [`Ppu::set_unlimited_sprites`] is enabled.
+    extra_sprites: Vec<[u8; 4]>,
+    unlimited_sprites: bool,
+    /// the pattern bytes of the sprites in secondary oam (and after those, the extra sprites),
+    /// fetched at the start of every line
+    sprite_patterns: [(u8, u8); 64],
+
+    /// the background tile that's currently being drawn, fetched when the first pixel of it is drawn
+    background_tile: Option<BackgroundTile>,
+    /// the last background pixel drawn and whether it was opaque
+    background_pixel: (u16, bool),
+
+    /// the last frame that was completely drawn
+    frame: Frame,
+    /// the frame that's currently being drawn
+    next_frame: Frame,
+    /// used to turn frames into RGB when they're sent to the screen
+    palette: Palette,
 
     bus: u8,
     // when reading from the ppu, everything is always lagging behind.
@@ -78,6 +111,14 @@ impl Ppu {
             vram: [0; 4096],
             oam: [0; 256],
             secondary_oam: [0xff; 32],
+            extra_sprites: Vec::with_capacity(56),
+            unlimited_sprites: false,
+            sprite_patterns: [(0, 0); 64],
+            background_tile: None,
+            background_pixel: (0, false),
+            frame: Frame::default(),
+            next_frame: Frame::default(),
+            palette: Palette::default(),
             bus: 0,
             data_buffer: 0,
             mirroring,
@@ -129,6 +170,34 @@ impl Ppu {
         }
     }
 
+    /// The real NES can only draw 8 sprites on one line, which makes sprites flicker in many games.
+    /// When enabled, all sprites on a line (up to 64) are drawn.
+    ///
+    /// Everything the cpu or cartridge can observe stays the same: the sprite overflow flag is still set
+    /// and only the first 8 sprites are fetched through [`Cpu::ppu_fetch_chr_rom`]. The patterns of the other
+    /// sprites go through [`Cpu::ppu_override_fetch`] (with slots 8 and up), so mappers that bank
+    /// character memory per sprite can still give the right bytes. This is disabled by default.
+    pub fn set_unlimited_sprites(&mut self, unlimited: bool) {
+        self.unlimited_sprites = unlimited;
+    }
+
+    /// The last frame that was completely drawn, as the colors the PPU outputs (see [`Frame`]).
+    /// This is updated at the start of every vblank.
+    pub fn frame(&self) -> &Frame {
+        &self.frame
+    }
+
+    /// Changes the palette used to turn frames into RGB colors on the screen. This can be
+    /// changed at any time and takes effect from the next frame.
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+    }
+
+    /// The palette used to turn frames into RGB colors on the screen
+    pub fn palette(&self) -> &Palette {
+        &self.palette
+    }
+
     /// Gets what buttons are currently pressed by the user/player.
     pub fn get_joypad_state(&self) -> Buttons {
         self.buttons
@@ -265,7 +334,7 @@ impl Ppu {
         self.oam = data_to_write;
     }
 
-    fn update_scanline(&mut self, cpu: &mut impl Cpu, screen: &mut ScreenWriter) {
+    fn update_scanline(&mut self, cpu: &mut impl Cpu, sink: &mut impl FrameSink) {
         self.line_progress += 1;
 
         if self.line_progress >= 257 && self.line_progress <= 320 {
@@ -274,6 +343,10 @@ impl Ppu {
 
         // Update x scroll every line
         if self.line_progress == 256 {
+            if self.scanline < 240 {
+                sink.scanline_completed(self.scanline, &self.next_frame, &self.palette);
+            }
+
             self.controller_register.nametable_address &= !0x0400;
             self.controller_register.nametable_address |= self.addr_new_nametable & 0x400;
             self.scroll.x = self.scroll_access.x;
@@ -291,8 +364,15 @@ impl Ppu {
 
             self.scanline += 1;
 
+            // wrap around before reading oam, so the sprites are found for the line that's drawn next
+            if self.scanline > 261 {
+                self.scanline = 0;
+                self.end_vblank();
+            }
+
             // read oam
             self.secondary_oam = [0xff; 32];
+            self.extra_sprites.clear();
             let mut sprite_index = 0;
             for (index, sprite) in self
                 .oam
@@ -306,12 +386,20 @@ impl Ppu {
                     && self.scanline
                         < sprite.0 as usize + self.controller_register.sprite_size.1 as usize
                 {
+                    let b2 = sprite.2 & 0b1110_0011;
+
                     if sprite_index == 8 {
                         self.status_register.sprite_overflow = true;
-                        break;
-                    }
 
-                    let b2 = sprite.2 & 0b1110_0011;
+                        if !self.unlimited_sprites {
+                            break;
+                        }
+
+                        // sprites over the limit only end up here, the hardware-visible
+                        // secondary oam still contains just the first 8.
+                        self.extra_sprites.push([sprite.0, sprite.1, b2, sprite.3]);
+                        continue;
+                    }
 
                     self.secondary_oam[sprite_index * 4] = sprite.0;
                     self.secondary_oam[sprite_index * 4 + 1] = sprite.1;
@@ -326,17 +414,18 @@ impl Ppu {
 
             // we've just passed the 240th line, vblank begins!
             if self.scanline == 241 {
-                self.start_vblank(cpu, screen);
+                self.start_vblank(cpu, sink);
             }
 
-            if self.scanline > 261 {
-                self.scanline = 0;
-                self.end_vblank();
+            // every line starts fetching background tiles from the first column again
+            self.background_tile = None;
+            if self.scanline < 240 {
+                self.fetch_sprite_patterns(cpu);
             }
         }
     }
 
-    fn start_vblank(&mut self, cpu: &mut impl Cpu, screen: &mut ScreenWriter) {
+    fn start_vblank(&mut self, cpu: &mut impl Cpu, sink: &mut impl FrameSink) {
         self.status_register.vblank_started = true;
         self.status_register.sprite_zero_hit = false;
         self.status_register.sprite_overflow = false;
@@ -345,16 +434,93 @@ impl Ppu {
             cpu.non_maskable_interrupt();
         }
 
-        screen.render_frame();
+        // the buttons first, so everything that shows the frame knows the buttons that go with it
+        if let Some(buttons) = sink.buttons() {
+            self.buttons = buttons;
+        }
+        sink.buttons_updated(self.buttons);
+
+        std::mem::swap(&mut self.frame, &mut self.next_frame);
+        sink.frame_completed(&self.frame, &self.palette);
     }
 
     fn end_vblank(&mut self) {
         self.status_register.vblank_started = false;
     }
 
-    fn get_palette(&self, tile_x: usize, tile_y: usize, attr_table: u16) -> [Color; 4] {
+    /// Does a fetch on the PPU bus while rendering. The cartridge gets the chance
+    /// to override what's returned, see [`Cpu::ppu_override_fetch`].
+    ///
+    /// With rendering turned off in the mask register, the real hardware doesn't fetch
+    /// anything. We still draw the picture, but read it without telling the cartridge,
+    /// so mappers like MMC2 don't switch banks while the screen is blanked.
+    fn fetch(&self, cpu: &mut impl Cpu, kind: FetchKind, address: u16, column: usize) -> u8 {
+        if !self.mask_register.rendering_enabled() {
+            return self.read_without_side_effects(cpu, kind, address);
+        }
+
+        let fetch = PpuFetch {
+            kind,
+            address,
+            column: column as u8,
+            scanline: self.scanline as u8,
+            large_sprites: self.controller_register.sprite_size.1 == 16,
+        };
+
+        let value = cpu.ppu_override_fetch(fetch);
+
+        match kind {
+            FetchKind::Nametable | FetchKind::Attribute => {
+                value.unwrap_or_else(|| self.vram_read_mirrored(address))
+            }
+            // the pattern is fetched even when it's overridden, so mappers that switch banks
+            // on fetches still see it
+            FetchKind::BackgroundPattern | FetchKind::SpritePattern => {
+                let fetched = cpu.ppu_fetch_chr_rom(address);
+                value.unwrap_or(fetched)
+            }
+        }
+    }
+
+    /// Reads what [`Ppu::fetch`] would, without giving the cartridge a chance to react
+    fn read_without_side_effects(&self, cpu: &impl Cpu, kind: FetchKind, address: u16) -> u8 {
+        match kind {
+            FetchKind::Nametable | FetchKind::Attribute => self.vram_read_mirrored(address),
+            FetchKind::BackgroundPattern | FetchKind::SpritePattern => {
+                cpu.ppu_read_chr_rom(address)
+            }
+        }
+    }
+
+    /// Like [`Ppu::fetch`] for the pattern of a sprite over the limit of 8, but without the
+    /// side effects of [`Cpu::ppu_fetch_chr_rom`]
+    fn fetch_extra_sprite(&self, cpu: &mut impl Cpu, address: u16, slot: usize) -> u8 {
+        if !self.mask_register.rendering_enabled() {
+            return cpu.ppu_read_chr_rom(address);
+        }
+
+        let fetch = PpuFetch {
+            kind: FetchKind::SpritePattern,
+            address,
+            column: slot as u8,
+            scanline: self.scanline as u8,
+            large_sprites: self.controller_register.sprite_size.1 == 16,
+        };
+
+        cpu.ppu_override_fetch(fetch)
+            .unwrap_or_else(|| cpu.ppu_read_chr_rom(address))
+    }
+
+    fn get_palette(
+        &self,
+        cpu: &mut impl Cpu,
+        tile_x: usize,
+        tile_y: usize,
+        attr_table: u16,
+        column: usize,
+    ) -> [u8; 4] {
         let index = tile_y / 4 * 8 + tile_x / 4;
-        let attr = self.vram_read_mirrored(attr_table + index as u16);
+        let attr = self.fetch(cpu, FetchKind::Attribute, attr_table + index as u16, column);
 
         let palette_index = match (tile_x % 4 / 2, tile_y % 4 / 2) {
             (0, 0) => attr & 0b11,
@@ -369,53 +535,59 @@ impl Ppu {
         let mask = if self.mask_register.greyscale {
             0x30
         } else {
-            0xff
+            0x3f
         };
 
         [
-            NES_COLOR_PALLETE[(self.palette_table[0] & mask) as usize],
-            NES_COLOR_PALLETE[(self.palette_table[start] & mask) as usize],
-            NES_COLOR_PALLETE[(self.palette_table[start + 1] & mask) as usize],
-            NES_COLOR_PALLETE[(self.palette_table[start + 2] & mask) as usize],
+            self.palette_table[0] & mask,
+            self.palette_table[start] & mask,
+            self.palette_table[start + 1] & mask,
+            self.palette_table[start + 2] & mask,
         ]
     }
 
-    fn get_sprite_palette(&self, palette_index: u8) -> [Color; 4] {
+    fn get_sprite_palette(&self, palette_index: u8) -> [u8; 4] {
         let start = 0x11 + (palette_index * 4) as usize;
 
         let mask = if self.mask_register.greyscale {
             0x30
         } else {
-            0xff
+            0x3f
         };
 
         [
-            NES_COLOR_PALLETE[0],
-            NES_COLOR_PALLETE[(self.palette_table[start] & mask) as usize],
-            NES_COLOR_PALLETE[(self.palette_table[start + 1] & mask) as usize],
-            NES_COLOR_PALLETE[(self.palette_table[start + 2] & mask) as usize],
+            0,
+            self.palette_table[start] & mask,
+            self.palette_table[start + 1] & mask,
+            self.palette_table[start + 2] & mask,
         ]
     }
 
-    // returns true if a tile was drawn on this pixel
-    #[allow(clippy::too_many_arguments)]
-    fn draw_pixel(
+    /// Puts a pixel in the frame that's being drawn, with the current color emphasis
+    fn put_pixel(&mut self, x: usize, y: usize, color: u8) -> u16 {
+        let pixel = u16::from(color) | u16::from(self.mask_register.emphasis()) << 6;
+        self.next_frame.set(x, y, pixel);
+        pixel
+    }
+
+    /// Fetches everything needed to draw one background tile. Like the real hardware,
+    /// this fetches the nametable byte, the attribute byte and then the low and high byte
+    /// of the pattern, exactly once for every tile on a line.
+    fn fetch_background_tile(
         &self,
         cpu: &mut impl Cpu,
-        screen: &mut ScreenWriter,
-        x: usize,
-        y: usize,
+        scrolled_x: usize,
+        scrolled_y: usize,
         name_table_address: u16,
-    ) -> bool {
-        let scroll_x = self.scroll.x;
-        let scroll_y = self.scroll.y;
-        let scrolled_x = (x as isize + scroll_x as isize).rem_euclid(WIDTH as isize * 2) as usize;
-        let scrolled_y = (y as isize + scroll_y as isize).rem_euclid(HEIGHT as isize * 2) as usize;
-
+        column: usize,
+    ) -> BackgroundTile {
         let name_table_idx = (scrolled_x / WIDTH as usize) + (scrolled_y / HEIGHT as usize) * 2;
         assert!(name_table_idx < 4);
 
-        let tile_nametable_address = name_table_address + (name_table_idx * 0x400) as u16;
+        // like the nametable bits of the hardware's address register, scrolling past the edge
+        // of a nametable flips to the next one instead of adding to the selected one
+        let selected_idx = (name_table_address >> 10) & 0b11;
+        let tile_nametable_address = 0x2000 | (selected_idx ^ name_table_idx as u16) << 10;
         let attr_table = tile_nametable_address + 0x3c0;
 
         let tile_x = (scrolled_x / 8) % 32;
@@ -423,41 +595,103 @@ impl Ppu {
 
         let off = tile_x + tile_y * 32;
 
-        let tile_num = self.vram_read_mirrored(tile_nametable_address + off as u16) as usize;
+        let tile_num = self.fetch(
+            cpu,
+            FetchKind::Nametable,
+            tile_nametable_address + off as u16,
+            column,
+        ) as usize;
 
-        let palette = self.get_palette(tile_x, tile_y, attr_table);
+        let palette = self.get_palette(cpu, tile_x, tile_y, attr_table, column);
 
-        let tile_x_off = 7 - (scrolled_x % 8);
         let tile_y_off = scrolled_y % 8;
 
         let bank = self.controller_register.background_pattern_address;
 
-        let byte_upper = cpu.ppu_read_chr_rom(bank + (tile_num * 16 + tile_y_off) as u16);
-        let byte_lower = cpu.ppu_read_chr_rom(bank + (tile_num * 16 + tile_y_off + 8) as u16);
+        let byte_upper = self.fetch(
+            cpu,
+            FetchKind::BackgroundPattern,
+            bank + (tile_num * 16 + tile_y_off) as u16,
+            column,
+        );
+        let byte_lower = self.fetch(
+            cpu,
+            FetchKind::BackgroundPattern,
+            bank + (tile_num * 16 + tile_y_off + 8) as u16,
+            column,
+        );
+
+        BackgroundTile {
+            column,
+            palette,
+            byte_upper,
+            byte_lower,
+        }
+    }
 
-        let bit_upper = (byte_upper & 1 << tile_x_off) != 0;
-        let bit_lower = (byte_lower & 1 << tile_x_off) != 0;
+    // returns true if a tile was drawn on this pixel
+    fn draw_pixel(
+        &mut self,
+        cpu: &mut impl Cpu,
+        x: usize,
+        y: usize,
+        name_table_address: u16,
+    ) -> bool {
+        let scroll_x = self.scroll.x;
+        let scroll_y = self.scroll.y;
+        let scrolled_x = (x as isize + scroll_x as isize).rem_euclid(WIDTH as isize * 2) as usize;
+        let scrolled_y = (y as isize + scroll_y as isize).rem_euclid(HEIGHT as isize * 2) as usize;
 
-        let mut color = match (bit_lower, bit_upper) {
-            (false, false) => palette[0],
-            (false, true) => palette[1],
-            (true, false) => palette[2],
-            (true, true) => palette[3],
+        // which tile on this line we're drawing, counted from the left of the screen
+        let column = (x + scroll_x as usize % 8) / 8;
+
+        let tile = match self.background_tile {
+            Some(tile) if tile.column == column => tile,
+            _ => {
+                let tile = self.fetch_background_tile(
+                    cpu,
+                    scrolled_x,
+                    scrolled_y,
+                    name_table_address,
+                    column,
+                );
+                self.background_tile = Some(tile);
+                tile
+            }
         };
 
-        if self.mask_register.emph_red {
-            color.0 = 0xff;
-        }
-        if self.mask_register.emph_green {
-            color.1 = 0xff;
-        }
-        if self.mask_register.emph_blue {
-            color.2 = 0xff;
-        }
+        let tile_x_off = 7 - (scrolled_x % 8);
 
-        screen.draw_pixel(x, y, color);
+        let bit_upper = (tile.byte_upper & 1 << tile_x_off) != 0;
+        let bit_lower = (tile.byte_lower & 1 << tile_x_off) != 0;
+
+        let color = match (bit_lower, bit_upper) {
+            (false, false) => tile.palette[0],
+            (false, true) => tile.palette[1],
+            (true, false) => tile.palette[2],
+            (true, true) => tile.palette[3],
+        };
+
+        let pixel = self.put_pixel(x, y, color);
+
+        let opaque = bit_lower || bit_upper;
+        self.background_pixel = (pixel, opaque);
+
+        opaque
+    }
 
-        bit_lower || bit_upper
+    /// The real hardware fetches 34 tiles on every line, one or two more than fit on the screen.
+    /// Those aren't drawn, but mappers like MMC2 switch banks on them, so they're fetched anyway.
+    fn fetch_tiles_past_the_edge(&mut self, cpu: &mut impl Cpu, y: usize, name_table_address: u16) {
+        let first_column = self.background_tile.map_or(0, |tile| tile.column + 1);
+        let scrolled_y = (y + self.scroll.y as usize) % (HEIGHT as usize * 2);
+
+        for column in first_column..34 {
+            let scrolled_x = (self.scroll.x as usize / 8 + column) * 8 % (WIDTH as usize * 2);
+            let tile =
+                self.fetch_background_tile(cpu, scrolled_x, scrolled_y, name_table_address, column);
+            self.background_tile = Some(tile);
+        }
     }
 
     #[inline]
@@ -465,31 +699,20 @@ impl Ppu {
         !(self.line_progress < 256 && self.scanline < 240)
     }
 
-    #[allow(clippy::too_many_arguments)]
-    fn draw_sprite_pixel(
-        &self,
-        cpu: &mut impl Cpu,
-        screen: &mut ScreenWriter,
-        sprite: [u8; 4],
-
-        x: usize,
-        y: usize,
-        mut sprite_x_off: u16,
-        mut sprite_y_off: u16,
-        name_table: u16,
-    ) -> bool {
-        let mut sprite_zero_hit = false;
-
+    /// The address of the low pattern byte of the row of a sprite that's drawn on the current line
+    fn sprite_row_address(&self, sprite: [u8; 4]) -> u16 {
         let tile_num = u16::from(sprite[1]);
 
+        let mut sprite_y_off = if sprite[0] == 0xff {
+            0
+        } else {
+            (self.scanline - sprite[0] as usize) as u16
+        };
+
         let flip_y = sprite[2] & 0b1000_0000 > 0;
-        let flip_x = sprite[2] & 0b0100_0000 > 0;
         if flip_y {
             sprite_y_off = (u16::from(self.controller_register.sprite_size.1) - 1) - sprite_y_off;
         }
-        if !flip_x {
-            sprite_x_off = 7 - sprite_x_off;
-        }
 
         let (bank, tile_num) = if self.controller_register.sprite_size.1 == 16 {
             let old_tile_num = tile_num;
@@ -505,15 +728,63 @@ impl Ppu {
             (self.controller_register.sprite_pattern_address, tile_num)
         };
 
+        bank + tile_num * 16 + sprite_y_off
+    }
+
+    /// Fetches the patterns of all sprites on the current line, in order of the slots in
+    /// secondary oam. Like on the real hardware, empty slots still do a (dummy) fetch of tile 0xff.
+    fn fetch_sprite_patterns(&mut self, cpu: &mut impl Cpu) {
+        for slot in 0..8 {
+            let sprite = [
+                self.secondary_oam[slot * 4],
+                self.secondary_oam[slot * 4 + 1],
+                self.secondary_oam[slot * 4 + 2],
+                self.secondary_oam[slot * 4 + 3],
+            ];
+            let address = self.sprite_row_address(sprite);
+
+            let byte_upper = self.fetch(cpu, FetchKind::SpritePattern, address, slot);
+            let byte_lower = self.fetch(cpu, FetchKind::SpritePattern, address + 8, slot);
+
+            self.sprite_patterns[slot] = (byte_upper, byte_lower);
+        }
+
+        // The real hardware never fetches the sprites over the limit of 8, so the cartridge
+        // can override them (for its banking) but they're otherwise read without side effects.
+        for index in 0..self.extra_sprites.len() {
+            let slot = 8 + index;
+            let address = self.sprite_row_address(self.extra_sprites[index]);
+
+            self.sprite_patterns[slot] = (
+                self.fetch_extra_sprite(cpu, address, slot),
+                self.fetch_extra_sprite(cpu, address + 8, slot),
+            );
+        }
+    }
+
+    fn draw_sprite_pixel(
+        &mut self,
+        sprite: [u8; 4],
+        slot: usize,
+        x: usize,
+        y: usize,
+        mut sprite_x_off: u16,
+    ) -> bool {
+        let mut sprite_zero_hit = false;
+
+        let flip_x = sprite[2] & 0b0100_0000 > 0;
+        if !flip_x {
+            sprite_x_off = 7 - sprite_x_off;
+        }
+
         let palette = self.get_sprite_palette(sprite[2] & 0b0000_0011);
 
-        let byte_upper = cpu.ppu_read_chr_rom(bank + tile_num * 16 + sprite_y_off);
-        let byte_lower = cpu.ppu_read_chr_rom(bank + tile_num * 16 + sprite_y_off + 8);
+        let (byte_upper, byte_lower) = self.sprite_patterns[slot];
 
         let bit_upper = (byte_upper & 1 << sprite_x_off) != 0;
         let bit_lower = (byte_lower & 1 << sprite_x_off) != 0;
 
-        let mut color = match (bit_lower, bit_upper) {
+        let color = match (bit_lower, bit_upper) {
             (false, false) => return sprite_zero_hit,
             (false, true) => palette[1],
             (true, false) => palette[2],
@@ -526,37 +797,37 @@ impl Ppu {
             sprite_zero_hit = true;
         }
 
-        if self.mask_register.emph_red {
-            color.0 = 0xff;
-        }
-        if self.mask_register.emph_green {
-            color.1 = 0xff;
-        }
-        if self.mask_register.emph_blue {
-            color.2 = 0xff;
-        }
-
         let behind_background = sprite[2] & 0b0010_0000 > 0;
 
         // Don't draw a background sprite over background tiles,
         // but do draw it over the background color
-        if behind_background && self.draw_pixel(cpu, screen, x, y, name_table) {
+        let (background_pixel, background_opaque) = self.background_pixel;
+        if behind_background && background_opaque {
+            self.next_frame.set(x, y, background_pixel);
             return sprite_zero_hit;
         }
 
-        screen.draw_pixel(x, y, color);
+        self.put_pixel(x, y, color);
 
         sprite_zero_hit
     }
 
-    fn draw_sprites(&self, cpu: &mut impl Cpu, screen: &mut ScreenWriter, name_table: u16) -> bool {
+    fn draw_sprites(&mut self) -> bool {
         let mut sprite_zero_hit = false;
 
-        for i in (0..8).rev() {
-            let sprite_y = self.secondary_oam[i * 4];
-            let sprite_1 = self.secondary_oam[i * 4 + 1];
-            let sprite_2 = self.secondary_oam[i * 4 + 2];
-            let sprite_x = self.secondary_oam[i * 4 + 3];
+        // draw from the lowest priority to the highest, so the sprite with the highest priority ends up on top
+        for i in (0..8 + self.extra_sprites.len()).rev() {
+            let sprite = if i < 8 {
+                [
+                    self.secondary_oam[i * 4],
+                    self.secondary_oam[i * 4 + 1],
+                    self.secondary_oam[i * 4 + 2],
+                    self.secondary_oam[i * 4 + 3],
+                ]
+            } else {
+                self.extra_sprites[i - 8]
+            };
+            let [sprite_y, _, _, sprite_x] = sprite;
 
             if self.line_progress >= sprite_x as usize
                 && self.line_progress < sprite_x as usize + 8
@@ -564,14 +835,11 @@ impl Ppu {
                 && sprite_y != 0xff
             {
                 sprite_zero_hit |= self.draw_sprite_pixel(
-                    cpu,
-                    screen,
-                    [sprite_y, sprite_1, sprite_2, sprite_x],
+                    sprite,
+                    i,
                     self.line_progress,
                     self.scanline,
                     (self.line_progress - sprite_x as usize) as u16,
-                    (self.scanline - sprite_y as usize) as u16,
-                    name_table,
                 );
             }
         }
@@ -579,24 +847,537 @@ impl Ppu {
         sprite_zero_hit
     }
 
-    /// the screen is optional, since sometimes there is no screen (headless mode)
-    pub(crate) fn update(&mut self, cpu: &mut impl Cpu, screen: &mut ScreenWriter) {
-        self.update_scanline(cpu, screen);
+    /// Advances the PPU by one cycle, drawing into `sink`. The PPU runs at 3 times the speed
+    /// of the cpu, so this should be called 3 times after every [`Cpu::tick`].
+    ///
+    /// This is done for you by [`run_cpu`](crate::run_cpu) and the other run functions. Only use this
+    /// if you need to drive the emulator from your own loop.
+    pub fn update(&mut self, cpu: &mut impl Cpu, sink: &mut impl FrameSink) {
+        self.update_scanline(cpu, sink);
 
         if !self.blanking() {
             let nametable_addr = self.controller_register.nametable_address;
 
-            self.draw_pixel(
-                cpu,
-                screen,
-                self.line_progress,
-                self.scanline,
-                nametable_addr,
-            );
+            self.draw_pixel(cpu, self.line_progress, self.scanline, nametable_addr);
 
-            if self.draw_sprites(cpu, screen, nametable_addr) {
+            if self.draw_sprites() {
                 self.status_register.sprite_zero_hit = true;
             }
+
+            if self.line_progress == WIDTH as usize - 1 {
+                self.fetch_tiles_past_the_edge(cpu, self.scanline, nametable_addr);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestCpu;
+    use crate::DummySink;
+
+    #[test]
+    fn large_sprites_at_the_bottom_dont_wrap_into_the_next_frame() {
+        let mut cpu = TestCpu::new(|_, _| {});
+        // solid sprites from the first pattern table, an empty background from the second
+        cpu.chr[..32].fill(0xff);
+        let mut ppu = Ppu::new(Mirroring::Horizontal);
+        write_palette(&mut ppu, &mut cpu, 0x3f00, &[0x0f]);
+        write_palette(&mut ppu, &mut cpu, 0x3f13, &[0x16]);
+
+        // 8x16 sprites, with everything shown
+        ppu.write_ppu_register(PpuRegister::Controller, 0b0011_0000, &mut cpu);
+        ppu.write_ppu_register(PpuRegister::Mask, 0b0001_1110, &mut cpu);
+        let mut oam = [0xff; 256];
+        oam[..4].copy_from_slice(&[232, 0, 0, 0]);
+        oam[4..8].copy_from_slice(&[248, 0, 0, 8]);
+        ppu.write_oam_dma(oam);
+
+        for _ in 0..341 * 262 * 3 {
+            ppu.update(&mut cpu, &mut DummySink);
+        }
+
+        // the sprite that's partly on the screen is drawn, the one below it isn't
+        // drawn at the top of the next frame
+        let frame = ppu.frame();
+        assert!((0..8).all(|x| frame.get(x, 239) == 0x16));
+        assert!((0..16).all(|y| (0..256).all(|x| frame.get(x, y) == 0x0f)));
+    }
+
+    #[derive(Default)]
+    struct OrderSink(Vec<&'static str>);
+
+    impl FrameSink for OrderSink {
+        fn frame_completed(&mut self, _frame: &Frame, _palette: &Palette) {
+            self.0.push("frame_completed");
+        }
+
+        fn buttons(&mut self) -> Option<Buttons> {
+            self.0.push("buttons");
+            None
+        }
+
+        fn buttons_updated(&mut self, _buttons: Buttons) {
+            self.0.push("buttons_updated");
+        }
+    }
+
+    #[test]
+    fn buttons_are_updated_before_the_frame_is_completed() {
+        let mut cpu = TestCpu::new(|_, _| {});
+        let mut ppu = Ppu::new(Mirroring::Horizontal);
+        let mut sink = OrderSink::default();
+
+        for _ in 0..341 * 262 {
+            ppu.update(&mut cpu, &mut sink);
+        }
+
+        assert_eq!(sink.0, ["buttons", "buttons_updated", "frame_completed"]);
+    }
+
+    /// Remembers the slots of the sprite fetches it was asked to override
+    #[derive(Default)]
+    struct SlotCpu(Vec<u8>);
+
+    impl Cpu for SlotCpu {
+        type TickError = std::convert::Infallible;
+
+        fn tick(&mut self, _ppu: &mut Ppu) -> Result<(), Self::TickError> {
+            Ok(())
+        }
+
+        fn ppu_read_chr_rom(&self, _offset: u16) -> u8 {
+            0
+        }
+
+        fn ppu_override_fetch(&mut self, fetch: PpuFetch) -> Option<u8> {
+            if fetch.kind == FetchKind::SpritePattern && !self.0.contains(&fetch.column) {
+                self.0.push(fetch.column);
+            }
+            None
+        }
+
+        fn ppu_memory_write(&mut self, _address: u16, _value: u8) {}
+
+        fn non_maskable_interrupt(&mut self) {}
+    }
+
+    /// Counts the fetches that the cartridge could react to
+    #[derive(Default)]
+    struct FetchCountCpu(usize);
+
+    impl Cpu for FetchCountCpu {
+        type TickError = std::convert::Infallible;
+
+        fn tick(&mut self, _ppu: &mut Ppu) -> Result<(), Self::TickError> {
+            Ok(())
+        }
+
+        fn ppu_read_chr_rom(&self, _offset: u16) -> u8 {
+            0
+        }
+
+        fn ppu_fetch_chr_rom(&mut self, _offset: u16) -> u8 {
+            self.0 += 1;
+            0
+        }
+
+        fn ppu_override_fetch(&mut self, _fetch: PpuFetch) -> Option<u8> {
+            self.0 += 1;
+            None
+        }
+
+        fn ppu_memory_write(&mut self, _address: u16, _value: u8) {}
+
+        fn non_maskable_interrupt(&mut self) {}
+    }
+
+    #[test]
+    fn nothing_is_fetched_while_rendering_is_off() {
+        let mut cpu = FetchCountCpu::default();
+        let mut ppu = Ppu::new(Mirroring::Horizontal);
+
+        for _ in 0..341 * 262 {
+            ppu.update(&mut cpu, &mut DummySink);
+        }
+        assert_eq!(cpu.0, 0);
+
+        // showing just the sprites is enough to make the PPU fetch again
+        ppu.write_ppu_register(PpuRegister::Mask, 0b0001_0000, &mut cpu);
+        for _ in 0..341 * 262 {
+            ppu.update(&mut cpu, &mut DummySink);
+        }
+        assert!(cpu.0 > 0);
+    }
+
+    /// Remembers every fetch the cartridge was asked to override
+    #[derive(Default)]
+    struct RecordingCpu(Vec<PpuFetch>);
+
+    impl Cpu for RecordingCpu {
+        type TickError = std::convert::Infallible;
+
+        fn tick(&mut self, _ppu: &mut Ppu) -> Result<(), Self::TickError> {
+            Ok(())
+        }
+
+        fn ppu_read_chr_rom(&self, _offset: u16) -> u8 {
+            0
+        }
+
+        fn ppu_override_fetch(&mut self, fetch: PpuFetch) -> Option<u8> {
+            self.0.push(fetch);
+            None
+        }
+
+        fn ppu_memory_write(&mut self, _address: u16, _value: u8) {}
+
+        fn non_maskable_interrupt(&mut self) {}
+    }
+
+    #[test]
+    fn scrolling_into_the_next_nametable_wraps_around() {
+        let mut cpu = RecordingCpu::default();
+        let mut ppu = Ppu::new(Mirroring::Vertical);
+        // the last nametable, scrolled 2 tiles to the right
+        ppu.write_ppu_register(PpuRegister::Controller, 0b0000_0011, &mut cpu);
+        ppu.write_ppu_register(PpuRegister::Scroll, 16, &mut cpu);
+        ppu.write_ppu_register(PpuRegister::Scroll, 0, &mut cpu);
+        ppu.write_ppu_register(PpuRegister::Mask, 0b0000_1010, &mut cpu);
+
+        // the scroll is only picked up during the first frame, which ends just before the
+        // first pixel of the next one is drawn
+        for _ in 0..341 * 262 - 1 {
+            ppu.update(&mut cpu, &mut DummySink);
+        }
+        cpu.0.clear();
+        for _ in 0..341 {
+            ppu.update(&mut cpu, &mut DummySink);
+        }
+
+        let fetch = |kind, column| {
+            *cpu.0
+                .iter()
+                .find(|fetch| fetch.scanline == 0 && fetch.kind == kind && fetch.column == column)
+                .unwrap()
+        };
+        let expected = |kind, address, column| PpuFetch {
+            kind,
+            address,
+            column,
+            scanline: 0,
+            large_sprites: false,
+        };
+
+        assert_eq!(
+            fetch(FetchKind::Nametable, 0),
+            expected(FetchKind::Nametable, 0x2c02, 0)
+        );
+        // 0x2c00 scrolled to the right is 0x2800, not 0x3000
+        assert_eq!(
+            fetch(FetchKind::Nametable, 30),
+            expected(FetchKind::Nametable, 0x2800, 30)
+        );
+        assert_eq!(
+            fetch(FetchKind::Attribute, 30),
+            expected(FetchKind::Attribute, 0x2bc0, 30)
+        );
+        assert!(cpu.0.iter().all(|fetch| match fetch.kind {
+            FetchKind::Nametable | FetchKind::Attribute =>
+                (0x2000..0x3000).contains(&fetch.address),
+            FetchKind::BackgroundPattern | FetchKind::SpritePattern => fetch.address < 0x2000,
+        }));
+    }
+
+    /// Makes the background tiles in column 5 and every sprite solid, and remembers the fetches
+    #[derive(Default)]
+    struct SolidOverrideCpu(Vec<PpuFetch>);
+
+    impl Cpu for SolidOverrideCpu {
+        type TickError = std::convert::Infallible;
+
+        fn tick(&mut self, _ppu: &mut Ppu) -> Result<(), Self::TickError> {
+            Ok(())
+        }
+
+        fn ppu_read_chr_rom(&self, _offset: u16) -> u8 {
+            0
+        }
+
+        fn ppu_override_fetch(&mut self, fetch: PpuFetch) -> Option<u8> {
+            self.0.push(fetch);
+            match fetch.kind {
+                FetchKind::BackgroundPattern if fetch.column == 5 => Some(0xff),
+                FetchKind::SpritePattern => Some(0xff),
+                _ => None,
+            }
         }
+
+        fn ppu_memory_write(&mut self, _address: u16, _value: u8) {}
+
+        fn non_maskable_interrupt(&mut self) {}
+    }
+
+    /// Writes `colors` to the palette table, starting at `address`
+    fn write_palette(ppu: &mut Ppu, cpu: &mut impl Cpu, address: u16, colors: &[u8]) {
+        let [high, low] = address.to_be_bytes();
+        ppu.write_ppu_register(PpuRegister::Address, high, cpu);
+        ppu.write_ppu_register(PpuRegister::Address, low, cpu);
+        for &color in colors {
+            ppu.write_ppu_register(PpuRegister::Data, color, cpu);
+        }
+    }
+
+    #[test]
+    fn overridden_fetches_are_drawn() {
+        let mut cpu = SolidOverrideCpu::default();
+        let mut ppu = Ppu::new(Mirroring::Horizontal);
+        // background color, the last color of the first background and sprite palettes
+        write_palette(&mut ppu, &mut cpu, 0x3f00, &[0x0f, 0, 0, 0x30]);
+        write_palette(&mut ppu, &mut cpu, 0x3f13, &[0x16]);
+        // 8x16 sprites, with everything shown
+        ppu.write_ppu_register(PpuRegister::Controller, 0b0010_0000, &mut cpu);
+        ppu.write_ppu_register(PpuRegister::Mask, 0b0001_1110, &mut cpu);
+        let mut oam = [0xff; 256];
+        oam[..4].copy_from_slice(&[100, 0, 0, 100]);
+        ppu.write_oam_dma(oam);
+
+        for _ in 0..341 * 262 {
+            ppu.update(&mut cpu, &mut DummySink);
+        }
+
+        let frame = ppu.frame();
+        for y in [0, 50, 239] {
+            assert_eq!(frame.get(39, y), 0x0f);
+            assert!((40..48).all(|x| frame.get(x, y) == 0x30));
+            assert_eq!(frame.get(48, y), 0x0f);
+        }
+        assert!((100..116).all(|y| (100..108).all(|x| frame.get(x, y) == 0x16)));
+        assert_eq!(frame.get(108, 100), 0x0f);
+        assert_eq!(frame.get(100, 116), 0x0f);
+
+        assert!(cpu.0.iter().all(|fetch| fetch.large_sprites));
+        let background_columns = cpu
+            .0
+            .iter()
+            .filter(|fetch| fetch.scanline == 50 && fetch.kind == FetchKind::BackgroundPattern)
+            .map(|fetch| fetch.column)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            background_columns,
+            (0..34)
+                .flat_map(|column| [column, column])
+                .collect::<Vec<_>>()
+        );
+        let sprite_slots = cpu
+            .0
+            .iter()
+            .filter(|fetch| fetch.scanline == 100 && fetch.kind == FetchKind::SpritePattern)
+            .map(|fetch| fetch.column)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            sprite_slots,
+            (0..8).flat_map(|slot| [slot, slot]).collect::<Vec<_>>()
+        );
+    }
+
+    /// Overrides every pattern fetch, while counting the overrides and the fetches it sees
+    #[derive(Default)]
+    struct OverrideEverythingCpu {
+        overridden: usize,
+        fetched: usize,
+    }
+
+    impl Cpu for OverrideEverythingCpu {
+        type TickError = std::convert::Infallible;
+
+        fn tick(&mut self, _ppu: &mut Ppu) -> Result<(), Self::TickError> {
+            Ok(())
+        }
+
+        fn ppu_read_chr_rom(&self, _offset: u16) -> u8 {
+            0
+        }
+
+        fn ppu_fetch_chr_rom(&mut self, _offset: u16) -> u8 {
+            self.fetched += 1;
+            0
+        }
+
+        fn ppu_override_fetch(&mut self, fetch: PpuFetch) -> Option<u8> {
+            match fetch.kind {
+                FetchKind::BackgroundPattern | FetchKind::SpritePattern => {
+                    self.overridden += 1;
+                    Some(0xff)
+                }
+                FetchKind::Nametable | FetchKind::Attribute => None,
+            }
+        }
+
+        fn ppu_memory_write(&mut self, _address: u16, _value: u8) {}
+
+        fn non_maskable_interrupt(&mut self) {}
+    }
+
+    #[test]
+    fn overridden_patterns_are_still_fetched() {
+        let mut cpu = OverrideEverythingCpu::default();
+        let mut ppu = Ppu::new(Mirroring::Horizontal);
+        ppu.write_ppu_register(PpuRegister::Mask, 0b0001_1000, &mut cpu);
+
+        for _ in 0..341 * 262 {
+            ppu.update(&mut cpu, &mut DummySink);
+        }
+
+        assert!(cpu.overridden > 0);
+        assert_eq!(cpu.fetched, cpu.overridden);
+    }
+
+    /// Remembers the offsets of the fetches through [`Cpu::ppu_fetch_chr_rom`]
+    #[derive(Default)]
+    struct ChrFetchCpu(Vec<u16>);
+
+    impl Cpu for ChrFetchCpu {
+        type TickError = std::convert::Infallible;
+
+        fn tick(&mut self, _ppu: &mut Ppu) -> Result<(), Self::TickError> {
+            Ok(())
+        }
+
+        fn ppu_read_chr_rom(&self, _offset: u16) -> u8 {
+            0
+        }
+
+        fn ppu_fetch_chr_rom(&mut self, offset: u16) -> u8 {
+            self.0.push(offset);
+            0
+        }
+
+        fn ppu_memory_write(&mut self, _address: u16, _value: u8) {}
+
+        fn non_maskable_interrupt(&mut self) {}
+    }
+
+    /// Writes `tiles` to the nametables, starting at `address`
+    fn write_nametable(ppu: &mut Ppu, cpu: &mut impl Cpu, address: u16, tiles: &[u8]) {
+        let [high, low] = address.to_be_bytes();
+        ppu.write_ppu_register(PpuRegister::Address, high, cpu);
+        ppu.write_ppu_register(PpuRegister::Address, low, cpu);
+        for &tile in tiles {
+            ppu.write_ppu_register(PpuRegister::Data, tile, cpu);
+        }
+    }
+
+    #[test]
+    fn patterns_are_fetched_in_order() {
+        let mut cpu = ChrFetchCpu::default();
+        let mut ppu = Ppu::new(Mirroring::Vertical);
+        // tile n in the nth column, continuing in the nametable to the right
+        write_nametable(&mut ppu, &mut cpu, 0x2000, &(0..32).collect::<Vec<_>>());
+        write_nametable(&mut ppu, &mut cpu, 0x2400, &[32, 33]);
+        // sprites from the second pattern table, with everything shown
+        ppu.write_ppu_register(PpuRegister::Controller, 0b0000_1000, &mut cpu);
+        ppu.write_ppu_register(PpuRegister::Mask, 0b0001_1110, &mut cpu);
+
+        // the first line of the second frame starts with the last update of the first
+        for _ in 0..341 * 262 - 1 {
+            ppu.update(&mut cpu, &mut DummySink);
+        }
+        cpu.0.clear();
+        for _ in 0..341 - 1 {
+            ppu.update(&mut cpu, &mut DummySink);
+        }
+
+        // first the 8 sprite slots, then all 34 background tiles, also the 2 right of the screen
+        let (sprites, background) = cpu.0.split_at(16);
+        assert!(sprites.iter().all(|&offset| offset >= 0x1000));
+        assert_eq!(
+            background,
+            (0..34)
+                .flat_map(|tile| [tile * 16, tile * 16 + 8])
+                .collect::<Vec<_>>()
+        );
+    }
+
+    /// A cartridge with an MMC2 like latch: fetching the pattern of tile 0xfe switches to
+    /// a bank where tile 0 is solid, and tile 0xfd switches back to the one where it's empty.
+    #[derive(Default)]
+    struct LatchCpu {
+        solid_bank: bool,
+    }
+
+    impl Cpu for LatchCpu {
+        type TickError = std::convert::Infallible;
+
+        fn tick(&mut self, _ppu: &mut Ppu) -> Result<(), Self::TickError> {
+            Ok(())
+        }
+
+        fn ppu_read_chr_rom(&self, offset: u16) -> u8 {
+            if self.solid_bank && offset < 16 {
+                0xff
+            } else {
+                0
+            }
+        }
+
+        fn ppu_fetch_chr_rom(&mut self, offset: u16) -> u8 {
+            let value = self.ppu_read_chr_rom(offset);
+            match offset {
+                0x0fd8 => self.solid_bank = false,
+                0x0fe8 => self.solid_bank = true,
+                _ => {}
+            }
+            value
+        }
+
+        fn ppu_memory_write(&mut self, _address: u16, _value: u8) {}
+
+        fn non_maskable_interrupt(&mut self) {}
+    }
+
+    #[test]
+    fn tiles_right_of_the_screen_switch_latches() {
+        let mut cpu = LatchCpu::default();
+        let mut ppu = Ppu::new(Mirroring::Vertical);
+        write_palette(&mut ppu, &mut cpu, 0x3f00, &[0x0f, 0, 0, 0x30]);
+        // 0xfe only in the 34th column of the second row, which is never shown
+        write_nametable(&mut ppu, &mut cpu, 0x2421, &[0xfe]);
+        // writing the address selected the nametable on the right, go back to the first
+        ppu.write_ppu_register(PpuRegister::Controller, 0, &mut cpu);
+        ppu.write_ppu_register(PpuRegister::Mask, 0b0000_1010, &mut cpu);
+
+        for _ in 0..341 * 262 {
+            ppu.update(&mut cpu, &mut DummySink);
+        }
+
+        // the first line of the second row is drawn with the empty bank, which is switched
+        // at the end of it
+        let frame = ppu.frame();
+        assert!((0..256).all(|x| frame.get(x, 8) == 0x0f));
+        assert!((0..256).all(|x| frame.get(x, 9) == 0x30));
+    }
+
+    #[test]
+    fn extra_sprites_can_be_overridden() {
+        let mut cpu = SlotCpu::default();
+        let mut ppu = Ppu::new(Mirroring::Horizontal);
+        ppu.set_unlimited_sprites(true);
+        ppu.write_ppu_register(PpuRegister::Mask, 0b0001_1000, &mut cpu);
+
+        // 10 sprites on the same lines
+        let mut oam = [0xff; 256];
+        for sprite in oam.chunks_exact_mut(4).take(10) {
+            sprite.copy_from_slice(&[100, 0, 0, 0]);
+        }
+        ppu.write_oam_dma(oam);
+
+        for _ in 0..341 * 262 {
+            ppu.update(&mut cpu, &mut DummySink);
+        }
+
+        cpu.0.sort();
+        assert_eq!(cpu.0, (0..10).collect::<Vec<_>>());
     }
 }
@@ -0,0 +1,281 @@
+use crate::ppu::colors::{Color, Palette};
+use std::f64::consts::PI;
+
+/// Voltage of the signal for the low half of each of the 4 luminance levels
+const LOW_LEVELS: [f64; 4] = [0.228, 0.312, 0.552, 0.880];
+/// Voltage of the signal for the high half of each of the 4 luminance levels
+const HIGH_LEVELS: [f64; 4] = [0.616, 0.840, 1.100, 1.100];
+const BLACK: f64 = LOW_LEVELS[1];
+const WHITE: f64 = HIGH_LEVELS[3];
+/// How much the signal is attenuated by color emphasis
+const EMPHASIS_ATTENUATION: f64 = 0.746;
+/// Phase offset (in 1/12th of a cycle) and gain of the decoded color. These are chosen so that
+/// the default settings give colors within 8 (of 255) of the measured 2C02 palette in every channel.
+const CARRIER_PHASE: f64 = 4.0;
+const CHROMA_GAIN: f64 = 1.6;
+/// The lowest gamma a TV is simulated with, lower (or invalid) gammas are raised to this
+const MIN_GAMMA: f64 = 0.1;
+
+/// The signal generated by the PPU for a pixel (see [`Frame`](crate::Frame)) at one of
+/// the 12 phases of the color subcarrier. Normalized so black is 0.0 and white 1.0.
+pub(crate) fn signal(pixel: u16, phase: usize) -> f64 {
+    let color = (pixel & 0x0f) as usize;
+    let emphasis = (pixel >> 6) & 0b111;
+    // the colors 0x0e and 0x0f are always black
+    let level = if color > 13 {
+        1
+    } else {
+        ((pixel >> 4) & 0b11) as usize
+    };
+
+    let mut low = LOW_LEVELS[level];
+    let mut high = HIGH_LEVELS[level];
+    if color == 0 {
+        low = high;
+    }
+    if color > 12 {
+        high = low;
+    }
+
+    let in_color_phase = |color: usize| (color + phase) % 12 < 6;
+
+    let mut signal = if in_color_phase(color) { high } else { low };
+
+    if color < 14
+        && ((emphasis & 0b001 != 0 && in_color_phase(0))
+            || (emphasis & 0b010 != 0 && in_color_phase(4))
+            || (emphasis & 0b100 != 0 && in_color_phase(8)))
+    {
+        signal *= EMPHASIS_ATTENUATION;
+    }
+
+    (signal - BLACK) / (WHITE - BLACK)
+}
+
+/// Settings to decode the NTSC signal like a TV would, shared by the palette generator and filters
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) struct Decoder {
+    pub(crate) hue: f64,
+    pub(crate) saturation: f64,
+    pub(crate) contrast: f64,
+    pub(crate) brightness: f64,
+    pub(crate) gamma: f64,
+}
+
+impl Decoder {
+    /// The phase of the subcarrier (in units of 1/12th of a cycle) used to decode the color,
+    /// shifted by the hue (in degrees)
+    pub(crate) fn carrier(&self, phase: f64) -> (f64, f64) {
+        let angle = PI * (phase + CARRIER_PHASE + self.hue / 30.0) / 6.0;
+        (CHROMA_GAIN * angle.cos(), CHROMA_GAIN * angle.sin())
+    }
+
+    /// Converts decoded luma (y) and chroma (i, q) to an RGB color
+    pub(crate) fn to_rgb(self, y: f64, i: f64, q: f64) -> Color {
+        let y = y * self.contrast + self.brightness;
+        let i = i * self.saturation * self.contrast;
+        let q = q * self.saturation * self.contrast;
+
+        let channel = |value: f64| {
+            let value = value.clamp(0.0, 1.0).powf(2.2 / self.gamma);
+            (value * 255.0).round() as u8
+        };
+
+        (
+            channel(y + 0.946_882 * i + 0.623_557 * q),
+            channel(y - 0.274_788 * i - 0.635_691 * q),
+            channel(y - 1.108_545 * i + 1.709_007 * q),
+        )
+    }
+}
+
+/// Generates a [`Palette`] by simulating the composite video signal of the 2C02 (the NTSC PPU)
+/// and decoding it like a TV would. The settings work like the knobs on a TV, so they
+/// can be used to reproduce the colors of a specific TV.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PaletteGenerator {
+    /// Rotates all colors, in degrees. 0 by default.
+    pub hue: f64,
+    /// How colorful the palette is. 1.0 by default, 0.0 gives a grey palette.
+    pub saturation: f64,
+    /// 1.0 by default
+    pub contrast: f64,
+    /// Added to the brightness of every color, where 1.0 is the difference between black and white.
+    /// 0.0 by default.
+    pub brightness: f64,
+    /// The gamma of the simulated TV. The generated colors are corrected from this
+    /// to the gamma of 2.2 expected by most monitors. 2.2 by default (so no correction).
+    ///
+    /// Must be positive, gammas below 0.1 are treated as 0.1.
+    pub gamma: f64,
+}
+
+impl Default for PaletteGenerator {
+    fn default() -> Self {
+        Self {
+            hue: 0.0,
+            saturation: 1.0,
+            contrast: 1.0,
+            brightness: 0.0,
+            gamma: 2.2,
+        }
+    }
+}
+
+impl PaletteGenerator {
+    pub(crate) fn decoder(&self) -> Decoder {
+        Decoder {
+            hue: self.hue,
+            saturation: self.saturation,
+            contrast: self.contrast,
+            brightness: self.brightness,
+            // `max` also replaces NaN
+            gamma: self.gamma.max(MIN_GAMMA),
+        }
+    }
+
+    /// Generates all 64 colors for each of the 8 combinations of emphasis bits.
+    pub fn generate(&self) -> Palette {
+        let decoder = self.decoder();
+
+        let colors = (0..512)
+            .map(|pixel| {
+                let (mut y, mut i, mut q) = (0.0, 0.0, 0.0);
+
+                for phase in 0..12 {
+                    let signal = signal(pixel, phase);
+                    let (cos, sin) = decoder.carrier(phase as f64);
+
+                    y += signal;
+                    i += signal * cos;
+                    q += signal * sin;
+                }
+
+                decoder.to_rgb(y / 12.0, i / 12.0, q / 12.0)
+            })
+            .collect::<Vec<_>>();
+
+        Palette::from_all_colors(colors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ppu::colors::BuiltinPalette;
+
+    /// How bright a color is, as the sum of its channels
+    fn brightness(color: Color) -> u32 {
+        u32::from(color.0) + u32::from(color.1) + u32::from(color.2)
+    }
+
+    #[test]
+    fn default_settings_are_close_to_the_2c02() {
+        let generated = PaletteGenerator::default().generate();
+        let measured = Palette::builtin(BuiltinPalette::Ppu2C02);
+
+        for pixel in 0..64 {
+            let (a, b) = (generated.color(pixel), measured.color(pixel));
+            for (a, b) in [(a.0, b.0), (a.1, b.1), (a.2, b.2)] {
+                assert!(
+                    a.abs_diff(b) <= 8,
+                    "color {pixel:#04x} is {a}, should be {b}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn hue_rotates_the_colors() {
+        let default = PaletteGenerator::default().generate();
+        // the 12 hues are 30 degrees apart
+        let rotated = PaletteGenerator {
+            hue: 30.0,
+            ..Default::default()
+        }
+        .generate();
+
+        for level in [0x00, 0x10, 0x20, 0x30] {
+            for hue in 1..=12 {
+                let previous = if hue == 1 { 12 } else { hue - 1 };
+                assert_eq!(rotated.color(level | hue), default.color(level | previous));
+            }
+        }
+    }
+
+    #[test]
+    fn saturation_changes_how_colorful_the_palette_is() {
+        let spread = |saturation: f64| {
+            let color = PaletteGenerator {
+                saturation,
+                ..Default::default()
+            }
+            .generate()
+            .color(0x16);
+            color.0.max(color.1).max(color.2) - color.0.min(color.1).min(color.2)
+        };
+
+        assert_eq!(spread(0.0), 0);
+        assert!(spread(0.5) < spread(1.0));
+        assert!(spread(1.0) < spread(1.2));
+    }
+
+    #[test]
+    fn contrast_brightness_and_gamma_make_grey_lighter() {
+        let grey = |generator: PaletteGenerator| brightness(generator.generate().color(0x10));
+        let default = grey(PaletteGenerator::default());
+
+        let settings = [
+            PaletteGenerator {
+                contrast: 1.2,
+                ..Default::default()
+            },
+            PaletteGenerator {
+                brightness: 0.1,
+                ..Default::default()
+            },
+            PaletteGenerator {
+                gamma: 2.6,
+                ..Default::default()
+            },
+        ];
+
+        for generator in settings {
+            assert!(grey(generator) > default, "{generator:?}");
+            // and the other way around makes it darker
+            let darker = PaletteGenerator {
+                contrast: 2.0 - generator.contrast,
+                brightness: -generator.brightness,
+                gamma: 4.4 - generator.gamma,
+                ..generator
+            };
+            assert!(grey(darker) < default, "{darker:?}");
+        }
+
+        // black stays black
+        let black = PaletteGenerator {
+            contrast: 1.2,
+            gamma: 2.6,
+            ..Default::default()
+        };
+        assert_eq!(black.generate().color(0x0f), (0, 0, 0));
+    }
+
+    #[test]
+    fn gamma_is_kept_positive() {
+        let palette = |gamma: f64| {
+            PaletteGenerator {
+                gamma,
+                ..Default::default()
+            }
+            .generate()
+        };
+
+        let lowest = palette(MIN_GAMMA);
+        assert_eq!(lowest.color(0x10), (0, 0, 0));
+        assert_eq!(lowest.color(0x30), (255, 255, 255));
+        for gamma in [0.0, -2.2, f64::NAN, f64::NEG_INFINITY] {
+            assert!(palette(gamma) == lowest, "gamma {gamma}");
+        }
+    }
+}
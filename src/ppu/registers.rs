@@ -121,6 +121,17 @@ impl MaskRegister {
 
         self.binary_value = value;
     }
+
+    /// Whether the background or the sprites are shown. When neither is, the real PPU
+    /// doesn't fetch anything from memory.
+    pub fn rendering_enabled(&self) -> bool {
+        self.show_background || self.show_sprites
+    }
+
+    /// The three color emphasis bits (red, green, blue from least to most significant)
+    pub fn emphasis(&self) -> u8 {
+        self.binary_value >> 5
+    }
 }
 
 #[derive(Default, Debug)]
@@ -132,18 +143,9 @@ pub(crate) struct StatusRegister {
 
 impl StatusRegister {
     pub fn read(&mut self) -> u8 {
-        let value = (self
-            .sprite_overflow
-            .then_some(0b0010_0000)
-            .unwrap_or_default())
-            | (self
-                .sprite_zero_hit
-                .then_some(0b0100_0000)
-                .unwrap_or_default())
-            | (self
-                .vblank_started
-                .then_some(0b1000_0000)
-                .unwrap_or_default());
+        let value = (if self.sprite_overflow { 0b0010_0000 } else { 0 })
+            | (if self.sprite_zero_hit { 0b0100_0000 } else { 0 })
+            | (if self.vblank_started { 0b1000_0000 } else { 0 });
 
         // SOMEHOW this is the expected behavior
         self.vblank_started = false;
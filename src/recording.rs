@@ -0,0 +1,281 @@
+use crate::filter::Pipeline;
+use crate::image::Image;
+use crate::osd::Osd;
+use crate::ppu::colors::Palette;
+use crate::ppu::frame::Frame;
+use crate::settings::Settings;
+use std::fmt;
+use std::fmt::{Debug, Formatter};
+use std::io;
+use std::io::Write;
+use std::path::PathBuf;
+
+/// The exact frame rate of the NTSC NES (about 60.0988 Hz), as a fraction
+pub const FRAME_RATE: (u32, u32) = (39_375_000, 655_171);
+
+/// The format videos are recorded in, see [`Control::start_recording`](crate::Control::start_recording).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
+pub enum VideoFormat {
+    /// A YUV4MPEG2 (`.y4m`) stream with full resolution color (4:4:4).
+    /// Most video tools, like ffmpeg, can read this directly.
+    #[default]
+    Y4m,
+    /// Raw RGB, 3 bytes per pixel, row by row, frame after frame, without any header.
+    /// This is the only format that's completely lossless. To encode it, tell the encoder
+    /// the size of the frames and the frame rate (see [`FRAME_RATE`]), for example
+    /// `ffmpeg -f rawvideo -pixel_format rgb24 -video_size 256x240 -framerate 39375000/655171 -i -`
+    RawRgb,
+}
+
+impl VideoFormat {
+    /// The file extension used for timestamped recordings
+    pub fn extension(&self) -> &'static str {
+        match self {
+            VideoFormat::Y4m => "y4m",
+            VideoFormat::RawRgb => "rgb",
+        }
+    }
+}
+
+/// Settings for videos recorded with [`Control::start_recording`](crate::Control::start_recording)
+/// or by pressing F9 in the window.
+#[derive(Debug, Clone)]
+pub struct RecordingSettings {
+    /// Where recordings are saved. The current directory by default.
+    pub directory: PathBuf,
+    pub format: VideoFormat,
+    /// Whether the edges cut off by [`Settings::overscan`](crate::Settings::overscan) are left out
+    /// of recordings too. `true` by default.
+    pub crop_overscan: bool,
+    /// Whether the [`Settings::filters`](crate::Settings::filters) are applied to recordings too.
+    /// `true` by default.
+    pub filtered: bool,
+    /// The palette used for recordings. When `None` (the default), the palette
+    /// that's currently selected on the PPU is used.
+    pub palette: Option<Palette>,
+    /// Whether the on-screen display (see [`Settings::osd`](crate::Settings::osd)) is recorded too.
+    /// `false` by default.
+    pub include_osd: bool,
+    /// Whether the controller of the on-screen display (see
+    /// [`OsdSettings::input_display`](crate::OsdSettings::input_display)) is recorded too,
+    /// so viewers can see the inputs of every frame. `false` by default.
+    pub include_input_display: bool,
+}
+
+impl Default for RecordingSettings {
+    fn default() -> Self {
+        Self {
+            directory: PathBuf::from("."),
+            format: VideoFormat::default(),
+            crop_overscan: true,
+            filtered: true,
+            palette: None,
+            include_osd: false,
+            include_input_display: false,
+        }
+    }
+}
+
+/// Where a recording is written to
+pub enum RecordingTarget {
+    /// A timestamped file in the recording directory
+    Timestamped,
+    File(PathBuf),
+    Writer(Box<dyn Write + Send>),
+}
+
+impl Debug for RecordingTarget {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            RecordingTarget::Timestamped => write!(f, "Timestamped"),
+            RecordingTarget::File(path) => f.debug_tuple("File").field(path).finish(),
+            RecordingTarget::Writer(_) => write!(f, "Writer(..)"),
+        }
+    }
+}
+
+/// Writes every frame it's given to a video stream
+pub(crate) struct Recorder {
+    writer: Box<dyn Write + Send>,
+    format: VideoFormat,
+    pipeline: Pipeline,
+    include_osd: bool,
+    include_input_display: bool,
+    /// The picture with the on-screen display drawn over it
+    with_osd: Image,
+    buf: Vec<u8>,
+}
+
+impl Recorder {
+    pub(crate) fn new(mut writer: Box<dyn Write + Send>, settings: &Settings) -> io::Result<Self> {
+        let recordings = &settings.recordings;
+        let pipeline = Pipeline::for_export(
+            settings,
+            recordings.filtered,
+            recordings.crop_overscan,
+            recordings.palette.clone(),
+        );
+        let (width, height) = pipeline.output_size();
+
+        if recordings.format == VideoFormat::Y4m {
+            writeln!(
+                writer,
+                "YUV4MPEG2 W{width} H{height} F{}:{} Ip A1:1 C444",
+                FRAME_RATE.0, FRAME_RATE.1,
+            )?;
+        }
+
+        Ok(Self {
+            writer,
+            format: recordings.format,
+            pipeline,
+            include_osd: recordings.include_osd,
+            include_input_display: recordings.include_input_display,
+            with_osd: Image::default(),
+            buf: Vec::with_capacity((width * height * 3) as usize),
+        })
+    }
+
+    pub(crate) fn write_frame(
+        &mut self,
+        frame: &Frame,
+        palette: &Palette,
+        osd: &Osd,
+    ) -> io::Result<()> {
+        let mut image = self.pipeline.render(frame, palette);
+        let draw_osd = self.include_osd && osd.is_visible();
+        let draw_inputs = self.include_input_display && osd.shows_inputs();
+        if draw_osd || draw_inputs {
+            self.with_osd.clone_from(image);
+            if draw_osd {
+                osd.draw(&mut self.with_osd);
+            }
+            if draw_inputs {
+                osd.draw_inputs(&mut self.with_osd);
+            }
+            image = &self.with_osd;
+        }
+        let rgba = image.rgba();
+
+        self.buf.clear();
+        match self.format {
+            VideoFormat::Y4m => {
+                self.buf.extend_from_slice(b"FRAME\n");
+
+                // the planes are written one after the other: all Y, then all U, then all V
+                for plane in 0..3 {
+                    self.buf.extend(
+                        rgba.chunks_exact(4)
+                            .map(|rgba| rgb_to_yuv(rgba[0], rgba[1], rgba[2])[plane]),
+                    );
+                }
+            }
+            VideoFormat::RawRgb => {
+                self.buf
+                    .extend(rgba.chunks_exact(4).flat_map(|rgba| &rgba[..3]));
+            }
+        }
+
+        self.writer.write_all(&self.buf)
+    }
+
+    pub(crate) fn finish(mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Converts a color to (studio range) BT.601 YCbCr, which is what Y4M players expect
+fn rgb_to_yuv(r: u8, g: u8, b: u8) -> [u8; 3] {
+    let (r, g, b) = (r as f32, g as f32, b as f32);
+
+    let y = 16.0 + (65.481 * r + 128.553 * g + 24.966 * b) / 255.0;
+    let u = 128.0 + (-37.797 * r - 74.203 * g + 112.0 * b) / 255.0;
+    let v = 128.0 + (112.0 * r - 93.786 * g - 18.214 * b) / 255.0;
+
+    [y.round() as u8, u.round() as u8, v.round() as u8]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::Overscan;
+    use std::sync::{Arc, Mutex};
+
+    /// A writer that can still be read after it's given to a recorder
+    #[derive(Clone, Default)]
+    struct SharedWriter(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Records 2 frames of color 0x16, cropped like an NTSC TV
+    fn record(format: VideoFormat) -> (Vec<u8>, Palette) {
+        let mut settings = Settings::default();
+        settings.overscan = Overscan::NTSC;
+        settings.recordings.format = format;
+        let palette = Palette::default();
+        let osd = Osd::new(settings.osd);
+        let mut frame = Frame::default();
+        for pixel in 0..256 * 240 {
+            frame.set(pixel % 256, pixel / 256, 0x16);
+        }
+
+        let writer = SharedWriter::default();
+        let mut recorder = Recorder::new(Box::new(writer.clone()), &settings).unwrap();
+        recorder.write_frame(&frame, &palette, &osd).unwrap();
+        recorder.write_frame(&frame, &palette, &osd).unwrap();
+        recorder.finish().unwrap();
+
+        let bytes = writer.0.lock().unwrap().clone();
+        (bytes, palette)
+    }
+
+    #[test]
+    fn y4m_has_a_header_and_planes_for_every_frame() {
+        let (bytes, palette) = record(VideoFormat::Y4m);
+
+        let header = b"YUV4MPEG2 W256 H224 F39375000:655171 Ip A1:1 C444\n";
+        assert_eq!(&bytes[..header.len()], header);
+
+        let (r, g, b) = palette.color(0x16);
+        let yuv = rgb_to_yuv(r, g, b);
+        let plane = 256 * 224;
+        let frames = bytes[header.len()..]
+            .chunks(6 + plane * 3)
+            .collect::<Vec<_>>();
+        assert_eq!(frames.len(), 2);
+        for frame in frames {
+            assert_eq!(frame.len(), 6 + plane * 3);
+            assert_eq!(&frame[..6], b"FRAME\n");
+            for (samples, value) in frame[6..].chunks(plane).zip(yuv) {
+                assert!(samples.iter().all(|&sample| sample == value));
+            }
+        }
+    }
+
+    #[test]
+    fn raw_rgb_is_just_the_pixels() {
+        let (bytes, palette) = record(VideoFormat::RawRgb);
+
+        let (r, g, b) = palette.color(0x16);
+        assert_eq!(bytes.len(), 2 * 256 * 224 * 3);
+        assert!(bytes.chunks_exact(3).all(|rgb| rgb == [r, g, b]));
+    }
+
+    #[test]
+    fn colors_are_converted_to_studio_range() {
+        assert_eq!(rgb_to_yuv(0, 0, 0), [16, 128, 128]);
+        assert_eq!(rgb_to_yuv(255, 255, 255), [235, 128, 128]);
+        assert_eq!(rgb_to_yuv(255, 0, 0), [81, 90, 240]);
+        assert_eq!(rgb_to_yuv(0, 255, 0), [145, 54, 34]);
+        assert_eq!(rgb_to_yuv(0, 0, 255), [41, 240, 110]);
+    }
+}
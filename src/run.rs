@@ -1,22 +1,316 @@
 use crate::cpu::Cpu;
-use crate::screen::{ButtonName, Message, Screen, ScreenWriter};
-use crate::{Mirroring, Ppu, CPU_FREQ, HEIGHT, WIDTH};
+use crate::filter::Pipeline;
+use crate::frame_hash::FrameHashLog;
+use crate::gif_capture::GifRecorder;
+use crate::image::Image;
+use crate::osd::Osd;
+use crate::ppu::colors::Palette;
+use crate::ppu::frame::Frame;
+use crate::recording::{Recorder, RecordingTarget};
+use crate::screen::{ButtonName, Buttons, Message, Screen, WindowBackend};
+use crate::screenshot::timestamped_path;
+use crate::settings::Settings;
+use crate::sink::{CapturedFrame, DummySink, FrameCapture, FrameSink};
+use crate::{Mirroring, Ppu, CPU_FREQ};
 use pixels::{Pixels, SurfaceTexture};
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 use std::{env, thread};
+use winit::dpi::LogicalSize;
 use winit::event::{ElementState, Event, VirtualKeyCode, WindowEvent};
 use winit::event_loop::{ControlFlow, EventLoop};
 use winit::window::WindowBuilder;
 
+/// Everything the frames the PPU draws go to while running: the sink, and the recordings (if any).
+struct Outputs<'a, S> {
+    sink: &'a mut S,
+    /// Applies the filters for the window
+    pipeline: Pipeline,
+    recorder: Option<Recorder>,
+    gif: Option<GifRecorder>,
+    osd: Osd,
+    /// The picture with the on-screen display drawn over it
+    with_osd: Image,
+}
+
+impl<S: FrameSink> Outputs<'_, S> {
+    fn start_recording(&mut self, target: RecordingTarget, settings: &Settings) {
+        self.stop_recording();
+
+        let recordings = &settings.recordings;
+        let writer: io::Result<Box<dyn Write + Send>> = match target {
+            RecordingTarget::Timestamped => {
+                let path = timestamped_path(&recordings.directory, recordings.format.extension());
+                log::info!("recording to {}", path.display());
+                File::create(path).map(|file| Box::new(BufWriter::new(file)) as _)
+            }
+            RecordingTarget::File(path) => {
+                log::info!("recording to {}", path.display());
+                File::create(path).map(|file| Box::new(BufWriter::new(file)) as _)
+            }
+            RecordingTarget::Writer(writer) => Ok(writer),
+        };
+
+        match writer.and_then(|writer| Recorder::new(writer, settings)) {
+            Ok(recorder) => {
+                self.recorder = Some(recorder);
+                self.osd.recording = true;
+                self.osd.show_message("Recording started");
+            }
+            Err(e) => {
+                log::warn!("failed to start recording: {e}");
+                self.osd.show_message("Failed to start recording");
+            }
+        }
+    }
+
+    fn start_gif(&mut self, path: Option<PathBuf>, frames: Option<u32>, settings: &Settings) {
+        self.stop_gif();
+
+        let gifs = &settings.gifs;
+        let path = path.unwrap_or_else(|| timestamped_path(&gifs.directory, "gif"));
+        match GifRecorder::new(path, gifs, settings.overscan, frames) {
+            Ok(gif) => {
+                self.gif = Some(gif);
+                self.osd.capturing_gif = true;
+                self.osd.show_message("Capturing GIF");
+            }
+            Err(e) => {
+                log::warn!("failed to start capturing gif: {e}");
+                self.osd.show_message("Failed to capture GIF");
+            }
+        }
+    }
+
+    fn stop_gif(&mut self) {
+        if let Some(gif) = self.gif.take() {
+            self.osd.capturing_gif = false;
+            match gif.finish() {
+                Ok(Some(path)) => {
+                    log::info!("saved gif to {}", path.display());
+                    self.osd.show_message("GIF saved");
+                }
+                Ok(None) => log::info!("no frames were captured, so no gif was saved"),
+                Err(e) => {
+                    log::warn!("failed to save gif: {e}");
+                    self.osd.show_message("Failed to save GIF");
+                }
+            }
+        }
+    }
+
+    /// Stops everything that's being recorded, when the emulator stops
+    fn finish(&mut self) {
+        self.stop_recording();
+        self.stop_gif();
+    }
+
+    fn stop_recording(&mut self) {
+        if let Some(recorder) = self.recorder.take() {
+            self.osd.recording = false;
+            match recorder.finish() {
+                Ok(()) => {
+                    log::info!("stopped recording");
+                    self.osd.show_message("Recording stopped");
+                }
+                Err(e) => {
+                    log::warn!("failed to finish recording: {e}");
+                    self.osd.show_message("Failed to finish recording");
+                }
+            }
+        }
+    }
+
+    /// Shows the last picture again, with "paused" over it, since no new pictures come while paused
+    fn show_paused(&mut self) {
+        self.osd.show_message("Paused");
+        if !self.sink.wants_images() {
+            return;
+        }
+        if let Some(image) = self.pipeline.last_image() {
+            self.with_osd.clone_from(image);
+            self.osd.draw(&mut self.with_osd);
+            self.osd.draw_inputs(&mut self.with_osd);
+            self.sink.image_completed(&self.with_osd);
+        }
+    }
+}
+
+impl<S: FrameSink> FrameSink for Outputs<'_, S> {
+    fn frame_completed(&mut self, frame: &Frame, palette: &Palette) {
+        self.sink.frame_completed(frame, palette);
+        self.osd.frame_completed();
+
+        // headless runs don't show the picture, so they don't need to spend time rendering it
+        if self.sink.wants_images() {
+            let image = self.pipeline.render(frame, palette);
+            if self.osd.is_visible() || self.osd.shows_inputs() {
+                self.with_osd.clone_from(image);
+                self.osd.draw(&mut self.with_osd);
+                self.osd.draw_inputs(&mut self.with_osd);
+                self.sink.image_completed(&self.with_osd);
+            } else {
+                self.sink.image_completed(image);
+            }
+        }
+
+        if let Some(recorder) = &mut self.recorder {
+            if let Err(e) = recorder.write_frame(frame, palette, &self.osd) {
+                log::warn!("failed to record frame, stopping the recording: {e}");
+                self.osd.show_message("Recording failed");
+                self.stop_recording();
+            }
+        }
+
+        if let Some(gif) = &mut self.gif {
+            if let Err(e) = gif.write_frame(frame, palette) {
+                log::warn!("failed to capture frame, stopping the gif: {e}");
+                self.osd.show_message("GIF capture failed");
+                self.stop_gif();
+            } else if gif.is_done() {
+                self.stop_gif();
+            }
+        }
+    }
+
+    fn scanline_completed(&mut self, line: usize, frame: &Frame, palette: &Palette) {
+        self.sink.scanline_completed(line, frame, palette);
+    }
+
+    fn wants_images(&self) -> bool {
+        self.sink.wants_images()
+    }
+
+    fn buttons(&mut self) -> Option<Buttons> {
+        self.sink.buttons()
+    }
+
+    fn buttons_updated(&mut self, buttons: Buttons) {
+        self.osd.buttons = buttons;
+        self.sink.buttons_updated(buttons);
+    }
+}
+
+fn handle_message<S: FrameSink>(
+    message: Message,
+    ppu: &mut Ppu,
+    outputs: &mut Outputs<S>,
+    settings: &mut Settings,
+) {
+    match message {
+        Message::Button(name, pressed) => match name {
+            ButtonName::A => {
+                ppu.buttons.a = pressed;
+            }
+            ButtonName::B => {
+                ppu.buttons.b = pressed;
+            }
+            ButtonName::Up => {
+                ppu.buttons.up = pressed;
+            }
+            ButtonName::Down => {
+                ppu.buttons.down = pressed;
+            }
+            ButtonName::Left => {
+                ppu.buttons.left = pressed;
+            }
+            ButtonName::Right => {
+                ppu.buttons.right = pressed;
+            }
+            ButtonName::Start => {
+                ppu.buttons.start = pressed;
+            }
+            ButtonName::Select => {
+                ppu.buttons.select = pressed;
+            }
+        },
+        Message::Screenshot(path) => {
+            let screenshots = &settings.screenshots;
+            let path = path.unwrap_or_else(|| timestamped_path(&screenshots.directory, "png"));
+            let same_as_window =
+                screenshots.filtered && screenshots.crop_overscan && screenshots.palette.is_none();
+
+            // filters like blending need the frames before, which only the window's pipeline has seen
+            let mut image = match outputs.pipeline.last_image() {
+                Some(image) if same_as_window => image.clone(),
+                _ => Pipeline::for_export(
+                    settings,
+                    screenshots.filtered,
+                    screenshots.crop_overscan,
+                    screenshots.palette.clone(),
+                )
+                .render(ppu.frame(), ppu.palette())
+                .clone(),
+            };
+            if screenshots.include_osd && same_as_window {
+                outputs.osd.draw(&mut image);
+            }
+            if screenshots.include_input_display && same_as_window {
+                outputs.osd.draw_inputs(&mut image);
+            }
+
+            match image.save_png(&path) {
+                Ok(()) => {
+                    log::info!("saved screenshot to {}", path.display());
+                    outputs.osd.show_message("Screenshot saved");
+                }
+                Err(e) => {
+                    log::warn!("failed to save screenshot to {}: {e}", path.display());
+                    outputs.osd.show_message("Failed to save screenshot");
+                }
+            }
+        }
+        Message::StartRecording(target) => outputs.start_recording(target, settings),
+        Message::StopRecording => outputs.stop_recording(),
+        Message::ToggleRecording => {
+            if outputs.recorder.is_some() {
+                outputs.stop_recording();
+            } else {
+                outputs.start_recording(RecordingTarget::Timestamped, settings);
+            }
+        }
+        Message::StartGif(path, frames) => outputs.start_gif(path, frames, settings),
+        Message::StopGif => outputs.stop_gif(),
+        Message::ToggleGif => {
+            if outputs.gif.is_some() {
+                outputs.stop_gif();
+            } else {
+                outputs.start_gif(None, None, settings);
+            }
+        }
+        Message::SetFilters(filters) => {
+            outputs.pipeline = Pipeline::new(filters.clone(), settings.overscan, None);
+            settings.filters = filters;
+        }
+        Message::ShowText(text) => outputs.osd.show_message(text),
+        Message::Pause(_) | Message::Quit => {}
+    }
+}
+
 fn run_ppu<CPU: Cpu>(
     mirroring: Mirroring,
     cpu: &mut CPU,
-    writer: &mut ScreenWriter,
+    sink: &mut impl FrameSink,
+    mut settings: Settings,
     max_cycles: Option<usize>,
 ) -> Result<(), CPU::TickError> {
     const ITER_PER_CYCLE: usize = 1000;
     let mut ppu = Ppu::new(mirroring);
+    ppu.set_palette(settings.palette.clone());
+
+    let control_rx = settings.take_control_receiver();
+    let mut outputs = Outputs {
+        sink,
+        pipeline: Pipeline::new(settings.filters.clone(), settings.overscan, None),
+        recorder: None,
+        gif: None,
+        osd: Osd::new(settings.osd),
+        with_osd: Image::default(),
+    };
 
     let mut busy_time = Duration::default();
     let mut cycles = 0;
@@ -24,59 +318,41 @@ fn run_ppu<CPU: Cpu>(
 
     loop {
         for _ in 0..ITER_PER_CYCLE {
-            if let ScreenWriter::Real {
-                control_rx: buttons_rx,
-                ..
-            } = writer
-            {
-                while let Ok(msg) = buttons_rx.try_recv() {
-                    match msg {
-                        Message::Button(name, pressed) => match name {
-                            ButtonName::A => {
-                                ppu.buttons.a = pressed;
-                            }
-                            ButtonName::B => {
-                                ppu.buttons.b = pressed;
-                            }
-                            ButtonName::Up => {
-                                ppu.buttons.up = pressed;
-                            }
-                            ButtonName::Down => {
-                                ppu.buttons.down = pressed;
-                            }
-                            ButtonName::Left => {
-                                ppu.buttons.left = pressed;
-                            }
-                            ButtonName::Right => {
-                                ppu.buttons.right = pressed;
+            if let Some(control_rx) = &control_rx {
+                while let Ok(msg) = control_rx.try_recv() {
+                    if let Message::Pause(true) = msg {
+                        outputs.show_paused();
+                        // keep handling messages until we're unpaused
+                        loop {
+                            match control_rx.recv().expect("sender closed") {
+                                Message::Pause(true) => {}
+                                Message::Pause(false) => break,
+                                Message::Quit => {
+                                    outputs.finish();
+                                    return Ok(());
+                                }
+                                msg => handle_message(msg, &mut ppu, &mut outputs, &mut settings),
                             }
-                            ButtonName::Start => {
-                                ppu.buttons.start = pressed;
-                            }
-                            ButtonName::Select => {
-                                ppu.buttons.select = pressed;
-                            }
-                        },
-                        Message::Pause(true) => {
-                            while let Message::Pause(true) =
-                                buttons_rx.recv().expect("sender closed")
-                            {
-                            }
-                            // skip over previous iterations
-                            last_tick = Instant::now();
                         }
-                        Message::Pause(false) => {}
+                        // skip over previous iterations
+                        last_tick = Instant::now();
+                    } else if let Message::Quit = msg {
+                        outputs.finish();
+                        return Ok(());
+                    } else {
+                        handle_message(msg, &mut ppu, &mut outputs, &mut settings);
                     }
                 }
             }
 
             if let Err(e) = cpu.tick(&mut ppu) {
                 log::warn!("cpu stopped");
+                outputs.finish();
                 return Err(e);
             }
 
             for _ in 0..3 {
-                ppu.update(cpu, writer);
+                ppu.update(cpu, &mut outputs);
             }
         }
 
@@ -84,10 +360,15 @@ fn run_ppu<CPU: Cpu>(
 
         if let Some(max_cycles) = max_cycles {
             if cycles > max_cycles {
+                outputs.finish();
                 break Ok(());
             }
         }
 
+        if settings.unlimited_speed {
+            continue;
+        }
+
         let now = Instant::now();
         busy_time += now.duration_since(last_tick);
 
@@ -98,10 +379,11 @@ fn run_ppu<CPU: Cpu>(
         } else if cycles % 1000 == 0
             && (busy_time - expected_time_spent) > Duration::from_secs_f64(0.2)
         {
-            println!(
+            log::debug!(
                 "emulation behind by {:?}. trying to catch up...",
                 busy_time - expected_time_spent
             );
+            outputs.osd.show_message("Running slow, catching up");
         }
 
         last_tick = now;
@@ -109,6 +391,11 @@ fn run_ppu<CPU: Cpu>(
 }
 
 /// Like [`run_cpu_headless`], but takes a cycle limit after which the function returns.
+/// Because the run has a fixed length, it isn't limited to the speed of a real NES
+/// but runs as fast as possible.
+///
+/// To record a video of a headless run, use [`run_cpu_with_sink`] with [`Settings::unlimited_speed`]
+/// and start the recording with [`Settings::control`] before running.
 pub fn run_cpu_headless_for<CPU>(
     cpu: &mut CPU,
     mirroring: Mirroring,
@@ -117,9 +404,69 @@ pub fn run_cpu_headless_for<CPU>(
 where
     CPU: Cpu + 'static,
 {
-    let (_, mut writer) = Screen::dummy();
+    run_cpu_with_sink(
+        cpu,
+        mirroring,
+        &mut DummySink,
+        unlimited_speed(),
+        Some(cycle_limit),
+    )
+}
 
-    run_ppu(mirroring, cpu, &mut writer, Some(cycle_limit))
+fn unlimited_speed() -> Settings {
+    let mut settings = Settings::default();
+    settings.unlimited_speed = true;
+    settings
+}
+
+/// Like [`run_cpu_headless_for`], but keeps the frames the PPU draws in memory.
+/// This makes it possible to check what is drawn in tests, where [`run_cpu`] can't be used.
+///
+/// `on_frame` is called with every frame as soon as it's completed. When the cycle limit
+/// is reached, the last completed frame is returned (or `None` if no frame was completed yet).
+pub fn run_cpu_headless_capture<CPU>(
+    cpu: &mut CPU,
+    mirroring: Mirroring,
+    cycle_limit: usize,
+    on_frame: impl FnMut(&CapturedFrame),
+) -> Result<Option<CapturedFrame>, CPU::TickError>
+where
+    CPU: Cpu + 'static,
+{
+    let mut capture = FrameCapture::new(on_frame);
+
+    run_cpu_with_sink(
+        cpu,
+        mirroring,
+        &mut capture,
+        unlimited_speed(),
+        Some(cycle_limit),
+    )?;
+
+    Ok(capture.into_last_frame())
+}
+
+/// Like [`run_cpu_headless_for`], but hashes every frame the PPU draws (see [`Frame::hash`]).
+/// Compare the log with the one of an earlier run to find the first frame that's drawn differently.
+pub fn run_cpu_headless_hashes<CPU>(
+    cpu: &mut CPU,
+    mirroring: Mirroring,
+    cycle_limit: usize,
+) -> Result<FrameHashLog, CPU::TickError>
+where
+    CPU: Cpu + 'static,
+{
+    let mut log = FrameHashLog::new();
+
+    run_cpu_with_sink(
+        cpu,
+        mirroring,
+        &mut log,
+        unlimited_speed(),
+        Some(cycle_limit),
+    )?;
+
+    Ok(log)
 }
 
 /// Runs the cpu as if connected to a PPU, but doesn't actually open
@@ -128,9 +475,23 @@ pub fn run_cpu_headless<CPU>(cpu: &mut CPU, mirroring: Mirroring) -> Result<(),
 where
     CPU: Cpu + 'static,
 {
-    let (_, mut writer) = Screen::dummy();
+    run_cpu_with_sink(cpu, mirroring, &mut DummySink, Settings::default(), None)
+}
 
-    run_ppu(mirroring, cpu, &mut writer, None)
+/// Runs the cpu with the ppu at the correct rate, drawing into your own [`FrameSink`]
+/// instead of a window. When a cycle limit is given, the function returns after that many cycles.
+/// Otherwise, it only returns when the cpu returns an error.
+pub fn run_cpu_with_sink<CPU>(
+    cpu: &mut CPU,
+    mirroring: Mirroring,
+    sink: &mut impl FrameSink,
+    settings: Settings,
+    cycle_limit: Option<usize>,
+) -> Result<(), CPU::TickError>
+where
+    CPU: Cpu + 'static,
+{
+    run_ppu(mirroring, cpu, sink, settings, cycle_limit)
 }
 
 /// Runs the cpu with the ppu. Takes ownership of the cpu, creates
@@ -141,26 +502,72 @@ where
 ///
 /// # Panics
 /// [`run_cpu`] can panic when the `cpu` returns an Error
-pub fn run_cpu<CPU>(mut cpu: CPU, mirroring: Mirroring)
+pub fn run_cpu<CPU>(cpu: CPU, mirroring: Mirroring)
+where
+    CPU: Cpu + Send + 'static,
+{
+    run_cpu_with_settings(cpu, mirroring, Settings::default())
+}
+
+/// Like [`run_cpu`], but with [`Settings`] for how the output is shown.
+///
+/// # Panics
+/// [`run_cpu_with_settings`] can panic when the `cpu` returns an Error
+pub fn run_cpu_with_settings<CPU>(mut cpu: CPU, mirroring: Mirroring, settings: Settings)
 where
     CPU: Cpu + Send + 'static,
 {
     env::set_var("WAYLAND_DISPLAY", "wayland-1");
 
+    // start with a window that shows every pixel of the picture a whole number of times,
+    // so it has the right shape
+    let (width, height) = settings.filters.output_size(settings.overscan);
+    let scale = (720 / height).max(1);
+
     let event_loop = EventLoop::new();
     let window = WindowBuilder::new()
         .with_title("NES")
+        .with_inner_size(LogicalSize::new(width * scale, height * scale))
+        .with_min_inner_size(LogicalSize::new(width, height))
         .build(&event_loop)
         .expect("failed to create window");
+    let window = Arc::new(window);
 
-    let window_size = window.inner_size();
-    let surface_texture = SurfaceTexture::new(window_size.width, window_size.height, &window);
-    let pixels = Pixels::new(WIDTH, HEIGHT, surface_texture).expect("failed to create surface");
+    let gpu = || {
+        let window_size = window.inner_size();
+        let surface_texture = SurfaceTexture::new(window_size.width, window_size.height, &*window);
+        Pixels::new(width, height, surface_texture)
+    };
+    #[cfg(feature = "software")]
+    let software = || {
+        Screen::software(window.clone(), width, height).expect("failed to create software surface")
+    };
 
-    let (mut screen, mut writer, control_tx) = Screen::new(pixels, window);
+    let (mut screen, mut sink) = match settings.window_backend {
+        WindowBackend::Gpu => Screen::new(gpu().expect("failed to create surface"), window.clone()),
+        #[cfg(feature = "software")]
+        WindowBackend::Software => software(),
+        #[cfg(feature = "software")]
+        WindowBackend::Auto => match gpu() {
+            Ok(pixels) => Screen::new(pixels, window.clone()),
+            Err(e) => {
+                log::warn!("failed to create GPU surface, drawing in software instead: {e}");
+                software()
+            }
+        },
+        #[cfg(not(feature = "software"))]
+        WindowBackend::Software => {
+            panic!("drawing the window in software needs the `software` feature")
+        }
+        #[cfg(not(feature = "software"))]
+        WindowBackend::Auto => {
+            Screen::new(gpu().expect("failed to create surface"), window.clone())
+        }
+    };
+    let control = settings.control();
 
     let handle = Arc::new(Mutex::new(Some(thread::spawn(move || {
-        match run_ppu(mirroring, &mut cpu, &mut writer, None) {
+        match run_ppu(mirroring, &mut cpu, &mut sink, settings, None) {
             Ok(_) => unreachable!(),
             Err(e) => {
                 panic!("cpu implementation returned an error: {e}")
@@ -185,7 +592,7 @@ where
                 event: WindowEvent::Focused(f),
                 ..
             } => {
-                control_tx.send(Message::Pause(!f)).expect("failed to send");
+                control.send(Message::Pause(!f));
             }
             Event::WindowEvent {
                 event: WindowEvent::KeyboardInput { input, .. },
@@ -194,68 +601,61 @@ where
                 if let Some(code) = input.virtual_keycode {
                     match code {
                         VirtualKeyCode::Left | VirtualKeyCode::A => {
-                            control_tx
-                                .send(Message::Button(
-                                    ButtonName::Left,
-                                    input.state == ElementState::Pressed,
-                                ))
-                                .expect("failed to send");
+                            control.send(Message::Button(
+                                ButtonName::Left,
+                                input.state == ElementState::Pressed,
+                            ));
                         }
                         VirtualKeyCode::Up | VirtualKeyCode::W => {
-                            control_tx
-                                .send(Message::Button(
-                                    ButtonName::Up,
-                                    input.state == ElementState::Pressed,
-                                ))
-                                .expect("failed to send");
+                            control.send(Message::Button(
+                                ButtonName::Up,
+                                input.state == ElementState::Pressed,
+                            ));
                         }
                         VirtualKeyCode::Right | VirtualKeyCode::D => {
-                            control_tx
-                                .send(Message::Button(
-                                    ButtonName::Right,
-                                    input.state == ElementState::Pressed,
-                                ))
-                                .expect("failed to send");
+                            control.send(Message::Button(
+                                ButtonName::Right,
+                                input.state == ElementState::Pressed,
+                            ));
                         }
                         VirtualKeyCode::Down | VirtualKeyCode::S => {
-                            control_tx
-                                .send(Message::Button(
-                                    ButtonName::Down,
-                                    input.state == ElementState::Pressed,
-                                ))
-                                .expect("failed to send");
+                            control.send(Message::Button(
+                                ButtonName::Down,
+                                input.state == ElementState::Pressed,
+                            ));
                         }
                         VirtualKeyCode::Return => {
-                            control_tx
-                                .send(Message::Button(
-                                    ButtonName::Start,
-                                    input.state == ElementState::Pressed,
-                                ))
-                                .expect("failed to send");
+                            control.send(Message::Button(
+                                ButtonName::Start,
+                                input.state == ElementState::Pressed,
+                            ));
                         }
                         VirtualKeyCode::RShift | VirtualKeyCode::LShift => {
-                            control_tx
-                                .send(Message::Button(
-                                    ButtonName::Select,
-                                    input.state == ElementState::Pressed,
-                                ))
-                                .expect("failed to send");
+                            control.send(Message::Button(
+                                ButtonName::Select,
+                                input.state == ElementState::Pressed,
+                            ));
                         }
                         VirtualKeyCode::Z => {
-                            control_tx
-                                .send(Message::Button(
-                                    ButtonName::B,
-                                    input.state == ElementState::Pressed,
-                                ))
-                                .expect("failed to send");
+                            control.send(Message::Button(
+                                ButtonName::B,
+                                input.state == ElementState::Pressed,
+                            ));
                         }
                         VirtualKeyCode::X => {
-                            control_tx
-                                .send(Message::Button(
-                                    ButtonName::A,
-                                    input.state == ElementState::Pressed,
-                                ))
-                                .expect("failed to send");
+                            control.send(Message::Button(
+                                ButtonName::A,
+                                input.state == ElementState::Pressed,
+                            ));
+                        }
+                        VirtualKeyCode::F12 if input.state == ElementState::Pressed => {
+                            control.screenshot();
+                        }
+                        VirtualKeyCode::F9 if input.state == ElementState::Pressed => {
+                            control.send(Message::ToggleRecording);
+                        }
+                        VirtualKeyCode::F10 if input.state == ElementState::Pressed => {
+                            control.send(Message::ToggleGif);
                         }
                         _ => {}
                     }
@@ -281,3 +681,155 @@ where
         }
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ppu::registers::PpuRegister;
+    use crate::testing::TestCpu;
+
+    #[test]
+    fn headless_capture_keeps_frames() {
+        let mut cpu = TestCpu::new(|ppu, cpu| {
+            // the backdrop color
+            ppu.write_ppu_register(PpuRegister::Address, 0x3f, cpu);
+            ppu.write_ppu_register(PpuRegister::Address, 0x00, cpu);
+            ppu.write_ppu_register(PpuRegister::Data, 0x21, cpu);
+            ppu.write_ppu_register(PpuRegister::Mask, 0b0000_1000, cpu);
+        });
+
+        let mut numbers = Vec::new();
+        let last = run_cpu_headless_capture(&mut cpu, Mirroring::Horizontal, 29781 * 3, |frame| {
+            numbers.push(frame.number)
+        })
+        .unwrap()
+        .expect("frames were completed");
+
+        assert_eq!(numbers, [0, 1, 2]);
+        assert_eq!(last.number, 2);
+        assert!(last.frame.pixels().iter().all(|pixel| *pixel == 0x21));
+        let color = last.palette.color(0x21);
+        assert_eq!(&last.to_rgba()[..4], &[color.0, color.1, color.2, 0xff]);
+    }
+
+    /// Takes the given number of bytes, then fails
+    struct FailingWriter(usize);
+
+    impl Write for FailingWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.0 == 0 {
+                return Err(io::Error::other("disk full"));
+            }
+            let written = buf.len().min(self.0);
+            self.0 -= written;
+            Ok(written)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn outputs<'a, S: FrameSink>(sink: &'a mut S, settings: &Settings) -> Outputs<'a, S> {
+        Outputs {
+            sink,
+            pipeline: Pipeline::new(settings.filters.clone(), settings.overscan, None),
+            recorder: None,
+            gif: None,
+            osd: Osd::new(settings.osd),
+            with_osd: Image::default(),
+        }
+    }
+
+    #[test]
+    fn failed_recordings_are_stopped() {
+        let settings = Settings::default();
+        let mut sink = DummySink;
+        let mut outputs = outputs(&mut sink, &settings);
+
+        // enough for the header, but not for a frame
+        let writer = FailingWriter(100);
+        outputs.start_recording(RecordingTarget::Writer(Box::new(writer)), &settings);
+        assert!(outputs.recorder.is_some());
+        assert!(outputs.osd.recording);
+
+        outputs.frame_completed(&Frame::default(), &Palette::default());
+        assert!(outputs.recorder.is_none());
+        assert!(!outputs.osd.recording);
+    }
+
+    /// Counts the images it gets
+    struct ImageCounter(usize);
+
+    impl FrameSink for ImageCounter {
+        fn frame_completed(&mut self, _frame: &Frame, _palette: &Palette) {}
+
+        fn image_completed(&mut self, _image: &Image) {
+            self.0 += 1;
+        }
+
+        fn wants_images(&self) -> bool {
+            true
+        }
+    }
+
+    #[test]
+    fn images_are_only_rendered_when_the_sink_wants_them() {
+        let settings = Settings::default();
+
+        let mut sink = DummySink;
+        let mut headless = outputs(&mut sink, &settings);
+        headless.frame_completed(&Frame::default(), &Palette::default());
+        assert!(headless.pipeline.last_image().is_none());
+
+        let mut sink = ImageCounter(0);
+        let mut window = outputs(&mut sink, &settings);
+        window.frame_completed(&Frame::default(), &Palette::default());
+        assert!(window.pipeline.last_image().is_some());
+        assert_eq!(sink.0, 1);
+    }
+
+    #[cfg(feature = "png")]
+    #[test]
+    fn screenshots_are_cropped_and_use_their_palette() {
+        use crate::{Overscan, PaletteGenerator};
+
+        let mut cpu = TestCpu::new(|_, _| {});
+        let mut ppu = Ppu::new(Mirroring::Horizontal);
+        // the backdrop color
+        ppu.write_ppu_register(PpuRegister::Address, 0x3f, &mut cpu);
+        ppu.write_ppu_register(PpuRegister::Address, 0x00, &mut cpu);
+        ppu.write_ppu_register(PpuRegister::Data, 0x21, &mut cpu);
+        ppu.write_ppu_register(PpuRegister::Mask, 0b0000_1000, &mut cpu);
+        for _ in 0..341 * 262 {
+            ppu.update(&mut cpu, &mut DummySink);
+        }
+
+        let palette = PaletteGenerator {
+            hue: 90.0,
+            ..Default::default()
+        }
+        .generate();
+        let mut settings = Settings::default();
+        settings.overscan = Overscan::NTSC;
+        settings.screenshots.palette = Some(palette.clone());
+        let mut sink = DummySink;
+        let mut outputs = outputs(&mut sink, &settings);
+
+        let path = env::temp_dir().join(format!("screenshot-{}.png", std::process::id()));
+        let message = Message::Screenshot(Some(path.clone()));
+        handle_message(message, &mut ppu, &mut outputs, &mut settings);
+
+        let decoder = png::Decoder::new(File::open(&path).unwrap());
+        let mut reader = decoder.read_info().unwrap();
+        let mut rgba = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut rgba).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!((info.width, info.height), (256, 224));
+        assert_eq!(info.color_type, png::ColorType::Rgba);
+        let (r, g, b) = palette.color(0x21);
+        assert_ne!((r, g, b), Palette::default().color(0x21));
+        assert!(rgba.chunks_exact(4).all(|pixel| pixel == [r, g, b, 0xff]));
+    }
+}
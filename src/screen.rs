@@ -1,8 +1,17 @@
-use crate::ppu::colors::Color;
-use crate::WIDTH;
+use crate::filter::Filters;
+use crate::image::Image;
+use crate::ppu::colors::Palette;
+use crate::ppu::frame::Frame;
+use crate::recording::RecordingTarget;
+use crate::sink::FrameSink;
+#[cfg(feature = "software")]
+use crate::software::SoftwarePresenter;
+use crate::triple_buffer::{triple_buffer, Reader, Writer};
 use pixels::Pixels;
-use std::sync::mpsc::{channel, Receiver, Sender};
-use std::sync::{Arc, Mutex};
+#[cfg(feature = "software")]
+use softbuffer::SoftBufferError;
+use std::path::PathBuf;
+use std::sync::Arc;
 use winit::window::Window;
 
 /// A struct containg all the buttons for one controller and whether they are pressed (`true`) or not (`false`)
@@ -17,6 +26,8 @@ use winit::window::Window;
 /// - right: arrow right and D
 /// - select: shift
 /// - start: enter
+///
+/// In the terminal (see `run_cpu_in_terminal`), select is tab instead.
 #[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
 pub struct Buttons {
     pub a: bool,
@@ -57,90 +68,148 @@ pub enum ButtonName {
     Select,
 }
 
-pub enum ScreenReader {
-    Dummy,
-    Real {
-        pixels: Box<Mutex<Pixels>>,
-        window: Window,
-    },
+/// How the window opened by [`run_cpu`](crate::run_cpu) draws the picture
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
+pub enum WindowBackend {
+    /// Uses the GPU when there is one, and software otherwise. This is the default.
+    ///
+    /// Without the `software` feature, this is the same as [`WindowBackend::Gpu`].
+    #[default]
+    Auto,
+    /// Draws with the GPU (through `wgpu`), and panics when there's no GPU that supports it
+    Gpu,
+    /// Copies the picture into the window in software. Slower, but works everywhere,
+    /// like on machines without a GPU or in a virtual X server.
+    ///
+    /// Needs the `software` feature, and panics without it.
+    Software,
 }
 
 pub enum Message {
     Button(ButtonName, bool),
     Pause(bool),
+    /// Save a screenshot, to the given path or a timestamped file in the screenshot directory
+    Screenshot(Option<PathBuf>),
+    /// Start recording a video, stopping the recording that's running (if any)
+    StartRecording(RecordingTarget),
+    StopRecording,
+    /// Start a timestamped recording, or stop the recording that's running
+    ToggleRecording,
+    /// Start capturing a GIF, to the given path or a timestamped file in the GIF directory,
+    /// of the given number of frames or until it's stopped
+    StartGif(Option<PathBuf>, Option<u32>),
+    StopGif,
+    /// Start capturing a timestamped GIF, or stop the GIF that's being captured
+    ToggleGif,
+    SetFilters(Filters),
+    /// Show a message on the on-screen display for a while
+    ShowText(String),
+    /// Stop the emulator, finishing everything that's being recorded.
+    /// Only the terminal sends this, when Esc or Ctrl+C is pressed.
+    #[cfg_attr(not(feature = "terminal"), allow(dead_code))]
+    Quit,
 }
 
-#[derive(Clone)]
-pub struct Screen(Arc<ScreenReader>);
+/// The window opened by [`run_cpu`](crate::run_cpu), which shows the newest picture from its [`WindowSink`]
+pub struct Screen {
+    buffer: Reader<Image>,
+    presenter: Presenter,
+    /// The size of the buffer of the [`Pixels`]
+    size: (u32, u32),
+    // the window has to stay alive for as long as we draw to its surface
+    _window: Arc<Window>,
+}
 
-pub enum ScreenWriter {
-    Dummy,
-    Real {
-        screen: Screen,
-        pixels: Vec<u8>,
-        control_rx: Receiver<Message>,
-    },
+/// How the [`Screen`] gets the picture in the window
+enum Presenter {
+    Gpu(Box<Pixels>),
+    #[cfg(feature = "software")]
+    Software(SoftwarePresenter),
 }
 
-impl ScreenWriter {
-    pub fn draw_pixel(&mut self, x: usize, y: usize, color: Color) {
-        if let Self::Real { pixels, .. } = self {
-            pixels[4 * (y * WIDTH as usize + x)] = color.0;
-            pixels[4 * (y * WIDTH as usize + x) + 1] = color.1;
-            pixels[4 * (y * WIDTH as usize + x) + 2] = color.2;
-            pixels[4 * (y * WIDTH as usize + x) + 3] = 0xff;
-        }
+/// The [`FrameSink`] that draws into the window opened by [`run_cpu`](crate::run_cpu).
+/// It hands every picture over without waiting for the window, which picks up the newest one when it redraws.
+pub struct WindowSink {
+    buffer: Writer<Image>,
+}
+
+impl FrameSink for WindowSink {
+    fn frame_completed(&mut self, _frame: &Frame, _palette: &Palette) {}
+
+    fn image_completed(&mut self, image: &Image) {
+        self.buffer.back_mut().clone_from(image);
+        self.buffer.publish();
     }
 
-    pub fn render_frame(&mut self) {
-        if let Self::Real { pixels, screen, .. } = self {
-            if let ScreenReader::Real {
-                pixels: reader_pixels,
-                ..
-            } = &*screen.0
-            {
-                reader_pixels
-                    .lock()
-                    .expect("failed to lock")
-                    .frame_mut()
-                    .clone_from_slice(pixels);
-            }
-        }
+    fn wants_images(&self) -> bool {
+        true
     }
 }
 
 impl Screen {
-    pub fn dummy() -> (Screen, ScreenWriter) {
-        (Screen(Arc::new(ScreenReader::Dummy)), ScreenWriter::Dummy)
+    pub fn new(pixels: Pixels, window: Arc<Window>) -> (Self, WindowSink) {
+        let size = (pixels.texture().width(), pixels.texture().height());
+        Self::with_presenter(Presenter::Gpu(Box::new(pixels)), window, size)
     }
 
-    pub fn new(pixels: Pixels, window: Window) -> (Self, ScreenWriter, Sender<Message>) {
-        let buf = pixels.frame().to_vec();
-        let (tx, rx) = channel();
-
-        let screen = Screen(Arc::new(ScreenReader::Real {
-            pixels: Box::new(Mutex::new(pixels)),
+    /// A screen that draws in software, instead of with [`Pixels`]
+    #[cfg(feature = "software")]
+    pub fn software(
+        window: Arc<Window>,
+        width: u32,
+        height: u32,
+    ) -> Result<(Self, WindowSink), SoftBufferError> {
+        let presenter = SoftwarePresenter::new(window.clone())?;
+        Ok(Self::with_presenter(
+            Presenter::Software(presenter),
             window,
-        }));
+            (width, height),
+        ))
+    }
+
+    fn with_presenter(
+        presenter: Presenter,
+        window: Arc<Window>,
+        size: (u32, u32),
+    ) -> (Self, WindowSink) {
+        let (writer, reader) = triple_buffer(Image::new(size.0, size.1));
 
         (
-            screen.clone(),
-            ScreenWriter::Real {
-                screen,
-                pixels: buf,
-                control_rx: rx,
+            Screen {
+                buffer: reader,
+                presenter,
+                size,
+                _window: window,
             },
-            tx,
+            WindowSink { buffer: writer },
         )
     }
 
     pub fn redraw(&mut self) {
-        if let ScreenReader::Real { pixels, .. } = &*self.0 {
-            pixels
-                .lock()
-                .expect("failed to lock")
-                .render()
-                .expect("failed to render using pixels library");
+        let new = self.buffer.update();
+        let image = self.buffer.front();
+
+        match &mut self.presenter {
+            Presenter::Gpu(pixels) => {
+                if new {
+                    let size = (image.width(), image.height());
+                    if size != self.size {
+                        pixels
+                            .resize_buffer(size.0, size.1)
+                            .expect("failed to resize the window buffer");
+                        self.size = size;
+                    }
+                    pixels.frame_mut().copy_from_slice(image.rgba());
+                }
+
+                pixels
+                    .render()
+                    .expect("failed to render using pixels library");
+            }
+            #[cfg(feature = "software")]
+            Presenter::Software(presenter) => presenter
+                .present(image)
+                .expect("failed to draw the window in software"),
         }
     }
 }
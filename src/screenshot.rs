@@ -0,0 +1,139 @@
+use crate::Palette;
+#[cfg(feature = "png")]
+use std::fs::File;
+use std::io;
+#[cfg(feature = "png")]
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Settings for screenshots taken with [`Control::screenshot`](crate::Control::screenshot)
+/// or by pressing F12 in the window.
+#[derive(Debug, Clone)]
+pub struct ScreenshotSettings {
+    /// Where screenshots are saved. The current directory by default.
+    pub directory: PathBuf,
+    /// Whether the edges cut off by [`Settings::overscan`](crate::Settings::overscan) are left out
+    /// of screenshots too. `true` by default.
+    pub crop_overscan: bool,
+    /// Whether the [`Settings::filters`](crate::Settings::filters) are applied to screenshots too.
+    /// `true` by default.
+    pub filtered: bool,
+    /// The palette used for screenshots. When `None` (the default), the palette
+    /// that's currently selected on the PPU is used.
+    pub palette: Option<Palette>,
+    /// Whether the on-screen display (see [`Settings::osd`](crate::Settings::osd)) is in screenshots too.
+    /// Only works for screenshots that look like the window. `false` by default.
+    pub include_osd: bool,
+    /// Whether the controller of the on-screen display (see
+    /// [`OsdSettings::input_display`](crate::OsdSettings::input_display)) is in screenshots too.
+    /// Only works for screenshots that look like the window. `false` by default.
+    pub include_input_display: bool,
+}
+
+impl Default for ScreenshotSettings {
+    fn default() -> Self {
+        Self {
+            directory: PathBuf::from("."),
+            crop_overscan: true,
+            filtered: true,
+            palette: None,
+            include_osd: false,
+            include_input_display: false,
+        }
+    }
+}
+
+/// Writes an RGBA image to a PNG file
+#[cfg(feature = "png")]
+pub(crate) fn save_png(path: &Path, rgba: &[u8], width: u32, height: u32) -> io::Result<()> {
+    let file = BufWriter::new(File::create(path)?);
+
+    let mut encoder = png::Encoder::new(file, width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(rgba)?;
+    writer.finish()?;
+
+    Ok(())
+}
+
+/// Without the `png` feature there's no encoder, so screenshots can't be saved
+#[cfg(not(feature = "png"))]
+pub(crate) fn save_png(_path: &Path, _rgba: &[u8], _width: u32, _height: u32) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "saving screenshots needs the `png` feature",
+    ))
+}
+
+/// A path in `directory` named after the current (UTC) time, like `nes-2023-09-01-12-30-00-000.png`
+pub(crate) fn timestamped_path(directory: &Path, extension: &str) -> PathBuf {
+    let since_epoch = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+
+    let seconds = since_epoch.as_secs();
+    let (year, month, day) = civil_from_days((seconds / 86400) as i64);
+    let time = seconds % 86400;
+
+    directory.join(format!(
+        "nes-{year:04}-{month:02}-{day:02}-{:02}-{:02}-{:02}-{:03}.{extension}",
+        time / 3600,
+        time / 60 % 60,
+        time % 60,
+        since_epoch.subsec_millis(),
+    ))
+}
+
+/// Converts days since 1970-01-01 to a (year, month, day) date.
+/// See <https://howardhinnant.github.io/date_algorithms.html#civil_from_days>
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn days_are_converted_to_dates() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+        assert_eq!(civil_from_days(59), (1970, 3, 1));
+        // 2000 is a leap year, even though it's divisible by 100
+        assert_eq!(civil_from_days(11_016), (2000, 2, 29));
+        assert_eq!(civil_from_days(11_017), (2000, 3, 1));
+        assert_eq!(civil_from_days(19_782), (2024, 2, 29));
+        assert_eq!(civil_from_days(19_783), (2024, 3, 1));
+    }
+
+    #[test]
+    fn paths_are_named_after_the_time() {
+        let path = timestamped_path(Path::new("shots"), "png");
+        let name = path.file_name().unwrap().to_str().unwrap();
+
+        assert_eq!(path.parent(), Some(Path::new("shots")));
+        assert!(name.starts_with("nes-20"));
+        assert!(name.ends_with(".png"));
+        // nes-yyyy-mm-dd-hh-mm-ss-mmm.png
+        assert_eq!(name.len(), 31);
+    }
+}
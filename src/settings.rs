@@ -0,0 +1,154 @@
+use crate::control::{Control, ControlChannel};
+use crate::screen::Message;
+#[cfg(feature = "terminal")]
+use crate::TerminalSettings;
+use crate::{
+    Filters, GifSettings, OsdSettings, Palette, RecordingSettings, ScreenshotSettings,
+    WindowBackend, HEIGHT, WIDTH,
+};
+use std::sync::mpsc::Receiver;
+
+/// How many pixels are cut off at each edge of the picture.
+///
+/// Old TVs didn't show the edges of the picture, and many games leave garbage
+/// there (especially in the top and bottom 8 lines). Cropping those makes
+/// the output look like it did on a TV.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
+pub struct Overscan {
+    pub top: u32,
+    pub bottom: u32,
+    pub left: u32,
+    pub right: u32,
+}
+
+impl Overscan {
+    /// Shows the whole picture. This is the default.
+    pub const NONE: Overscan = Overscan {
+        top: 0,
+        bottom: 0,
+        left: 0,
+        right: 0,
+    };
+
+    /// Hides what a typical NTSC TV hides: the top and bottom 8 lines
+    pub const NTSC: Overscan = Overscan {
+        top: 8,
+        bottom: 8,
+        left: 0,
+        right: 0,
+    };
+
+    /// The width of the picture that's left after cropping. At least one column is always left,
+    /// when more than the whole picture is cropped the right edge is cropped less.
+    pub fn width(&self) -> u32 {
+        let clamped = self.clamped();
+        WIDTH - clamped.left - clamped.right
+    }
+
+    /// The height of the picture that's left after cropping. At least one line is always left,
+    /// when more than the whole picture is cropped the bottom edge is cropped less.
+    pub fn height(&self) -> u32 {
+        let clamped = self.clamped();
+        HEIGHT - clamped.top - clamped.bottom
+    }
+
+    /// The overscan that's actually cropped, which leaves at least one pixel of the picture
+    pub(crate) fn clamped(self) -> Overscan {
+        let top = self.top.min(HEIGHT - 1);
+        let left = self.left.min(WIDTH - 1);
+
+        Overscan {
+            top,
+            bottom: self.bottom.min(HEIGHT - 1 - top),
+            left,
+            right: self.right.min(WIDTH - 1 - left),
+        }
+    }
+}
+
+/// Settings for how the emulator shows its output, used by [`run_cpu_with_settings`](crate::run_cpu_with_settings).
+///
+/// The [`Default`] gives the same behavior as [`run_cpu`](crate::run_cpu).
+#[derive(Debug, Clone, Default)]
+pub struct Settings {
+    /// Which parts of the picture are cut off. This applies to everything that's shown or saved.
+    pub overscan: Overscan,
+    /// The palette the PPU starts with. It can be changed while running
+    /// with [`Ppu::set_palette`](crate::Ppu::set_palette).
+    pub palette: Palette,
+    /// How the picture is changed before it's shown or saved, like upscaling.
+    pub filters: Filters,
+    pub screenshots: ScreenshotSettings,
+    pub recordings: RecordingSettings,
+    pub gifs: GifSettings,
+    /// How the picture fits in the terminal, when running with
+    /// [`run_cpu_in_terminal_with_settings`](crate::run_cpu_in_terminal_with_settings).
+    #[cfg(feature = "terminal")]
+    pub terminal: TerminalSettings,
+    /// How the window draws the picture. By default it uses the GPU when it can.
+    pub window_backend: WindowBackend,
+    /// What the on-screen display shows, like messages and the frame rate
+    pub osd: OsdSettings,
+    /// Run the emulator as fast as possible, instead of at the speed of a real NES.
+    /// Useful for rendering (or recording) without a window.
+    pub unlimited_speed: bool,
+
+    control: ControlChannel,
+}
+
+impl Settings {
+    /// Gives a [`Control`] to control the emulator while it's running with these settings.
+    /// This can be called as many times as needed, but only the first emulator that
+    /// runs with these settings (or a clone of them) can be controlled.
+    pub fn control(&self) -> Control {
+        self.control.control()
+    }
+
+    pub(crate) fn take_control_receiver(&self) -> Option<Receiver<Message>> {
+        self.control.take_receiver()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cropping_makes_the_picture_smaller() {
+        assert_eq!(
+            (Overscan::NONE.width(), Overscan::NONE.height()),
+            (256, 240)
+        );
+        assert_eq!(
+            (Overscan::NTSC.width(), Overscan::NTSC.height()),
+            (256, 224)
+        );
+
+        let sides = Overscan {
+            left: 8,
+            right: 16,
+            ..Overscan::NONE
+        };
+        assert_eq!((sides.width(), sides.height()), (232, 240));
+    }
+
+    #[test]
+    fn at_least_one_pixel_is_left() {
+        let too_much = Overscan {
+            top: 200,
+            bottom: 100,
+            left: 256,
+            right: u32::MAX,
+        };
+        assert_eq!((too_much.width(), too_much.height()), (1, 1));
+        assert_eq!(
+            too_much.clamped(),
+            Overscan {
+                top: 200,
+                bottom: 39,
+                left: 255,
+                right: 0,
+            }
+        );
+    }
+}
@@ -0,0 +1,123 @@
+use crate::{Buttons, Frame, Image, Palette};
+
+/// Something the [`Ppu`](crate::Ppu) draws its output into, like a window.
+///
+/// Implement this to show the output of the emulator in your own frontend,
+/// or to record it. The window opened by [`run_cpu`](crate::run_cpu) is an implementation of this
+/// trait as well. Use [`run_cpu_with_sink`](crate::run_cpu_with_sink) to run the emulator with your own sink.
+pub trait FrameSink {
+    /// Called at the start of every vblank, when the PPU completed a frame.
+    /// The `palette` is the palette that's currently selected on the PPU
+    /// (see [`Ppu::set_palette`](crate::Ppu::set_palette)).
+    fn frame_completed(&mut self, frame: &Frame, palette: &Palette);
+
+    /// Called right after [`FrameSink::frame_completed`], with the frame as it's shown in the window:
+    /// with the colors looked up, cropped and with the [`Settings::filters`](crate::Settings::filters)
+    /// applied. Only called when [`FrameSink::wants_images`] returns `true`. Does nothing by default.
+    fn image_completed(&mut self, image: &Image) {
+        let _ = image;
+    }
+
+    /// Whether this sink uses the images passed to [`FrameSink::image_completed`].
+    /// Looking up the colors and applying the filters takes time, so it's only done for
+    /// sinks that return `true` here. Returns `false` by default.
+    fn wants_images(&self) -> bool {
+        false
+    }
+
+    /// Called every time the PPU completed drawing one of the 240 visible lines.
+    /// `frame` is the frame that's being drawn, so only lines up to and including `line`
+    /// are up to date. Does nothing by default.
+    fn scanline_completed(&mut self, line: usize, frame: &Frame, palette: &Palette) {
+        let _ = (line, frame, palette);
+    }
+
+    /// Called once every frame, at the start of vblank right before [`FrameSink::frame_completed`].
+    /// Frontends that take input can return which buttons are pressed on the controller.
+    /// When `None` is returned, which is the default, the buttons stay the same.
+    fn buttons(&mut self) -> Option<Buttons> {
+        None
+    }
+
+    /// Called once every frame, right after [`FrameSink::buttons`] and before
+    /// [`FrameSink::frame_completed`], with the buttons that are pressed on the controller
+    /// from then on. Does nothing by default.
+    fn buttons_updated(&mut self, buttons: Buttons) {
+        let _ = buttons;
+    }
+}
+
+/// A [`FrameSink`] that throws away everything the PPU draws.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct DummySink;
+
+impl FrameSink for DummySink {
+    fn frame_completed(&mut self, _frame: &Frame, _palette: &Palette) {}
+}
+
+/// A frame drawn by the PPU, see [`FrameCapture`].
+#[derive(Clone)]
+pub struct CapturedFrame {
+    /// How many frames were completed before this one
+    pub number: u64,
+    /// The frame, as the colors the PPU outputs
+    pub frame: Frame,
+    /// The palette the PPU used when this frame was completed
+    pub palette: Palette,
+}
+
+impl CapturedFrame {
+    /// The frame as RGBA, 4 bytes per pixel, row by row. See [`Frame::to_rgba`].
+    pub fn to_rgba(&self) -> Vec<u8> {
+        self.frame.to_rgba(&self.palette)
+    }
+}
+
+/// A [`FrameSink`] that keeps the last frame in memory, and calls a function
+/// with every frame as soon as it's completed.
+pub struct FrameCapture<F> {
+    last: Option<CapturedFrame>,
+    frames: u64,
+    on_frame: F,
+}
+
+impl<F: FnMut(&CapturedFrame)> FrameCapture<F> {
+    pub fn new(on_frame: F) -> Self {
+        Self {
+            last: None,
+            frames: 0,
+            on_frame,
+        }
+    }
+
+    /// The last frame that was completed, if any
+    pub fn last_frame(&self) -> Option<&CapturedFrame> {
+        self.last.as_ref()
+    }
+
+    pub fn into_last_frame(self) -> Option<CapturedFrame> {
+        self.last
+    }
+}
+
+impl<F: FnMut(&CapturedFrame)> FrameSink for FrameCapture<F> {
+    fn frame_completed(&mut self, frame: &Frame, palette: &Palette) {
+        let last = match &mut self.last {
+            // reuse the buffers of the previous frame
+            Some(last) => {
+                last.number = self.frames;
+                last.frame.clone_from(frame);
+                last.palette.clone_from(palette);
+                last
+            }
+            None => self.last.insert(CapturedFrame {
+                number: self.frames,
+                frame: frame.clone(),
+                palette: palette.clone(),
+            }),
+        };
+
+        self.frames += 1;
+        (self.on_frame)(last);
+    }
+}
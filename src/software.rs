@@ -0,0 +1,152 @@
+use crate::image::Image;
+use raw_window_handle::{HasRawDisplayHandle, HasRawWindowHandle};
+use raw_window_handle_06 as rwh06;
+use softbuffer::{Context, SoftBufferError, Surface};
+use std::num::{NonZeroIsize, NonZeroU32};
+use std::ptr::NonNull;
+use std::sync::Arc;
+use winit::window::Window;
+
+/// Shows pictures in a window by copying them in software, for when there's no GPU
+pub(crate) struct SoftwarePresenter {
+    surface: Surface<WindowHandles, WindowHandles>,
+    /// The size of the surface, when last drawn
+    size: (u32, u32),
+}
+
+impl SoftwarePresenter {
+    pub(crate) fn new(window: Arc<Window>) -> Result<Self, SoftBufferError> {
+        let context = Context::new(WindowHandles(window.clone()))?;
+        let surface = Surface::new(&context, WindowHandles(window))?;
+
+        Ok(Self {
+            surface,
+            size: (0, 0),
+        })
+    }
+
+    /// Draws `image` as big as it fits in the window, in the middle, with black bars around it.
+    /// Like the GPU backend, every pixel is shown a whole number of times when the window is big enough.
+    pub(crate) fn present(&mut self, image: &Image) -> Result<(), SoftBufferError> {
+        let window_size = self.surface.window().0.inner_size();
+        let (Some(width), Some(height)) = (
+            NonZeroU32::new(window_size.width),
+            NonZeroU32::new(window_size.height),
+        ) else {
+            // a minimized window has nothing to draw in
+            return Ok(());
+        };
+
+        if self.size != (width.get(), height.get()) {
+            self.surface.resize(width, height)?;
+            self.size = (width.get(), height.get());
+        }
+        let (width, height) = self.size;
+
+        let fit = (width as f64 / image.width() as f64).min(height as f64 / image.height() as f64);
+        let scale = if fit >= 1.0 { fit.floor() } else { fit };
+        let scaled_width = ((image.width() as f64 * scale) as u32).clamp(1, width);
+        let scaled_height = ((image.height() as f64 * scale) as u32).clamp(1, height);
+        let left = (width - scaled_width) / 2;
+        let top = (height - scaled_height) / 2;
+
+        // which column of the image every column of the window shows
+        let columns = (0..scaled_width)
+            .map(|x| (x as u64 * image.width() as u64 / scaled_width as u64) as u32)
+            .collect::<Vec<_>>();
+
+        let mut buffer = self.surface.buffer_mut()?;
+        buffer.fill(0);
+
+        for (y, row) in buffer
+            .chunks_exact_mut(width as usize)
+            .enumerate()
+            .skip(top as usize)
+            .take(scaled_height as usize)
+        {
+            let source_y =
+                ((y as u64 - top as u64) * image.height() as u64 / scaled_height as u64) as u32;
+            let out = &mut row[left as usize..][..scaled_width as usize];
+
+            for (out, &source_x) in out.iter_mut().zip(&columns) {
+                let [r, g, b, _] = image.get(source_x, source_y);
+                *out = u32::from_be_bytes([0, r, g, b]);
+            }
+        }
+
+        buffer.present()
+    }
+}
+
+/// The handles of a winit window, for softbuffer. Winit and softbuffer use different versions
+/// of `raw-window-handle`, so the handles are converted between them.
+#[derive(Clone)]
+struct WindowHandles(Arc<Window>);
+
+impl rwh06::HasWindowHandle for WindowHandles {
+    fn window_handle(&self) -> Result<rwh06::WindowHandle<'_>, rwh06::HandleError> {
+        use raw_window_handle::RawWindowHandle as Old;
+        use rwh06::RawWindowHandle as New;
+
+        let raw = match self.0.raw_window_handle() {
+            Old::Xlib(old) => {
+                let mut new = rwh06::XlibWindowHandle::new(old.window);
+                new.visual_id = old.visual_id;
+                New::Xlib(new)
+            }
+            Old::Xcb(old) => {
+                let window = NonZeroU32::new(old.window).ok_or(rwh06::HandleError::Unavailable)?;
+                let mut new = rwh06::XcbWindowHandle::new(window);
+                new.visual_id = NonZeroU32::new(old.visual_id);
+                New::Xcb(new)
+            }
+            Old::Wayland(old) => {
+                let surface = NonNull::new(old.surface).ok_or(rwh06::HandleError::Unavailable)?;
+                New::Wayland(rwh06::WaylandWindowHandle::new(surface))
+            }
+            Old::Win32(old) => {
+                let hwnd =
+                    NonZeroIsize::new(old.hwnd as isize).ok_or(rwh06::HandleError::Unavailable)?;
+                let mut new = rwh06::Win32WindowHandle::new(hwnd);
+                new.hinstance = NonZeroIsize::new(old.hinstance as isize);
+                New::Win32(new)
+            }
+            Old::AppKit(old) => {
+                let ns_view = NonNull::new(old.ns_view).ok_or(rwh06::HandleError::Unavailable)?;
+                New::AppKit(rwh06::AppKitWindowHandle::new(ns_view))
+            }
+            _ => return Err(rwh06::HandleError::NotSupported),
+        };
+
+        // Safety: the handle belongs to the window, which lives at least as long as `self`
+        Ok(unsafe { rwh06::WindowHandle::borrow_raw(raw) })
+    }
+}
+
+impl rwh06::HasDisplayHandle for WindowHandles {
+    fn display_handle(&self) -> Result<rwh06::DisplayHandle<'_>, rwh06::HandleError> {
+        use raw_window_handle::RawDisplayHandle as Old;
+        use rwh06::RawDisplayHandle as New;
+
+        let raw = match self.0.raw_display_handle() {
+            Old::Xlib(old) => New::Xlib(rwh06::XlibDisplayHandle::new(
+                NonNull::new(old.display),
+                old.screen,
+            )),
+            Old::Xcb(old) => New::Xcb(rwh06::XcbDisplayHandle::new(
+                NonNull::new(old.connection),
+                old.screen,
+            )),
+            Old::Wayland(old) => {
+                let display = NonNull::new(old.display).ok_or(rwh06::HandleError::Unavailable)?;
+                New::Wayland(rwh06::WaylandDisplayHandle::new(display))
+            }
+            Old::Windows(_) => New::Windows(rwh06::WindowsDisplayHandle::new()),
+            Old::AppKit(_) => New::AppKit(rwh06::AppKitDisplayHandle::new()),
+            _ => return Err(rwh06::HandleError::NotSupported),
+        };
+
+        // Safety: the display belongs to the window, which lives at least as long as `self`
+        Ok(unsafe { rwh06::DisplayHandle::borrow_raw(raw) })
+    }
+}
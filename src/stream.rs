@@ -0,0 +1,449 @@
+use crate::ppu::colors::Palette;
+use crate::ppu::frame::Frame;
+use crate::screen::Buttons;
+use crate::settings::Overscan;
+use crate::sink::FrameSink;
+use std::io;
+use std::io::{BufWriter, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::Duration;
+use tungstenite::WebSocket;
+
+/// How many frames can wait to be sent to a client. When a client is slower than this,
+/// frames are skipped for that client until it catches up.
+const QUEUE_LENGTH: usize = 2;
+/// How often the stream checks for new spectators, and whether it should stop listening
+const ACCEPT_INTERVAL: Duration = Duration::from_millis(50);
+/// How long sending to or reading from a client may take before the client is disconnected,
+/// so clients that stop reading don't keep their threads around forever
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// How spectators connect to a [`FrameStream`]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum StreamProtocol {
+    /// Plain TCP. Every message is preceded by its length, as a 32-bit little endian number.
+    Tcp,
+    /// WebSocket, where every message is a binary WebSocket message. For web pages.
+    WebSocket,
+}
+
+/// A [`FrameSink`] that streams every frame to the spectators connected to it, for example to
+/// watch a headless run live. Run the emulator with it using [`run_cpu_with_sink`](crate::run_cpu_with_sink).
+///
+/// Sending happens on other threads, so slow clients don't slow down the emulator:
+/// they miss frames instead. Clients that stop reading for a few seconds are disconnected.
+/// Clients can connect and disconnect at any time.
+///
+/// Every frame is sent as one message, with all numbers in little endian:
+///
+/// | bytes | contents |
+/// |-------|----------|
+/// | 1     | kind: 0 for a key frame, 1 for a delta frame |
+/// | 8     | frame number, counting from 0 |
+/// | 1     | buttons pressed at the end of the frame, where bit `i` is [`Buttons::get_by_index`]`(i)` |
+/// | 2     | width |
+/// | 2     | height |
+/// | rest  | runs of pixels, row by row |
+///
+/// Every run is 4 bytes: how many pixels it covers (1 to 255), then red, green and blue.
+/// In a key frame, that's the color of the pixels. In a delta frame, that's the color XORed with
+/// the color of the same pixel in the frame before, so runs of zeroes are pixels that didn't change.
+/// Clients get a key frame first, and whenever they missed frames.
+///
+/// The frames are the whole picture, with the colors of the palette, without filters or cropping.
+///
+/// When the stream is dropped, it stops listening and disconnects all clients.
+pub struct FrameStream {
+    address: SocketAddr,
+    clients: Vec<StreamClient>,
+    /// Clients that connected since the last frame
+    new_clients: Arc<Mutex<Vec<StreamClient>>>,
+    /// Set to stop accepting clients
+    shutdown: Arc<AtomicBool>,
+    accepting: Option<JoinHandle<()>>,
+    buttons: Buttons,
+    /// How many frames were completed
+    frames: u64,
+    /// The colors of the current frame and the one before, 3 bytes per pixel
+    current: Vec<u8>,
+    previous: Vec<u8>,
+    rgba: Vec<u8>,
+}
+
+struct StreamClient {
+    sender: SyncSender<Arc<Vec<u8>>>,
+    /// Whether the client missed a frame (or didn't get any yet), so it can't use a delta frame
+    needs_key_frame: bool,
+}
+
+impl FrameStream {
+    /// Starts listening for spectators on `address`
+    pub fn bind(address: impl ToSocketAddrs, protocol: StreamProtocol) -> io::Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        let address = listener.local_addr()?;
+        log::info!("streaming frames on {address}");
+
+        // not blocking, so the thread can check whether to stop
+        listener.set_nonblocking(true)?;
+
+        let new_clients = Arc::new(Mutex::new(Vec::new()));
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let accepting = {
+            let clients = new_clients.clone();
+            let shutdown = shutdown.clone();
+            thread::spawn(move || accept(listener, protocol, clients, shutdown))
+        };
+
+        Ok(Self {
+            address,
+            clients: Vec::new(),
+            new_clients,
+            shutdown,
+            accepting: Some(accepting),
+            buttons: Buttons::default(),
+            frames: 0,
+            current: Vec::new(),
+            previous: Vec::new(),
+            rgba: Vec::new(),
+        })
+    }
+
+    /// The address the stream listens on. Useful when binding to port 0.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.address
+    }
+
+    fn encode(&self, delta: bool, buttons: Buttons) -> Vec<u8> {
+        let mut message = Vec::new();
+        message.push(delta as u8);
+        message.extend((self.frames - 1).to_le_bytes());
+        message.push((0..8).fold(0, |bits, index| {
+            bits | (buttons.get_by_index(index) as u8) << index
+        }));
+        message.extend((Overscan::NONE.width() as u16).to_le_bytes());
+        message.extend((Overscan::NONE.height() as u16).to_le_bytes());
+
+        let pixels = self
+            .current
+            .chunks_exact(3)
+            .enumerate()
+            .map(|(index, pixel)| {
+                let mut pixel = [pixel[0], pixel[1], pixel[2]];
+                if delta {
+                    for (channel, previous) in pixel.iter_mut().zip(&self.previous[index * 3..]) {
+                        *channel ^= previous;
+                    }
+                }
+                pixel
+            });
+
+        let mut run: Option<([u8; 3], u8)> = None;
+        for pixel in pixels {
+            match &mut run {
+                Some((color, length)) if *color == pixel && *length < u8::MAX => *length += 1,
+                _ => {
+                    if let Some((color, length)) = run {
+                        message.push(length);
+                        message.extend(color);
+                    }
+                    run = Some((pixel, 1));
+                }
+            }
+        }
+        if let Some((color, length)) = run {
+            message.push(length);
+            message.extend(color);
+        }
+
+        message
+    }
+}
+
+impl FrameSink for FrameStream {
+    fn frame_completed(&mut self, frame: &Frame, palette: &Palette) {
+        self.frames += 1;
+
+        self.clients
+            .append(&mut self.new_clients.lock().expect("failed to lock"));
+        if self.clients.is_empty() {
+            // forget the last frame, so nothing is sent as a delta of it later
+            self.current.clear();
+            return;
+        }
+
+        self.rgba.resize(frame.pixels().len() * 4, 0);
+        frame.write_rgba(palette, Overscan::NONE, &mut self.rgba);
+        std::mem::swap(&mut self.current, &mut self.previous);
+        self.current.clear();
+        self.current.extend(
+            self.rgba
+                .chunks_exact(4)
+                .flat_map(|rgba| [rgba[0], rgba[1], rgba[2]]),
+        );
+
+        // only made when some client needs them
+        let mut key_frame = None;
+        let mut delta_frame = None;
+        // the first frame has nothing to be a delta of
+        let can_delta = self.previous.len() == self.current.len();
+
+        let mut clients = std::mem::take(&mut self.clients);
+        clients.retain_mut(|client| {
+            let message = if client.needs_key_frame || !can_delta {
+                key_frame.get_or_insert_with(|| Arc::new(self.encode(false, self.buttons)))
+            } else {
+                delta_frame.get_or_insert_with(|| Arc::new(self.encode(true, self.buttons)))
+            };
+
+            match client.sender.try_send(message.clone()) {
+                Ok(()) => {
+                    client.needs_key_frame = false;
+                    true
+                }
+                Err(TrySendError::Full(_)) => {
+                    client.needs_key_frame = true;
+                    true
+                }
+                Err(TrySendError::Disconnected(_)) => false,
+            }
+        });
+        self.clients = clients;
+    }
+
+    fn buttons_updated(&mut self, buttons: Buttons) {
+        self.buttons = buttons;
+    }
+}
+
+impl Drop for FrameStream {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::Relaxed);
+        if let Some(accepting) = self.accepting.take() {
+            if accepting.join().is_err() {
+                log::warn!("the thread accepting stream clients panicked");
+            }
+        }
+        // dropping the senders disconnects the clients
+        self.clients.clear();
+        self.new_clients.lock().expect("failed to lock").clear();
+    }
+}
+
+/// Accepts clients until the stream is dropped
+fn accept(
+    listener: TcpListener,
+    protocol: StreamProtocol,
+    clients: Arc<Mutex<Vec<StreamClient>>>,
+    shutdown: Arc<AtomicBool>,
+) {
+    while !shutdown.load(Ordering::Relaxed) {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                let clients = clients.clone();
+                let shutdown = shutdown.clone();
+                thread::spawn(move || connect(stream, protocol, clients, &shutdown));
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => thread::sleep(ACCEPT_INTERVAL),
+            Err(e) => log::warn!("failed to accept stream client: {e}"),
+        }
+    }
+}
+
+/// Sets up a new client, and sends it frames until it disconnects
+fn connect(
+    stream: TcpStream,
+    protocol: StreamProtocol,
+    clients: Arc<Mutex<Vec<StreamClient>>>,
+    shutdown: &AtomicBool,
+) {
+    let peer = stream.peer_addr().ok();
+    if let Err(e) = stream
+        .set_nonblocking(false)
+        .and_then(|()| stream.set_nodelay(true))
+        .and_then(|()| stream.set_read_timeout(Some(CLIENT_TIMEOUT)))
+        .and_then(|()| stream.set_write_timeout(Some(CLIENT_TIMEOUT)))
+    {
+        log::warn!("failed to set up stream client {peer:?}: {e}");
+        return;
+    }
+
+    let connection = match protocol {
+        StreamProtocol::Tcp => Connection::Tcp(BufWriter::new(stream)),
+        StreamProtocol::WebSocket => match tungstenite::accept(stream) {
+            Ok(websocket) => Connection::WebSocket(Box::new(websocket)),
+            Err(e) => {
+                log::info!("stream client {peer:?} failed to connect: {e}");
+                return;
+            }
+        },
+    };
+
+    let (sender, receiver) = sync_channel(QUEUE_LENGTH);
+    {
+        let mut clients = clients.lock().expect("failed to lock");
+        // the stream is gone, so nobody would send this client anything
+        if shutdown.load(Ordering::Relaxed) {
+            return;
+        }
+        clients.push(StreamClient {
+            sender,
+            needs_key_frame: true,
+        });
+    }
+    // only the stream may keep the sender alive, so the client disconnects when it's dropped
+    drop(clients);
+    log::info!("stream client {peer:?} connected");
+
+    let result = connection.send_all(&receiver);
+    match result {
+        Ok(()) => log::info!("stream client {peer:?} disconnected"),
+        Err(e) => log::info!("stream client {peer:?} disconnected: {e}"),
+    }
+}
+
+enum Connection {
+    Tcp(BufWriter<TcpStream>),
+    WebSocket(Box<WebSocket<TcpStream>>),
+}
+
+impl Connection {
+    /// Sends the frames from `receiver` until the emulator stops, or the client disconnects
+    fn send_all(self, receiver: &Receiver<Arc<Vec<u8>>>) -> io::Result<()> {
+        match self {
+            Connection::Tcp(mut writer) => {
+                // the sender only goes away when the stream is dropped
+                while let Ok(message) = receiver.recv() {
+                    writer.write_all(&(message.len() as u32).to_le_bytes())?;
+                    writer.write_all(&message)?;
+                    writer.flush()?;
+                }
+                Ok(())
+            }
+            Connection::WebSocket(mut websocket) => {
+                while let Ok(message) = receiver.recv() {
+                    websocket
+                        .send(tungstenite::Message::Binary(message.to_vec()))
+                        .map_err(io::Error::other)?;
+                }
+                websocket.close(None).map_err(io::Error::other)?;
+                websocket.flush().map_err(io::Error::other)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::time::Instant;
+
+    /// Waits until the stream has `count` clients that didn't get a frame yet
+    fn wait_for_clients(stream: &FrameStream, count: usize) {
+        let start = Instant::now();
+        while stream.new_clients.lock().unwrap().len() < count {
+            assert!(
+                start.elapsed() < Duration::from_secs(5),
+                "client didn't connect"
+            );
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    fn read_message(client: &mut TcpStream) -> Vec<u8> {
+        let mut length = [0; 4];
+        client.read_exact(&mut length).unwrap();
+        let mut message = vec![0; u32::from_le_bytes(length) as usize];
+        client.read_exact(&mut message).unwrap();
+        message
+    }
+
+    #[test]
+    fn sends_key_frames_then_deltas() {
+        let mut stream = FrameStream::bind("127.0.0.1:0", StreamProtocol::Tcp).unwrap();
+        let mut client = TcpStream::connect(stream.local_addr()).unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        wait_for_clients(&stream, 1);
+
+        let palette = Palette::default();
+        stream.buttons_updated(Buttons {
+            a: true,
+            ..Buttons::default()
+        });
+        stream.frame_completed(&Frame::default(), &palette);
+        stream.frame_completed(&Frame::default(), &palette);
+
+        let key_frame = read_message(&mut client);
+        assert_eq!(key_frame[0], 0);
+        assert_eq!(&key_frame[1..9], &0u64.to_le_bytes());
+        assert_eq!(key_frame[9], 0b1);
+        assert_eq!(&key_frame[10..14], &[0, 1, 240, 0]);
+        // every pixel is the same color, in runs of 255
+        let (r, g, b) = palette.color(0);
+        assert_eq!(key_frame.len(), 14 + 4 * (256 * 240usize).div_ceil(255));
+        assert_eq!(&key_frame[14..18], &[255, r, g, b]);
+
+        let delta_frame = read_message(&mut client);
+        assert_eq!(delta_frame[0], 1);
+        assert_eq!(&delta_frame[1..9], &1u64.to_le_bytes());
+        assert_eq!(&delta_frame[14..18], &[255, 0, 0, 0]);
+    }
+
+    #[test]
+    fn frames_without_clients_are_not_converted() {
+        let mut stream = FrameStream::bind("127.0.0.1:0", StreamProtocol::Tcp).unwrap();
+        let palette = Palette::default();
+        stream.frame_completed(&Frame::default(), &palette);
+        assert!(stream.current.is_empty());
+
+        let mut client = TcpStream::connect(stream.local_addr()).unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        wait_for_clients(&stream, 1);
+        stream.frame_completed(&Frame::default(), &palette);
+
+        let key_frame = read_message(&mut client);
+        assert_eq!(key_frame[0], 0);
+        assert_eq!(&key_frame[1..9], &1u64.to_le_bytes());
+    }
+
+    #[test]
+    fn dropping_stops_listening() {
+        let stream = FrameStream::bind("127.0.0.1:0", StreamProtocol::Tcp).unwrap();
+        let address = stream.local_addr();
+        let mut client = TcpStream::connect(address).unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        wait_for_clients(&stream, 1);
+
+        drop(stream);
+
+        // the client is disconnected, and nobody can connect anymore
+        let mut rest = Vec::new();
+        client.read_to_end(&mut rest).unwrap();
+        assert!(rest.is_empty());
+        assert!(TcpStream::connect(address).is_err());
+    }
+
+    #[test]
+    fn clients_that_never_finish_the_handshake_are_disconnected() {
+        let stream = FrameStream::bind("127.0.0.1:0", StreamProtocol::WebSocket).unwrap();
+        let mut client = TcpStream::connect(stream.local_addr()).unwrap();
+        client
+            .set_read_timeout(Some(CLIENT_TIMEOUT + Duration::from_secs(5)))
+            .unwrap();
+
+        let mut rest = Vec::new();
+        client.read_to_end(&mut rest).unwrap();
+        assert!(rest.is_empty());
+    }
+}
@@ -0,0 +1,550 @@
+use crate::control::Control;
+use crate::cpu::Cpu;
+use crate::image::Image;
+use crate::ppu::colors::Palette;
+use crate::ppu::frame::Frame;
+use crate::run::run_cpu_with_sink;
+use crate::screen::{ButtonName, Message};
+use crate::settings::Settings;
+use crate::sink::FrameSink;
+use crate::Mirroring;
+use crossterm::event::{
+    Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers, KeyboardEnhancementFlags,
+    ModifierKeyCode, PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
+};
+use crossterm::style::{Color, Print, ResetColor, SetBackgroundColor, SetForegroundColor};
+use crossterm::terminal::{Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{cursor, event, execute, queue, terminal};
+use std::io;
+use std::io::{Stdout, Write};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long a button stays pressed after the terminal reports a key press, when it can't report
+/// key releases. The first press lasts until the keyboard starts repeating the key, after that
+/// every repeat keeps the button pressed a bit longer.
+const FIRST_PRESS_TIME: Duration = Duration::from_millis(550);
+const REPEAT_PRESS_TIME: Duration = Duration::from_millis(100);
+
+/// The line at the bottom of the terminal, below the picture
+const STATUS_LINE: &str = "F12 screenshot, F9 record, F10 gif, Esc quit";
+
+/// Settings for showing the emulator in a terminal, used by [`run_cpu_in_terminal_with_settings`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct TerminalSettings {
+    /// How many pixels of the picture (in both directions) become one pixel in the terminal.
+    /// Every terminal character shows two pixels, one above the other.
+    /// When `None`, the picture is made as small as needed to fit in the terminal. This is the default.
+    pub downscale: Option<u32>,
+    /// How many times per second the terminal is redrawn, 30 by default. Drawing
+    /// less often saves bandwidth over slow connections. The emulator runs at full speed either way.
+    pub frame_rate: u32,
+}
+
+impl Default for TerminalSettings {
+    fn default() -> Self {
+        Self {
+            downscale: None,
+            frame_rate: 30,
+        }
+    }
+}
+
+/// The [`FrameSink`] that hands the pictures to the terminal
+struct TerminalSink {
+    latest: Arc<Mutex<Option<Image>>>,
+}
+
+impl FrameSink for TerminalSink {
+    fn frame_completed(&mut self, _frame: &Frame, _palette: &Palette) {}
+
+    fn image_completed(&mut self, image: &Image) {
+        let mut latest = self.latest.lock().expect("failed to lock");
+        match &mut *latest {
+            Some(latest) => latest.clone_from(image),
+            None => *latest = Some(image.clone()),
+        }
+    }
+
+    fn wants_images(&self) -> bool {
+        true
+    }
+}
+
+/// Like [`run_cpu`](crate::run_cpu), but shows the picture in the terminal instead of a window,
+/// so it works without a display, for example over SSH. Needs a terminal with 24-bit color.
+///
+/// The keys are the same as in the window (see [`Buttons`](crate::Buttons)), except that select is Tab,
+/// since most terminals don't report the shift key on its own. Esc or Ctrl+C stops the emulator
+/// and returns.
+///
+/// Most terminals only report when a key is pressed, not when it's released. There, a button stays
+/// pressed for as long as the keyboard repeats the key, and a bit longer. Terminals that support
+/// the kitty keyboard protocol report releases, and work just like the window.
+///
+/// # Panics
+/// [`run_cpu_in_terminal`] can panic when the `cpu` returns an Error, or when the terminal can't be used
+pub fn run_cpu_in_terminal<CPU>(cpu: CPU, mirroring: Mirroring)
+where
+    CPU: Cpu + Send + 'static,
+{
+    run_cpu_in_terminal_with_settings(cpu, mirroring, Settings::default())
+}
+
+/// Like [`run_cpu_in_terminal`], but with [`Settings`] for how the output is shown.
+/// How the picture fits in the terminal is set in [`Settings::terminal`].
+///
+/// # Panics
+/// [`run_cpu_in_terminal_with_settings`] can panic when the `cpu` returns an Error,
+/// or when the terminal can't be used
+pub fn run_cpu_in_terminal_with_settings<CPU>(
+    mut cpu: CPU,
+    mirroring: Mirroring,
+    settings: Settings,
+) where
+    CPU: Cpu + Send + 'static,
+{
+    let terminal_settings = settings.terminal;
+    let control = settings.control();
+    let latest = Arc::new(Mutex::new(None));
+
+    let mut sink = TerminalSink {
+        latest: latest.clone(),
+    };
+    let handle = thread::spawn(move || {
+        if let Err(e) = run_cpu_with_sink(&mut cpu, mirroring, &mut sink, settings, None) {
+            panic!("cpu implementation returned an error: {e}")
+        }
+    });
+
+    let mut screen = TerminalScreen::new(terminal_settings).expect("failed to set up the terminal");
+    let mut keys = Keys::new(control.clone(), screen.reports_releases);
+    let wait_time = Duration::from_secs_f64(1.0 / terminal_settings.frame_rate.max(1) as f64);
+    let mut next_draw = Instant::now();
+
+    while !handle.is_finished() {
+        let timeout = keys
+            .next_release()
+            .map_or(next_draw, |release| release.min(next_draw))
+            .saturating_duration_since(Instant::now());
+
+        if event::poll(timeout).expect("failed to read from the terminal") {
+            match event::read().expect("failed to read from the terminal") {
+                Event::Key(key) if is_quit(&key) => {
+                    control.send(Message::Quit);
+                    break;
+                }
+                Event::Key(key) => keys.handle(key, Instant::now()),
+                Event::Resize(..) => screen.clear(),
+                _ => {}
+            }
+        }
+        keys.release_expired(Instant::now());
+
+        if Instant::now() >= next_draw {
+            let image = latest.lock().expect("failed to lock").take();
+            if let Some(image) = image {
+                screen.draw(&image).expect("failed to draw in the terminal");
+            }
+            next_draw += wait_time;
+            // don't try to catch up on draws that were missed
+            next_draw = next_draw.max(Instant::now());
+        }
+    }
+
+    // put the terminal back before any panic of the emulator is printed
+    drop(screen);
+    if let Err(panic) = handle.join() {
+        std::panic::resume_unwind(panic);
+    }
+}
+
+fn is_quit(key: &KeyEvent) -> bool {
+    key.kind == KeyEventKind::Press
+        && (key.code == KeyCode::Esc
+            || (key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL)))
+}
+
+/// Turns the keys reported by the terminal into button presses
+struct Keys {
+    control: Control,
+    /// Whether the terminal reports when keys are released
+    reports_releases: bool,
+    /// The buttons that are pressed, and when they're released if the terminal doesn't tell
+    pressed: Vec<(ButtonName, Option<Instant>)>,
+}
+
+impl Keys {
+    fn new(control: Control, reports_releases: bool) -> Self {
+        Self {
+            control,
+            reports_releases,
+            pressed: Vec::new(),
+        }
+    }
+
+    /// Handles a key the terminal reported at `now`
+    fn handle(&mut self, key: KeyEvent, now: Instant) {
+        if key.kind == KeyEventKind::Press {
+            match key.code {
+                KeyCode::F(12) => return self.control.screenshot(),
+                KeyCode::F(9) => return self.control.send(Message::ToggleRecording),
+                KeyCode::F(10) => return self.control.send(Message::ToggleGif),
+                _ => {}
+            }
+        }
+
+        let Some(button) = button(key.code) else {
+            return;
+        };
+        let index = self
+            .pressed
+            .iter()
+            .position(|(pressed, _)| *pressed == button);
+
+        match (key.kind, index) {
+            (KeyEventKind::Release, Some(index)) => {
+                self.pressed.remove(index);
+                self.control.send(Message::Button(button, false));
+            }
+            (KeyEventKind::Release, None) => {}
+            (_, Some(index)) => {
+                if !self.reports_releases {
+                    self.pressed[index].1 = Some(now + REPEAT_PRESS_TIME);
+                }
+            }
+            (_, None) => {
+                let release = (!self.reports_releases).then(|| now + FIRST_PRESS_TIME);
+                self.pressed.push((button, release));
+                self.control.send(Message::Button(button, true));
+            }
+        }
+    }
+
+    /// When the next button has to be released, if any
+    fn next_release(&self) -> Option<Instant> {
+        self.pressed
+            .iter()
+            .filter_map(|(_, release)| *release)
+            .min()
+    }
+
+    /// Releases the buttons that should be released by `now`
+    fn release_expired(&mut self, now: Instant) {
+        let control = &self.control;
+        self.pressed.retain(|(button, release)| match release {
+            Some(release) if *release <= now => {
+                control.send(Message::Button(*button, false));
+                false
+            }
+            _ => true,
+        });
+    }
+}
+
+fn button(code: KeyCode) -> Option<ButtonName> {
+    Some(match code {
+        KeyCode::Left | KeyCode::Char('a' | 'A') => ButtonName::Left,
+        KeyCode::Up | KeyCode::Char('w' | 'W') => ButtonName::Up,
+        KeyCode::Right | KeyCode::Char('d' | 'D') => ButtonName::Right,
+        KeyCode::Down | KeyCode::Char('s' | 'S') => ButtonName::Down,
+        KeyCode::Enter => ButtonName::Start,
+        KeyCode::Tab
+        | KeyCode::Modifier(ModifierKeyCode::LeftShift | ModifierKeyCode::RightShift) => {
+            ButtonName::Select
+        }
+        KeyCode::Char('z' | 'Z') => ButtonName::B,
+        KeyCode::Char('x' | 'X') => ButtonName::A,
+        _ => return None,
+    })
+}
+
+/// The terminal while the emulator is shown in it. Puts the terminal back the way it was when dropped.
+struct TerminalScreen {
+    stdout: Stdout,
+    settings: TerminalSettings,
+    /// Whether the terminal reports when keys are released
+    reports_releases: bool,
+    /// The colors of the top and bottom half of every character on the screen, as last drawn
+    cells: Vec<Option<([u8; 3], [u8; 3])>>,
+    /// The size of the terminal in characters, when last drawn
+    size: (u16, u16),
+    /// What's drawn, so it can be reused between frames
+    buffer: Vec<u8>,
+    downscaled: Image,
+}
+
+impl TerminalScreen {
+    fn new(settings: TerminalSettings) -> io::Result<Self> {
+        let mut stdout = io::stdout();
+        terminal::enable_raw_mode()?;
+        execute!(stdout, EnterAlternateScreen, cursor::Hide)?;
+
+        let reports_releases = terminal::supports_keyboard_enhancement().unwrap_or(false);
+        if reports_releases {
+            execute!(
+                stdout,
+                PushKeyboardEnhancementFlags(
+                    KeyboardEnhancementFlags::DISAMBIGUATE_ESCAPE_CODES
+                        | KeyboardEnhancementFlags::REPORT_EVENT_TYPES
+                        | KeyboardEnhancementFlags::REPORT_ALL_KEYS_AS_ESCAPE_CODES
+                )
+            )?;
+        }
+
+        Ok(Self {
+            stdout,
+            settings,
+            reports_releases,
+            cells: Vec::new(),
+            size: (0, 0),
+            buffer: Vec::new(),
+            downscaled: Image::default(),
+        })
+    }
+
+    /// Makes the next draw redraw everything, like after the terminal is resized
+    fn clear(&mut self) {
+        self.cells.clear();
+    }
+
+    fn draw(&mut self, image: &Image) -> io::Result<()> {
+        let (columns, rows) = terminal::size()?;
+        // the last row is for the status line
+        let picture_rows = rows.saturating_sub(1);
+        if columns == 0 || picture_rows == 0 {
+            return Ok(());
+        }
+
+        self.buffer.clear();
+        if self.size != (columns, rows) || self.cells.is_empty() {
+            self.size = (columns, rows);
+            self.cells = vec![None; columns as usize * rows as usize];
+            queue!(
+                self.buffer,
+                ResetColor,
+                Clear(ClearType::All),
+                cursor::MoveTo(0, rows.saturating_sub(1)),
+                Print(&STATUS_LINE[..STATUS_LINE.len().min(columns as usize)])
+            )?;
+        }
+
+        let factor = self.settings.downscale.unwrap_or_else(|| {
+            fitting_factor(image.width(), image.height(), columns, picture_rows)
+        });
+        downscale(image, factor.max(1), &mut self.downscaled);
+        let image = &self.downscaled;
+
+        // the picture goes in the middle
+        let width = image.width().min(columns as u32) as u16;
+        let height = image.height().div_ceil(2).min(picture_rows as u32) as u16;
+        let left = (columns - width) / 2;
+        let top = (picture_rows - height) / 2;
+
+        // the colors that are set, so they're only changed when needed
+        let mut colors = None;
+        for row in 0..height {
+            // whether the cursor is right after the last character that was drawn
+            let mut in_place = false;
+
+            for column in 0..width {
+                let cell = cell(image, column as u32, row as u32);
+                let index = (top + row) as usize * columns as usize + (left + column) as usize;
+                if self.cells[index] == Some(cell) {
+                    in_place = false;
+                    continue;
+                }
+                self.cells[index] = Some(cell);
+
+                if !in_place {
+                    queue!(self.buffer, cursor::MoveTo(left + column, top + row))?;
+                }
+                if colors != Some(cell) {
+                    let ([r, g, b], [r2, g2, b2]) = cell;
+                    queue!(
+                        self.buffer,
+                        SetForegroundColor(Color::Rgb { r, g, b }),
+                        SetBackgroundColor(Color::Rgb {
+                            r: r2,
+                            g: g2,
+                            b: b2
+                        })
+                    )?;
+                    colors = Some(cell);
+                }
+                // the top half is drawn in the foreground color, the bottom half shows the background
+                queue!(self.buffer, Print('▀'))?;
+                in_place = true;
+            }
+        }
+
+        queue!(self.buffer, ResetColor)?;
+        self.stdout.write_all(&self.buffer)?;
+        self.stdout.flush()
+    }
+}
+
+impl Drop for TerminalScreen {
+    fn drop(&mut self) {
+        // there's nothing left to do when this fails
+        if self.reports_releases {
+            let _ = execute!(self.stdout, PopKeyboardEnhancementFlags);
+        }
+        let _ = execute!(self.stdout, ResetColor, cursor::Show, LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+/// The smallest factor to downscale a picture of `width` by `height` with, so it fits in
+/// `columns` by `rows` characters
+fn fitting_factor(width: u32, height: u32, columns: u16, rows: u16) -> u32 {
+    let fits = |factor: u32| {
+        width.div_ceil(factor) <= columns as u32 && height.div_ceil(factor) <= rows as u32 * 2
+    };
+    (1..).find(|&factor| fits(factor)).expect("some size fits")
+}
+
+/// The colors of the top and bottom half of the character at `column`, `row`. When the image
+/// has an odd height, the bottom half of the last row is black.
+fn cell(image: &Image, column: u32, row: u32) -> ([u8; 3], [u8; 3]) {
+    let pixel = |y: u32| {
+        if y < image.height() {
+            let [r, g, b, _] = image.get(column, y);
+            [r, g, b]
+        } else {
+            [0; 3]
+        }
+    };
+
+    (pixel(row * 2), pixel(row * 2 + 1))
+}
+
+/// Makes `image` `factor` times smaller into `output`, where every pixel is the average
+/// of the block of pixels it replaces.
+fn downscale(image: &Image, factor: u32, output: &mut Image) {
+    let (width, height) = (
+        image.width().div_ceil(factor),
+        image.height().div_ceil(factor),
+    );
+    output.resize(width, height);
+
+    for y in 0..height {
+        for x in 0..width {
+            let mut sum = [0u32; 4];
+            let mut count = 0;
+            for source_y in y * factor..((y + 1) * factor).min(image.height()) {
+                for source_x in x * factor..((x + 1) * factor).min(image.width()) {
+                    for (sum, value) in sum.iter_mut().zip(image.get(source_x, source_y)) {
+                        *sum += value as u32;
+                    }
+                    count += 1;
+                }
+            }
+
+            let index = ((y * width + x) * 4) as usize;
+            for (out, sum) in output.rgba_mut()[index..index + 4].iter_mut().zip(sum) {
+                *out = ((sum + count / 2) / count) as u8;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::control::ControlChannel;
+    use std::sync::mpsc::Receiver;
+
+    /// A grey image with the given levels, row by row
+    fn grey(width: u32, levels: &[u8]) -> Image {
+        let rgba = levels
+            .iter()
+            .flat_map(|&level| [level, level, level, 0xff])
+            .collect::<Vec<_>>();
+        Image::from_rgba(width, levels.len() as u32 / width, rgba)
+    }
+
+    #[test]
+    fn pictures_are_made_small_enough_to_fit() {
+        assert_eq!(fitting_factor(256, 240, 256, 120), 1);
+        assert_eq!(fitting_factor(256, 240, 256, 119), 2);
+        // a common 80x24 terminal, with a line for the status
+        assert_eq!(fitting_factor(256, 240, 80, 23), 6);
+        assert_eq!(fitting_factor(256, 224, 40, 100), 7);
+    }
+
+    #[test]
+    fn downscaled_pixels_are_averaged() {
+        let image = grey(4, &[0, 10, 20, 30, 40, 50, 60, 70, 80, 90, 100, 110]);
+        let mut output = Image::default();
+
+        downscale(&image, 2, &mut output);
+
+        // the last row only has one row of the picture left to average
+        assert_eq!(output, grey(2, &[25, 45, 85, 105]));
+    }
+
+    #[test]
+    fn odd_heights_end_with_a_black_half() {
+        let image = grey(1, &[10, 20, 30]);
+
+        assert_eq!(cell(&image, 0, 0), ([10; 3], [20; 3]));
+        assert_eq!(cell(&image, 0, 1), ([30; 3], [0; 3]));
+    }
+
+    fn key(code: KeyCode, kind: KeyEventKind) -> KeyEvent {
+        KeyEvent::new_with_kind(code, KeyModifiers::NONE, kind)
+    }
+
+    /// The buttons pressed and released since the last call
+    fn buttons(receiver: &Receiver<Message>) -> Vec<(ButtonName, bool)> {
+        receiver
+            .try_iter()
+            .filter_map(|message| match message {
+                Message::Button(button, pressed) => Some((button, pressed)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn buttons_are_released_after_the_key_stops_repeating() {
+        let channel = ControlChannel::default();
+        let receiver = channel.take_receiver().unwrap();
+        let mut keys = Keys::new(channel.control(), false);
+        let start = Instant::now();
+        let after = |millis: u64| start + Duration::from_millis(millis);
+
+        keys.handle(key(KeyCode::Left, KeyEventKind::Press), start);
+        assert_eq!(buttons(&receiver), [(ButtonName::Left, true)]);
+        assert_eq!(keys.next_release(), Some(after(550)));
+
+        // the keyboard starts repeating the key, which keeps it pressed a bit longer every time
+        keys.handle(key(KeyCode::Left, KeyEventKind::Repeat), after(500));
+        keys.handle(key(KeyCode::Left, KeyEventKind::Press), after(530));
+        assert_eq!(keys.next_release(), Some(after(630)));
+        keys.release_expired(after(629));
+        assert_eq!(buttons(&receiver), []);
+
+        keys.release_expired(after(630));
+        assert_eq!(buttons(&receiver), [(ButtonName::Left, false)]);
+        assert_eq!(keys.next_release(), None);
+    }
+
+    #[test]
+    fn reported_releases_release_right_away() {
+        let channel = ControlChannel::default();
+        let receiver = channel.take_receiver().unwrap();
+        let mut keys = Keys::new(channel.control(), true);
+        let start = Instant::now();
+
+        keys.handle(key(KeyCode::Char('x'), KeyEventKind::Press), start);
+        assert_eq!(keys.next_release(), None);
+        keys.release_expired(start + Duration::from_secs(10));
+        assert_eq!(buttons(&receiver), [(ButtonName::A, true)]);
+
+        keys.handle(key(KeyCode::Char('x'), KeyEventKind::Release), start);
+        assert_eq!(buttons(&receiver), [(ButtonName::A, false)]);
+    }
+}
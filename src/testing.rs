@@ -0,0 +1,39 @@
+use crate::{Cpu, Ppu};
+use std::convert::Infallible;
+
+/// A cpu for tests, that only has character memory and sets up the PPU on its first tick
+pub(crate) struct TestCpu {
+    pub(crate) chr: Vec<u8>,
+    pub(crate) setup: fn(&mut Ppu, &mut TestCpu),
+    ticks: u64,
+}
+
+impl TestCpu {
+    pub(crate) fn new(setup: fn(&mut Ppu, &mut TestCpu)) -> Self {
+        Self {
+            chr: vec![0; 0x2000],
+            setup,
+            ticks: 0,
+        }
+    }
+}
+
+impl Cpu for TestCpu {
+    type TickError = Infallible;
+
+    fn tick(&mut self, ppu: &mut Ppu) -> Result<(), Self::TickError> {
+        if self.ticks == 0 {
+            (self.setup)(ppu, self);
+        }
+        self.ticks += 1;
+        Ok(())
+    }
+
+    fn ppu_read_chr_rom(&self, offset: u16) -> u8 {
+        self.chr[offset as usize % self.chr.len()]
+    }
+
+    fn ppu_memory_write(&mut self, _address: u16, _value: u8) {}
+
+    fn non_maskable_interrupt(&mut self) {}
+}
@@ -0,0 +1,155 @@
+use std::cell::UnsafeCell;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+
+/// Set in the state when the middle buffer holds a value the reader hasn't seen yet
+const NEW: u8 = 0b100;
+/// The bits of the state that say which buffer is in the middle
+const INDEX: u8 = 0b011;
+
+/// Three buffers to hand values from one thread to another, without either of them ever waiting.
+///
+/// The writer fills its back buffer and swaps it with the middle one. The reader swaps its front buffer
+/// with the middle one whenever there's a new value there. Because every buffer belongs to exactly
+/// one of them at a time, the writer never overwrites what the reader is using, and the reader
+/// always gets the newest complete value.
+struct Shared<T> {
+    buffers: [UnsafeCell<T>; 3],
+    /// Which buffer is in the middle, and whether it's new
+    state: AtomicU8,
+}
+
+// Safety: the writer and reader only ever touch the buffer they own, and hand buffers over
+// through `state`, which orders the writes to a buffer before the reads of it. `T` has to be `Sync`
+// as well, so values that can only be shared within one thread, like a `Cell`, never are.
+unsafe impl<T: Send + Sync> Sync for Shared<T> {}
+
+/// Creates a triple buffer, with every buffer starting as `initial`
+pub(crate) fn triple_buffer<T: Clone + Send + Sync>(initial: T) -> (Writer<T>, Reader<T>) {
+    let shared = Arc::new(Shared {
+        buffers: [
+            UnsafeCell::new(initial.clone()),
+            UnsafeCell::new(initial.clone()),
+            UnsafeCell::new(initial),
+        ],
+        state: AtomicU8::new(1),
+    });
+
+    (
+        Writer {
+            shared: shared.clone(),
+            back: 0,
+        },
+        Reader { shared, front: 2 },
+    )
+}
+
+/// The side of a triple buffer that produces values
+pub(crate) struct Writer<T> {
+    shared: Arc<Shared<T>>,
+    back: u8,
+}
+
+impl<T> Writer<T> {
+    /// The buffer to write the next value in. It can hold any older value.
+    pub(crate) fn back_mut(&mut self) -> &mut T {
+        // Safety: the back buffer belongs to the writer until it's published
+        unsafe { &mut *self.shared.buffers[self.back as usize].get() }
+    }
+
+    /// Hands the back buffer to the reader
+    pub(crate) fn publish(&mut self) {
+        let old = self.shared.state.swap(self.back | NEW, Ordering::AcqRel);
+        self.back = old & INDEX;
+    }
+}
+
+/// The side of a triple buffer that uses the values
+pub(crate) struct Reader<T> {
+    shared: Arc<Shared<T>>,
+    front: u8,
+}
+
+impl<T> Reader<T> {
+    /// Takes the newest value, if the writer published one since the last update.
+    /// Returns whether there was a new value.
+    pub(crate) fn update(&mut self) -> bool {
+        if self.shared.state.load(Ordering::Relaxed) & NEW == 0 {
+            return false;
+        }
+
+        let old = self.shared.state.swap(self.front, Ordering::AcqRel);
+        self.front = old & INDEX;
+        true
+    }
+
+    /// The newest value the reader took
+    pub(crate) fn front(&self) -> &T {
+        // Safety: the front buffer belongs to the reader until the next update
+        unsafe { &*self.shared.buffers[self.front as usize].get() }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reader_gets_the_newest_value() {
+        let (mut writer, mut reader) = triple_buffer(0);
+        assert!(!reader.update());
+        assert_eq!(*reader.front(), 0);
+
+        *writer.back_mut() = 1;
+        writer.publish();
+        *writer.back_mut() = 2;
+        writer.publish();
+
+        // values published in between are skipped
+        assert!(reader.update());
+        assert_eq!(*reader.front(), 2);
+        assert!(!reader.update());
+        assert_eq!(*reader.front(), 2);
+    }
+
+    #[test]
+    fn writer_never_touches_the_front_buffer() {
+        let (mut writer, mut reader) = triple_buffer(0);
+
+        *writer.back_mut() = 1;
+        writer.publish();
+        assert!(reader.update());
+
+        // the writer keeps going while the reader holds on to 1
+        for value in 2..10 {
+            *writer.back_mut() = value;
+            writer.publish();
+            assert_eq!(*reader.front(), 1);
+        }
+
+        assert!(reader.update());
+        assert_eq!(*reader.front(), 9);
+    }
+
+    #[test]
+    fn values_cross_threads() {
+        let (mut writer, mut reader) = triple_buffer(0u64);
+
+        let writing = std::thread::spawn(move || {
+            for value in 1..=10_000 {
+                *writer.back_mut() = value;
+                writer.publish();
+            }
+        });
+
+        // the values the reader sees only ever go up, and end at the last one
+        let mut last = 0;
+        while last < 10_000 {
+            if reader.update() {
+                assert!(*reader.front() > last);
+                last = *reader.front();
+            }
+        }
+        writing.join().unwrap();
+    }
+}
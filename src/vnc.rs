@@ -0,0 +1,751 @@
+use crate::control::Control;
+use crate::cpu::Cpu;
+use crate::image::Image;
+use crate::ppu::colors::Palette;
+use crate::ppu::frame::Frame;
+use crate::run::run_cpu_with_sink;
+use crate::screen::{ButtonName, Message};
+use crate::settings::Settings;
+use crate::sink::FrameSink;
+use crate::Mirroring;
+use std::io;
+use std::io::{BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// The name the desktop gets in VNC clients
+const NAME: &str = "NES";
+/// How long a client waits for the next frame before it's told nothing changed
+const FRAME_TIMEOUT: Duration = Duration::from_millis(100);
+/// The pseudo-encoding clients send when they can handle the size of the picture changing
+const DESKTOP_SIZE_ENCODING: i32 = -223;
+
+/// Like [`run_cpu`](crate::run_cpu), but instead of opening a window, runs a VNC server on `address`
+/// that shows the picture. Any VNC client can connect, and several clients can connect at once.
+/// They all watch the same picture and play with the same controller, with the same keys
+/// as the window (see [`Buttons`](crate::Buttons)). Nobody needs a password, so only listen on
+/// addresses you trust, like `127.0.0.1:5900`.
+///
+/// Only returns when the server can't listen on `address`.
+///
+/// # Panics
+/// [`run_cpu_vnc`] can panic when the `cpu` returns an Error
+pub fn run_cpu_vnc<CPU>(
+    cpu: CPU,
+    mirroring: Mirroring,
+    address: impl ToSocketAddrs,
+) -> io::Result<()>
+where
+    CPU: Cpu + 'static,
+{
+    run_cpu_vnc_with_settings(cpu, mirroring, address, Settings::default())
+}
+
+/// Like [`run_cpu_vnc`], but with [`Settings`] for how the output is shown.
+///
+/// # Panics
+/// [`run_cpu_vnc_with_settings`] can panic when the `cpu` returns an Error
+pub fn run_cpu_vnc_with_settings<CPU>(
+    mut cpu: CPU,
+    mirroring: Mirroring,
+    address: impl ToSocketAddrs,
+    settings: Settings,
+) -> io::Result<()>
+where
+    CPU: Cpu + 'static,
+{
+    let listener = TcpListener::bind(address)?;
+    log::info!("VNC server listening on {}", listener.local_addr()?);
+
+    let (width, height) = settings.filters.output_size(settings.overscan);
+    let latest = Arc::new(LatestImage {
+        image: Mutex::new((0, Arc::new(Image::new(width, height)))),
+        completed: Condvar::new(),
+    });
+
+    serve(listener, latest.clone(), settings.control());
+
+    let mut sink = VncSink { latest };
+    if let Err(e) = run_cpu_with_sink(&mut cpu, mirroring, &mut sink, settings, None) {
+        panic!("cpu implementation returned an error: {e}")
+    }
+    unreachable!("the emulator only stops when the cpu returns an error")
+}
+
+/// Accepts VNC clients on another thread, and handles every client on a thread of its own
+fn serve(listener: TcpListener, latest: Arc<LatestImage>, control: Control) {
+    let held = Arc::new(HeldButtons::default());
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    log::warn!("failed to accept VNC client: {e}");
+                    continue;
+                }
+            };
+
+            let latest = latest.clone();
+            let control = control.clone();
+            let held = held.clone();
+            thread::spawn(move || {
+                let peer = stream.peer_addr().ok();
+                log::info!("VNC client {peer:?} connected");
+                match Client::new(stream, latest, control, held).and_then(Client::run) {
+                    Ok(()) => log::info!("VNC client {peer:?} disconnected"),
+                    Err(e) => log::info!("VNC client {peer:?} disconnected: {e}"),
+                }
+            });
+        }
+    });
+}
+
+/// The last completed picture, with how many pictures were completed so far
+struct LatestImage {
+    image: Mutex<(u64, Arc<Image>)>,
+    completed: Condvar,
+}
+
+/// The buttons every client holds down. A button stays pressed as long as any client holds it.
+#[derive(Default)]
+struct HeldButtons {
+    /// Every button once for every client that holds it
+    held: Mutex<Vec<ButtonName>>,
+}
+
+impl HeldButtons {
+    /// Returns whether nobody held `button` before
+    fn press(&self, button: ButtonName) -> bool {
+        let mut held = self.held.lock().expect("failed to lock");
+        let first = !held.contains(&button);
+        held.push(button);
+        first
+    }
+
+    /// Returns whether nobody holds `button` anymore
+    fn release(&self, button: ButtonName) -> bool {
+        let mut held = self.held.lock().expect("failed to lock");
+        if let Some(index) = held.iter().position(|held| *held == button) {
+            held.swap_remove(index);
+        }
+        !held.contains(&button)
+    }
+}
+
+/// The [`FrameSink`] that hands the pictures to the VNC clients
+struct VncSink {
+    latest: Arc<LatestImage>,
+}
+
+impl FrameSink for VncSink {
+    fn frame_completed(&mut self, _frame: &Frame, _palette: &Palette) {}
+
+    fn image_completed(&mut self, image: &Image) {
+        let image = Arc::new(image.clone());
+        let mut latest = self.latest.image.lock().expect("failed to lock");
+        *latest = (latest.0 + 1, image);
+        self.latest.completed.notify_all();
+    }
+
+    fn wants_images(&self) -> bool {
+        true
+    }
+}
+
+/// How a client wants its pixels
+#[derive(Debug, Copy, Clone)]
+struct PixelFormat {
+    bits_per_pixel: u8,
+    depth: u8,
+    big_endian: bool,
+    true_color: bool,
+    max: [u16; 3],
+    shift: [u8; 3],
+}
+
+impl PixelFormat {
+    /// What the server offers: 8 bits per channel in 32-bit little endian pixels
+    const DEFAULT: PixelFormat = PixelFormat {
+        bits_per_pixel: 32,
+        depth: 24,
+        big_endian: false,
+        true_color: true,
+        max: [255; 3],
+        shift: [16, 8, 0],
+    };
+
+    fn read(bytes: &[u8; 16]) -> Self {
+        let u16_at = |index: usize| u16::from_be_bytes([bytes[index], bytes[index + 1]]);
+        Self {
+            bits_per_pixel: bytes[0],
+            depth: bytes[1],
+            big_endian: bytes[2] != 0,
+            true_color: bytes[3] != 0,
+            max: [u16_at(4), u16_at(6), u16_at(8)],
+            shift: [bytes[10], bytes[11], bytes[12]],
+        }
+    }
+
+    /// Whether pixels can be sent in this format: true colour with 8, 16 or 32 bits per pixel,
+    /// where every channel fits in the pixel
+    fn is_supported(&self) -> bool {
+        self.true_color
+            && [8, 16, 32].contains(&self.bits_per_pixel)
+            && self.max.iter().all(|max| *max != 0)
+            && self.shift.iter().all(|shift| *shift < self.bits_per_pixel)
+    }
+
+    fn write(&self, out: &mut Vec<u8>) {
+        out.extend([
+            self.bits_per_pixel,
+            self.depth,
+            self.big_endian as u8,
+            self.true_color as u8,
+        ]);
+        for max in self.max {
+            out.extend(max.to_be_bytes());
+        }
+        out.extend(self.shift);
+        out.extend([0; 3]);
+    }
+
+    /// Adds a pixel in this format to `out`
+    fn push(&self, [r, g, b, _]: [u8; 4], out: &mut Vec<u8>) {
+        let mut value = 0u32;
+        for ((channel, max), shift) in [r, g, b].into_iter().zip(self.max).zip(self.shift) {
+            let channel = (channel as u32 * max as u32 + 127) / 255;
+            value |= channel << shift;
+        }
+
+        let bytes = (self.bits_per_pixel / 8) as usize;
+        if self.big_endian {
+            out.extend(&value.to_be_bytes()[4 - bytes..]);
+        } else {
+            out.extend(&value.to_le_bytes()[..bytes]);
+        }
+    }
+}
+
+/// A connection to a VNC client, speaking version 3.3, 3.7 or 3.8 of the RFB protocol
+struct Client {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    latest: Arc<LatestImage>,
+    control: Control,
+    held: Arc<HeldButtons>,
+    format: PixelFormat,
+    /// Whether the client handles the size of the picture changing
+    desktop_size: bool,
+    /// The size of the picture the client has
+    size: (u32, u32),
+    /// The picture the client has, and its number
+    sent: Option<(u64, Arc<Image>)>,
+    /// Whether the client asked for an update that wasn't sent yet
+    update_requested: bool,
+    /// The buttons this client holds down
+    pressed: Vec<ButtonName>,
+    buffer: Vec<u8>,
+}
+
+impl Client {
+    fn new(
+        stream: TcpStream,
+        latest: Arc<LatestImage>,
+        control: Control,
+        held: Arc<HeldButtons>,
+    ) -> io::Result<Self> {
+        stream.set_nodelay(true)?;
+        let size = {
+            let image = &latest.image.lock().expect("failed to lock").1;
+            (image.width(), image.height())
+        };
+
+        Ok(Self {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: stream,
+            latest,
+            control,
+            held,
+            format: PixelFormat::DEFAULT,
+            desktop_size: false,
+            size,
+            sent: None,
+            update_requested: false,
+            pressed: Vec::new(),
+            buffer: Vec::new(),
+        })
+    }
+
+    fn run(mut self) -> io::Result<()> {
+        let result = self.handshake().and_then(|()| loop {
+            self.handle_message()?;
+            if self.update_requested {
+                self.send_update()?;
+            }
+        });
+
+        // this client can't let go of these buttons anymore
+        for button in std::mem::take(&mut self.pressed) {
+            if self.held.release(button) {
+                self.control.send(Message::Button(button, false));
+            }
+        }
+
+        match result {
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(()),
+            result => result,
+        }
+    }
+
+    fn handshake(&mut self) -> io::Result<()> {
+        self.writer.write_all(b"RFB 003.008\n")?;
+        let mut version = [0; 12];
+        self.reader.read_exact(&mut version)?;
+        let minor = match &version {
+            b"RFB 003.003\n" => 3,
+            b"RFB 003.007\n" => 7,
+            // later versions have to accept 3.8, and some clients use other 3.x numbers
+            _ if version.starts_with(b"RFB 003.") => 8,
+            _ => return Err(invalid("unsupported protocol version")),
+        };
+
+        // no authentication
+        if minor == 3 {
+            self.writer.write_all(&1u32.to_be_bytes())?;
+        } else {
+            self.writer.write_all(&[1, 1])?;
+            if self.read_u8()? != 1 {
+                return Err(invalid("unsupported security type"));
+            }
+            if minor == 8 {
+                self.writer.write_all(&0u32.to_be_bytes())?;
+            }
+        }
+
+        // everyone shares the same picture, so whether the client wants to share doesn't matter
+        self.read_u8()?;
+
+        let mut init = Vec::new();
+        init.extend((self.size.0 as u16).to_be_bytes());
+        init.extend((self.size.1 as u16).to_be_bytes());
+        self.format.write(&mut init);
+        init.extend((NAME.len() as u32).to_be_bytes());
+        init.extend(NAME.as_bytes());
+        self.writer.write_all(&init)
+    }
+
+    fn handle_message(&mut self) -> io::Result<()> {
+        match self.read_u8()? {
+            // SetPixelFormat
+            0 => {
+                let mut message = [0; 19];
+                self.reader.read_exact(&mut message)?;
+                let format = PixelFormat::read(message[3..].try_into().expect("16 bytes"));
+                if !format.is_supported() {
+                    return Err(invalid("unsupported pixel format"));
+                }
+                self.format = format;
+            }
+            // SetEncodings
+            2 => {
+                self.read_u8()?;
+                let count = self.read_u16()?;
+                self.desktop_size = false;
+                for _ in 0..count {
+                    let mut encoding = [0; 4];
+                    self.reader.read_exact(&mut encoding)?;
+                    if i32::from_be_bytes(encoding) == DESKTOP_SIZE_ENCODING {
+                        self.desktop_size = true;
+                    }
+                }
+            }
+            // FramebufferUpdateRequest
+            3 => {
+                let incremental = self.read_u8()? != 0;
+                // always sends what changed in the whole picture, wherever the client asked
+                let mut area = [0; 8];
+                self.reader.read_exact(&mut area)?;
+                if !incremental {
+                    self.sent = None;
+                }
+                self.update_requested = true;
+            }
+            // KeyEvent
+            4 => {
+                let down = self.read_u8()? != 0;
+                let mut message = [0; 6];
+                self.reader.read_exact(&mut message)?;
+                let key = u32::from_be_bytes([message[2], message[3], message[4], message[5]]);
+                self.handle_key(key, down);
+            }
+            // PointerEvent
+            5 => {
+                let mut message = [0; 5];
+                self.reader.read_exact(&mut message)?;
+            }
+            // ClientCutText
+            6 => {
+                let mut padding = [0; 3];
+                self.reader.read_exact(&mut padding)?;
+                let length = self.read_u32()?;
+                io::copy(&mut (&mut self.reader).take(length as u64), &mut io::sink())?;
+            }
+            _ => return Err(invalid("unsupported message")),
+        }
+
+        Ok(())
+    }
+
+    fn handle_key(&mut self, key: u32, down: bool) {
+        if down {
+            match key {
+                // F12, F9 and F10
+                0xffc9 => return self.control.screenshot(),
+                0xffc6 => return self.control.send(Message::ToggleRecording),
+                0xffc7 => return self.control.send(Message::ToggleGif),
+                _ => {}
+            }
+        }
+
+        let button = match key {
+            0xff51 | 0x61 | 0x41 => ButtonName::Left,
+            0xff52 | 0x77 | 0x57 => ButtonName::Up,
+            0xff53 | 0x64 | 0x44 => ButtonName::Right,
+            0xff54 | 0x73 | 0x53 => ButtonName::Down,
+            0xff0d => ButtonName::Start,
+            0xffe1 | 0xffe2 => ButtonName::Select,
+            0x7a | 0x5a => ButtonName::B,
+            0x78 | 0x58 => ButtonName::A,
+            _ => return,
+        };
+
+        let holding = self.pressed.contains(&button);
+        let changed = if down && !holding {
+            self.pressed.push(button);
+            self.held.press(button)
+        } else if !down && holding {
+            self.pressed.retain(|pressed| *pressed != button);
+            self.held.release(button)
+        } else {
+            false
+        };
+        if changed {
+            self.control.send(Message::Button(button, down));
+        }
+    }
+
+    /// Whether the client sent something that wasn't read yet, without waiting for it
+    fn input_pending(&mut self) -> io::Result<bool> {
+        if !self.reader.buffer().is_empty() {
+            return Ok(true);
+        }
+
+        self.writer.set_nonblocking(true)?;
+        let pending = match self.writer.peek(&mut [0]) {
+            // also when the client closed the connection, which the next read finds out
+            Ok(_) => Ok(true),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(false),
+            Err(e) => Err(e),
+        };
+        self.writer.set_nonblocking(false)?;
+        pending
+    }
+
+    /// Sends the part of the picture that changed since the last update.
+    /// Waits for the next frame when the client already has the latest one,
+    /// unless the client sent something else first, which is handled before the update is sent.
+    fn send_update(&mut self) -> io::Result<()> {
+        // checked before locking, so finishing a frame never waits for the socket
+        let pending = self.sent.is_some() && self.input_pending()?;
+        let (number, image) = {
+            let mut latest = self.latest.image.lock().expect("failed to lock");
+            if let Some((sent, _)) = &self.sent {
+                if latest.0 == *sent {
+                    if pending {
+                        return Ok(());
+                    }
+                    latest = self
+                        .latest
+                        .completed
+                        .wait_timeout(latest, FRAME_TIMEOUT)
+                        .expect("failed to lock")
+                        .0;
+                }
+            }
+            latest.clone()
+        };
+        self.update_requested = false;
+
+        self.buffer.clear();
+        // FramebufferUpdate, with one rectangle or none
+        self.buffer.extend([0, 0]);
+
+        let size = (image.width(), image.height());
+        if size != self.size && self.desktop_size {
+            self.size = size;
+            self.sent = None;
+            self.buffer.extend(1u16.to_be_bytes());
+            push_rectangle(
+                &mut self.buffer,
+                (0, 0, size.0, size.1),
+                DESKTOP_SIZE_ENCODING,
+            );
+            self.writer.write_all(&self.buffer)?;
+            return Ok(());
+        }
+
+        let changed = match &self.sent {
+            Some((_, sent)) => changed_area(sent, &image, self.size),
+            None => Some((0, 0, self.size.0, self.size.1)),
+        };
+
+        match changed {
+            Some(area @ (left, top, width, height)) => {
+                self.buffer.extend(1u16.to_be_bytes());
+                push_rectangle(&mut self.buffer, area, 0);
+
+                for y in top..top + height {
+                    for x in left..left + width {
+                        // when the client can't be told about a new size, the picture is cut off or filled up
+                        let pixel = if x < image.width() && y < image.height() {
+                            image.get(x, y)
+                        } else {
+                            [0, 0, 0, 0xff]
+                        };
+                        self.format.push(pixel, &mut self.buffer);
+                    }
+                }
+            }
+            None => self.buffer.extend(0u16.to_be_bytes()),
+        }
+
+        self.sent = Some((number, image));
+        self.writer.write_all(&self.buffer)
+    }
+
+    fn read_u8(&mut self) -> io::Result<u8> {
+        let mut bytes = [0; 1];
+        self.reader.read_exact(&mut bytes)?;
+        Ok(bytes[0])
+    }
+
+    fn read_u16(&mut self) -> io::Result<u16> {
+        let mut bytes = [0; 2];
+        self.reader.read_exact(&mut bytes)?;
+        Ok(u16::from_be_bytes(bytes))
+    }
+
+    fn read_u32(&mut self) -> io::Result<u32> {
+        let mut bytes = [0; 4];
+        self.reader.read_exact(&mut bytes)?;
+        Ok(u32::from_be_bytes(bytes))
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Adds the header of a rectangle at `left`, `top` of `width` by `height` pixels
+fn push_rectangle(
+    out: &mut Vec<u8>,
+    (left, top, width, height): (u32, u32, u32, u32),
+    encoding: i32,
+) {
+    for value in [left, top, width, height] {
+        out.extend((value as u16).to_be_bytes());
+    }
+    out.extend(encoding.to_be_bytes());
+}
+
+/// The smallest area of `size` that contains every pixel that's different between `old` and `new`,
+/// as `(left, top, width, height)`, if any pixel is different
+fn changed_area(old: &Image, new: &Image, size: (u32, u32)) -> Option<(u32, u32, u32, u32)> {
+    if (old.width(), old.height()) != (new.width(), new.height()) {
+        return Some((0, 0, size.0, size.1));
+    }
+
+    let (width, height) = (new.width().min(size.0), new.height().min(size.1));
+    let (mut left, mut top, mut right, mut bottom) = (width, height, 0, 0);
+    let rows = old.rgba().chunks_exact(old.width() as usize * 4);
+    let new_rows = new.rgba().chunks_exact(new.width() as usize * 4);
+
+    for (y, (old_row, new_row)) in rows.zip(new_rows).take(height as usize).enumerate() {
+        if old_row == new_row {
+            continue;
+        }
+        let different = |x: &u32| {
+            let index = *x as usize * 4;
+            old_row[index..index + 4] != new_row[index..index + 4]
+        };
+        // the rows can be different only in the part the client doesn't see
+        let Some(first) = (0..width).find(different) else {
+            continue;
+        };
+        let last = (0..width).rev().find(different).unwrap_or(first);
+
+        left = left.min(first);
+        right = right.max(last + 1);
+        top = top.min(y as u32);
+        bottom = y as u32 + 1;
+    }
+
+    (left < right).then(|| (left, top, right - left, bottom - top))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::SocketAddr;
+    use std::sync::mpsc::Receiver;
+
+    /// Starts a server on loopback showing a 2x1 picture, and connects a client to it
+    fn connect() -> (TcpStream, Receiver<Message>) {
+        let (address, receiver) = start();
+        (connect_to(address), receiver)
+    }
+
+    /// Starts a server on loopback showing a 2x1 picture
+    fn start() -> (SocketAddr, Receiver<Message>) {
+        let settings = Settings::default();
+        let control = settings.control();
+        let receiver = settings
+            .take_control_receiver()
+            .expect("nobody took the receiver yet");
+
+        let picture = Image::from_rgba(2, 1, vec![0xff, 0x80, 0x00, 0xff, 0x00, 0x00, 0xff, 0xff]);
+        let latest = Arc::new(LatestImage {
+            image: Mutex::new((1, Arc::new(picture))),
+            completed: Condvar::new(),
+        });
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        serve(listener, latest, control);
+        (address, receiver)
+    }
+
+    /// Connects a client to the server at `address`, going through the handshake
+    fn connect_to(address: SocketAddr) -> TcpStream {
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+
+        let mut version = [0; 12];
+        stream.read_exact(&mut version).unwrap();
+        assert_eq!(&version, b"RFB 003.008\n");
+        stream.write_all(b"RFB 003.008\n").unwrap();
+
+        let mut security = [0; 2];
+        stream.read_exact(&mut security).unwrap();
+        assert_eq!(security, [1, 1]);
+        stream.write_all(&[1]).unwrap();
+        let mut result = [0; 4];
+        stream.read_exact(&mut result).unwrap();
+        assert_eq!(result, [0; 4]);
+
+        // shared
+        stream.write_all(&[1]).unwrap();
+        let mut init = [0; 24 + NAME.len()];
+        stream.read_exact(&mut init).unwrap();
+        assert_eq!(&init[..4], &[0, 2, 0, 1]);
+        assert_eq!(&init[24..], NAME.as_bytes());
+
+        stream
+    }
+
+    #[test]
+    fn sends_picture_and_handles_keys() {
+        let (mut stream, receiver) = connect();
+
+        // FramebufferUpdateRequest for the whole picture
+        stream.write_all(&[3, 0, 0, 0, 0, 0, 0, 2, 0, 1]).unwrap();
+        let mut update = [0; 4 + 12 + 2 * 4];
+        stream.read_exact(&mut update).unwrap();
+        assert_eq!(&update[..4], &[0, 0, 0, 1]);
+        // at 0, 0 and 2 by 1 pixels, raw encoding
+        assert_eq!(&update[4..16], &[0, 0, 0, 0, 0, 2, 0, 1, 0, 0, 0, 0]);
+        // little endian, red shifted by 16
+        assert_eq!(&update[16..], &[0x00, 0x80, 0xff, 0, 0xff, 0x00, 0x00, 0]);
+
+        // KeyEvent: x pressed
+        stream.write_all(&[4, 1, 0, 0, 0, 0, 0, 0x78]).unwrap();
+        let message = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(matches!(message, Message::Button(ButtonName::A, true)));
+    }
+
+    #[test]
+    fn rejects_invalid_pixel_format() {
+        let (mut stream, receiver) = connect();
+
+        // KeyEvent: x pressed
+        stream.write_all(&[4, 1, 0, 0, 0, 0, 0, 0x78]).unwrap();
+        let message = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(matches!(message, Message::Button(ButtonName::A, true)));
+
+        // SetPixelFormat: 32 bits per pixel, with red shifted by 40
+        let mut message = vec![0, 0, 0, 0, 32, 24, 0, 1];
+        message.extend([0, 255, 0, 255, 0, 255, 40, 8, 0, 0, 0, 0]);
+        stream.write_all(&message).unwrap();
+
+        // the connection is closed, and the button let go
+        let mut rest = Vec::new();
+        stream.read_to_end(&mut rest).unwrap();
+        assert!(rest.is_empty());
+        let message = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(matches!(message, Message::Button(ButtonName::A, false)));
+    }
+
+    #[test]
+    fn buttons_stay_pressed_while_any_client_holds_them() {
+        let (address, receiver) = start();
+        let mut first = connect_to(address);
+        let mut second = connect_to(address);
+
+        // KeyEvent: x pressed by both clients, and z by the second to know its x was handled
+        first.write_all(&[4, 1, 0, 0, 0, 0, 0, 0x78]).unwrap();
+        let message = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(matches!(message, Message::Button(ButtonName::A, true)));
+        second.write_all(&[4, 1, 0, 0, 0, 0, 0, 0x78]).unwrap();
+        second.write_all(&[4, 1, 0, 0, 0, 0, 0, 0x7a]).unwrap();
+        let message = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(matches!(message, Message::Button(ButtonName::B, true)));
+
+        // KeyEvent: x let go by the first client, and enter pressed to know the release was handled
+        first.write_all(&[4, 0, 0, 0, 0, 0, 0, 0x78]).unwrap();
+        first.write_all(&[4, 1, 0, 0, 0, 0, 0xff, 0x0d]).unwrap();
+        let message = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(matches!(message, Message::Button(ButtonName::Start, true)));
+
+        // the second client disconnecting lets go of x
+        drop(second);
+        let message = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(matches!(message, Message::Button(ButtonName::A, false)));
+    }
+
+    #[test]
+    fn handles_keys_while_waiting_for_a_frame() {
+        let (mut stream, receiver) = connect();
+
+        // FramebufferUpdateRequest for the whole picture
+        stream.write_all(&[3, 0, 0, 0, 0, 0, 0, 2, 0, 1]).unwrap();
+        let mut update = [0; 4 + 12 + 2 * 4];
+        stream.read_exact(&mut update).unwrap();
+
+        // an incremental FramebufferUpdateRequest, which waits for a frame that never comes,
+        // and KeyEvent: x pressed
+        let mut message = vec![3, 1, 0, 0, 0, 0, 0, 2, 0, 1];
+        message.extend([4, 1, 0, 0, 0, 0, 0, 0x78]);
+        stream.write_all(&message).unwrap();
+
+        // the key is handled before the empty update is sent
+        let mut update = [0; 4];
+        stream.read_exact(&mut update).unwrap();
+        assert_eq!(update, [0; 4]);
+        let message = receiver.try_recv().unwrap();
+        assert!(matches!(message, Message::Button(ButtonName::A, true)));
+    }
+}